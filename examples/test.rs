@@ -24,21 +24,21 @@ pub async fn main() -> Result<()> {
     let ws_loop = tokio::spawn(Arc::clone(&ws_client_one).start_loop::<_, ()>(
         |_client, message| {
             match message {
-                wicrs_server::prelude::WsServerMessage::ChatMessage {
+                wicrs_api::protocol::WsServerMessage::ChatMessage {
                     sender_id,
                     hub_id: _,
                     channel_id: _,
                     message_id: _,
                     message,
                 } => println!("{} sent '{}'", sender_id, message),
-                wicrs_server::prelude::WsServerMessage::HubUpdated {
+                wicrs_api::protocol::WsServerMessage::HubUpdated {
                     hub_id,
                     update_type,
                 } => match update_type {
-                    wicrs_server::prelude::WsHubUpdateType::UserJoined(user_id) => {
+                    wicrs_api::protocol::WsHubUpdateType::UserJoined(user_id) => {
                         println!("{} joined {}", user_id, hub_id)
                     }
-                    wicrs_server::prelude::WsHubUpdateType::UserLeft(user_id) => {
+                    wicrs_api::protocol::WsHubUpdateType::UserLeft(user_id) => {
                         println!("{} left {}", user_id, hub_id);
                         return Some(());
                     }
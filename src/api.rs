@@ -0,0 +1,342 @@
+//! Traits over the clients so code using them can be tested without a server, see
+//! [`crate::fake`] for in-memory implementations.
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+#[cfg(feature = "use-tokio")]
+use crate::protocol::WsServerMessage;
+#[cfg(feature = "use-tokio")]
+use crate::websocket::{asyncws::WebsocketClient, CloseReason, Subscription};
+use crate::{
+    error::Result,
+    handle::HubHandle,
+    http::HttpClient,
+    protocol::{
+        Channel, ChannelPermission, HttpChannelUpdate, HttpHubUpdate, HttpMemberStatus, Hub,
+        HubMember, HubPermission, Message, PermissionSetting, ID,
+    },
+};
+
+/// Hub, channel, message and member operations of the HTTP API.
+#[async_trait]
+pub trait WicrsApi: Send + Sync {
+    /// ID of the user the requests are made as.
+    fn user_id(&self) -> ID;
+
+    /// Handle for the hub with the given ID, see [`crate::handle`].
+    fn hub(&self, hub: ID) -> HubHandle<'_, Self>
+    where
+        Self: Sized,
+    {
+        HubHandle::new(self, hub)
+    }
+
+    async fn hub_create(&self, name: String) -> Result<ID>;
+    async fn hub_get(&self, hub: ID) -> Result<Hub>;
+    async fn hub_update(
+        &self,
+        hub: ID,
+        name: Option<String>,
+        description: Option<String>,
+        default_group: Option<ID>,
+    ) -> Result<HttpHubUpdate>;
+    async fn hub_delete(&self, hub: ID) -> Result<()>;
+    async fn hub_join(&self, hub: ID) -> Result<()>;
+    async fn hub_leave(&self, hub: ID) -> Result<()>;
+
+    async fn message_get(&self, hub: ID, channel: ID, message: ID) -> Result<Message>;
+    async fn messages_get_after(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: ID,
+        max: usize,
+    ) -> Result<Vec<Message>>;
+    async fn messages_get_before(
+        &self,
+        hub: ID,
+        channel: ID,
+        to: ID,
+        max: usize,
+    ) -> Result<Vec<Message>>;
+    async fn messages_get_last(&self, hub: ID, channel: ID, max: usize) -> Result<Vec<Message>>;
+    async fn messages_get_between(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        max: usize,
+        new_to_old: bool,
+    ) -> Result<Vec<Message>>;
+    async fn message_send(&self, hub: ID, channel: ID, message: String) -> Result<ID>;
+
+    async fn channel_get(&self, hub: ID, channel: ID) -> Result<Channel>;
+    async fn channel_create(&self, hub: ID, name: String) -> Result<ID>;
+    async fn channel_update(
+        &self,
+        hub: ID,
+        channel: ID,
+        update: HttpChannelUpdate,
+    ) -> Result<HttpChannelUpdate>;
+    async fn channel_delete(&self, hub: ID, channel: ID) -> Result<()>;
+
+    async fn member_status(&self, hub: ID, member: ID) -> Result<HttpMemberStatus>;
+    async fn member_get(&self, hub: ID, member: ID) -> Result<HubMember>;
+    async fn member_kick(&self, hub: ID, member: ID) -> Result<()>;
+    async fn member_ban(&self, hub: ID, member: ID) -> Result<()>;
+    async fn member_unban(&self, hub: ID, member: ID) -> Result<()>;
+    async fn member_mute(&self, hub: ID, member: ID) -> Result<()>;
+    async fn member_unmute(&self, hub: ID, member: ID) -> Result<()>;
+    async fn member_get_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+    ) -> Result<PermissionSetting>;
+    async fn member_set_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+        setting: PermissionSetting,
+    ) -> Result<()>;
+    async fn member_get_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+    ) -> Result<PermissionSetting>;
+    async fn member_set_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+        setting: PermissionSetting,
+    ) -> Result<()>;
+}
+
+#[async_trait]
+impl WicrsApi for HttpClient {
+    fn user_id(&self) -> ID {
+        self.user_id
+    }
+
+    async fn hub_create(&self, name: String) -> Result<ID> {
+        HttpClient::hub_create(self, name).await
+    }
+
+    async fn hub_get(&self, hub: ID) -> Result<Hub> {
+        HttpClient::hub_get(self, hub).await
+    }
+
+    async fn hub_update(
+        &self,
+        hub: ID,
+        name: Option<String>,
+        description: Option<String>,
+        default_group: Option<ID>,
+    ) -> Result<HttpHubUpdate> {
+        HttpClient::hub_update(self, hub, name, description, default_group).await
+    }
+
+    async fn hub_delete(&self, hub: ID) -> Result<()> {
+        HttpClient::hub_delete(self, hub).await
+    }
+
+    async fn hub_join(&self, hub: ID) -> Result<()> {
+        HttpClient::hub_join(self, hub).await
+    }
+
+    async fn hub_leave(&self, hub: ID) -> Result<()> {
+        HttpClient::hub_leave(self, hub).await
+    }
+
+    async fn message_get(&self, hub: ID, channel: ID, message: ID) -> Result<Message> {
+        HttpClient::message_get(self, hub, channel, message).await
+    }
+
+    async fn messages_get_after(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: ID,
+        max: usize,
+    ) -> Result<Vec<Message>> {
+        HttpClient::messages_get_after(self, hub, channel, from, max).await
+    }
+
+    async fn messages_get_before(
+        &self,
+        hub: ID,
+        channel: ID,
+        to: ID,
+        max: usize,
+    ) -> Result<Vec<Message>> {
+        HttpClient::messages_get_before(self, hub, channel, to, max).await
+    }
+
+    async fn messages_get_last(&self, hub: ID, channel: ID, max: usize) -> Result<Vec<Message>> {
+        HttpClient::messages_get_last(self, hub, channel, max).await
+    }
+
+    async fn messages_get_between(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        max: usize,
+        new_to_old: bool,
+    ) -> Result<Vec<Message>> {
+        HttpClient::messages_get_between(self, hub, channel, from, to, max, new_to_old).await
+    }
+
+    async fn message_send(&self, hub: ID, channel: ID, message: String) -> Result<ID> {
+        HttpClient::message_send(self, hub, channel, message).await
+    }
+
+    async fn channel_get(&self, hub: ID, channel: ID) -> Result<Channel> {
+        HttpClient::channel_get(self, hub, channel).await
+    }
+
+    async fn channel_create(&self, hub: ID, name: String) -> Result<ID> {
+        HttpClient::channel_create(self, hub, name).await
+    }
+
+    async fn channel_update(
+        &self,
+        hub: ID,
+        channel: ID,
+        update: HttpChannelUpdate,
+    ) -> Result<HttpChannelUpdate> {
+        HttpClient::channel_update(self, hub, channel, update).await
+    }
+
+    async fn channel_delete(&self, hub: ID, channel: ID) -> Result<()> {
+        HttpClient::channel_delete(self, hub, channel).await
+    }
+
+    async fn member_status(&self, hub: ID, member: ID) -> Result<HttpMemberStatus> {
+        HttpClient::member_status(self, hub, member).await
+    }
+
+    async fn member_get(&self, hub: ID, member: ID) -> Result<HubMember> {
+        HttpClient::member_get(self, hub, member).await
+    }
+
+    async fn member_kick(&self, hub: ID, member: ID) -> Result<()> {
+        HttpClient::member_kick(self, hub, member).await
+    }
+
+    async fn member_ban(&self, hub: ID, member: ID) -> Result<()> {
+        HttpClient::member_ban(self, hub, member).await
+    }
+
+    async fn member_unban(&self, hub: ID, member: ID) -> Result<()> {
+        HttpClient::member_unban(self, hub, member).await
+    }
+
+    async fn member_mute(&self, hub: ID, member: ID) -> Result<()> {
+        HttpClient::member_mute(self, hub, member).await
+    }
+
+    async fn member_unmute(&self, hub: ID, member: ID) -> Result<()> {
+        HttpClient::member_unmute(self, hub, member).await
+    }
+
+    async fn member_get_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+    ) -> Result<PermissionSetting> {
+        HttpClient::member_get_hub_permission(self, hub, member, permission).await
+    }
+
+    async fn member_set_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        HttpClient::member_set_hub_permission(self, hub, member, permission, setting).await
+    }
+
+    async fn member_get_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+    ) -> Result<PermissionSetting> {
+        HttpClient::member_get_channel_permission(self, hub, member, channel, permission).await
+    }
+
+    async fn member_set_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        HttpClient::member_set_channel_permission(self, hub, member, channel, permission, setting)
+            .await
+    }
+}
+
+/// Commands and events of a websocket connection.
+#[cfg(feature = "use-tokio")]
+#[async_trait]
+pub trait WicrsWebsocket: Send + Sync {
+    async fn send_message(&self, hub_id: ID, channel_id: ID, message: String) -> Result<()>;
+    async fn subscribe(&self, subscription: Subscription) -> Result<()>;
+    async fn unsubscribe(&self, subscription: Subscription) -> Result<()>;
+    fn subscriptions(&self) -> Vec<Subscription>;
+    async fn start_typing(&self, hub_id: ID, channel_id: ID) -> Result<()>;
+    async fn stop_typing(&self, hub_id: ID, channel_id: ID) -> Result<()>;
+    /// Waits for the next event sent by the server.
+    async fn next_event(&self) -> Result<WsServerMessage>;
+    async fn close(&self, code: u16, reason: &str) -> Result<Option<CloseReason>>;
+}
+
+#[cfg(feature = "use-tokio")]
+#[async_trait]
+impl WicrsWebsocket for WebsocketClient {
+    async fn send_message(&self, hub_id: ID, channel_id: ID, message: String) -> Result<()> {
+        WebsocketClient::send_message(self, hub_id, channel_id, message).await
+    }
+
+    async fn subscribe(&self, subscription: Subscription) -> Result<()> {
+        WebsocketClient::subscribe(self, subscription).await
+    }
+
+    async fn unsubscribe(&self, subscription: Subscription) -> Result<()> {
+        WebsocketClient::unsubscribe(self, subscription).await
+    }
+
+    fn subscriptions(&self) -> Vec<Subscription> {
+        WebsocketClient::subscriptions(self)
+    }
+
+    async fn start_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+        WebsocketClient::start_typing(self, hub_id, channel_id).await
+    }
+
+    async fn stop_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+        WebsocketClient::stop_typing(self, hub_id, channel_id).await
+    }
+
+    async fn next_event(&self) -> Result<WsServerMessage> {
+        self.next_ws_message().await
+    }
+
+    async fn close(&self, code: u16, reason: &str) -> Result<Option<CloseReason>> {
+        WebsocketClient::close(self, code, reason).await
+    }
+}
@@ -0,0 +1,402 @@
+//! File attachments.
+//!
+//! Files are uploaded as `multipart/form-data` to `/attachment/<hub>/<channel>` and downloaded
+//! from `/attachment/<hub>/<channel>/<attachment>`, downloads can be resumed with a `Range`
+//! request. Messages reference attachments with links using the `wicrs-attachment:` scheme, see
+//! [`Attachment::reference`].
+
+use std::{
+    io::SeekFrom,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt};
+use reqwest::{
+    header::{CONTENT_LENGTH, CONTENT_RANGE, RANGE},
+    multipart::{Form, Part},
+    Body, Method, StatusCode,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    error::Result,
+    format::{self, Node},
+    http::HttpClient,
+    protocol::{Response, ID},
+    version::Feature,
+};
+
+pub const ATTACHMENT_SCHEME: &str = "wicrs-attachment:";
+
+/// Size of the chunks in-memory uploads are split into for progress reporting.
+const UPLOAD_CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: ID,
+    pub hub_id: ID,
+    pub channel_id: ID,
+    pub name: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+}
+
+impl Attachment {
+    /// Markup linking to the attachment, to be included in a message.
+    pub fn reference(&self) -> String {
+        format::to_markup(&[Node::Link {
+            text: vec![Node::Text(self.name.clone())],
+            url: format!(
+                "{}{}/{}/{}",
+                ATTACHMENT_SCHEME, self.hub_id, self.channel_id, self.id
+            ),
+        }])
+    }
+}
+
+/// Attachment referenced by a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentRef {
+    pub id: ID,
+    pub hub_id: ID,
+    pub channel_id: ID,
+    pub name: String,
+}
+
+/// Finds the attachments referenced in a message.
+pub fn attachments_in(message: &str) -> Vec<AttachmentRef> {
+    fn collect(nodes: &[Node], attachments: &mut Vec<AttachmentRef>) {
+        for node in nodes {
+            match node {
+                Node::Link { text, url } => {
+                    let ids = url
+                        .strip_prefix(ATTACHMENT_SCHEME)
+                        .map(|path| {
+                            path.split('/')
+                                .filter_map(|id| id.parse::<ID>().ok())
+                                .collect::<Vec<_>>()
+                        })
+                        .unwrap_or_default();
+                    if let [hub_id, channel_id, id] = ids[..] {
+                        attachments.push(AttachmentRef {
+                            id,
+                            hub_id,
+                            channel_id,
+                            name: format::to_plain(text, &()),
+                        });
+                    }
+                }
+                Node::Bold(children) | Node::Italic(children) => collect(children, attachments),
+                _ => {}
+            }
+        }
+    }
+    let mut attachments = Vec::new();
+    collect(&format::parse(message), &mut attachments);
+    attachments
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub transferred: u64,
+    pub total: Option<u64>,
+}
+
+/// Describes the file sent by [`HttpClient::attachment_upload_stream`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upload {
+    pub name: String,
+    pub content_type: Option<String>,
+    /// Sent as the length of the part and used for progress if known.
+    pub size: Option<u64>,
+}
+
+pub type ProgressCallback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Response body of an attachment download.
+pub struct AttachmentDownload {
+    /// Position in the file of the first byte of the body, not 0 if the download was resumed.
+    pub offset: u64,
+    /// Size of the whole file if the server sent it.
+    pub total: Option<u64>,
+    response: reqwest::Response,
+}
+
+impl AttachmentDownload {
+    pub async fn chunk(&mut self) -> Result<Option<Bytes>> {
+        Ok(self.response.chunk().await?)
+    }
+
+    pub fn into_stream(self) -> impl Stream<Item = Result<Bytes>> {
+        stream::unfold(self.response, |mut response| async move {
+            match response.chunk().await {
+                Ok(Some(chunk)) => Some((Ok(chunk), response)),
+                Ok(None) => None,
+                Err(error) => Some((Err(error.into()), response)),
+            }
+        })
+    }
+}
+
+impl HttpClient {
+    pub async fn attachment_upload_stream<S>(
+        &self,
+        hub: ID,
+        channel: ID,
+        upload: Upload,
+        data: S,
+        progress: Option<ProgressCallback>,
+    ) -> Result<Attachment>
+    where
+        S: Stream<Item = std::io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        self.require(Feature::Attachments).await?;
+        let Upload {
+            name,
+            content_type,
+            size,
+        } = upload;
+        let transferred = AtomicU64::new(0);
+        let data = data.map(move |chunk| {
+            if let (Ok(chunk), Some(progress)) = (&chunk, &progress) {
+                let transferred = transferred.fetch_add(chunk.len() as u64, Ordering::Relaxed)
+                    + chunk.len() as u64;
+                progress(Progress {
+                    transferred,
+                    total: size,
+                });
+            }
+            chunk
+        });
+        let body = Body::wrap_stream(data);
+        let mut part = match size {
+            Some(size) => Part::stream_with_length(body, size),
+            None => Part::stream(body),
+        }
+        .file_name(name);
+        if let Some(content_type) = content_type {
+            part = part.mime_str(&content_type)?;
+        }
+        let request = self
+            .request_builder(Method::POST, format!("/attachment/{}/{}", hub, channel))?
+            .multipart(Form::new().part("file", part))
+            .build()?;
+        self.execute(request).await
+    }
+
+    pub async fn attachment_upload_bytes(
+        &self,
+        hub: ID,
+        channel: ID,
+        name: String,
+        content_type: Option<String>,
+        data: Vec<u8>,
+        progress: Option<ProgressCallback>,
+    ) -> Result<Attachment> {
+        let size = data.len() as u64;
+        let data = Bytes::from(data);
+        let chunks = (0..data.len())
+            .step_by(UPLOAD_CHUNK_SIZE)
+            .map(move |start| Ok(data.slice(start..(start + UPLOAD_CHUNK_SIZE).min(data.len()))))
+            .collect::<Vec<_>>();
+        self.attachment_upload_stream(
+            hub,
+            channel,
+            Upload {
+                name,
+                content_type,
+                size: Some(size),
+            },
+            stream::iter(chunks),
+            progress,
+        )
+        .await
+    }
+
+    /// Uploads a file, named after the last component of its path.
+    pub async fn attachment_upload_file<P: AsRef<Path>>(
+        &self,
+        hub: ID,
+        channel: ID,
+        path: P,
+        content_type: Option<String>,
+        progress: Option<ProgressCallback>,
+    ) -> Result<Attachment> {
+        let path = path.as_ref();
+        let file = File::open(path).await?;
+        let size = file.metadata().await?.len();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "attachment".to_string());
+        self.attachment_upload_stream(
+            hub,
+            channel,
+            Upload {
+                name,
+                content_type,
+                size: Some(size),
+            },
+            ReaderStream::new(file),
+            progress,
+        )
+        .await
+    }
+
+    /// Sends a message with references to the given attachments on the lines after it.
+    pub async fn message_send_with_attachments(
+        &self,
+        hub: ID,
+        channel: ID,
+        message: String,
+        attachments: &[Attachment],
+    ) -> Result<ID> {
+        let mut message = message;
+        for attachment in attachments {
+            if !message.is_empty() {
+                message.push('\n');
+            }
+            message.push_str(&attachment.reference());
+        }
+        self.message_send(hub, channel, message).await
+    }
+
+    /// Starts downloading an attachment from `offset` bytes into the file. Fails with
+    /// [`crate::Error::RangeNotSatisfiable`] if the attachment is not longer than `offset`.
+    pub async fn attachment_download(
+        &self,
+        hub: ID,
+        channel: ID,
+        attachment: ID,
+        offset: u64,
+    ) -> Result<AttachmentDownload> {
+        self.require(Feature::Attachments).await?;
+        let mut request = self.request_builder(
+            Method::GET,
+            format!("/attachment/{}/{}/{}", hub, channel, attachment),
+        )?;
+        if offset > 0 {
+            request = request.header(RANGE, format!("bytes={}-", offset));
+        }
+        let request = request.build()?;
+        self.observe(request, |response| self.start_download(response, offset))
+            .await
+    }
+
+    /// Reads the headers of a download response.
+    async fn start_download(
+        &self,
+        response: reqwest::Response,
+        offset: u64,
+    ) -> Result<AttachmentDownload> {
+        let status = response.status();
+        if status == StatusCode::RANGE_NOT_SATISFIABLE {
+            // `bytes */<size>`
+            let size = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.strip_prefix("bytes */"))
+                .and_then(|size| size.parse::<u64>().ok());
+            return Err(crate::Error::RangeNotSatisfiable { offset, size });
+        }
+        if let Err(error) = response.error_for_status_ref() {
+            // API errors come in the usual response envelope.
+            return match response.json::<Response<String>>().await {
+                Ok(Response::Error(error)) => Err(error.into()),
+                _ => Err(error.into()),
+            };
+        }
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value: &reqwest::header::HeaderValue| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let length = header(CONTENT_LENGTH).and_then(|length| length.parse::<u64>().ok());
+        let (offset, total) = if status == StatusCode::PARTIAL_CONTENT {
+            // `bytes <start>-<end>/<total>`
+            let range = header(CONTENT_RANGE).unwrap_or_default();
+            let start = range
+                .trim_start_matches("bytes ")
+                .split('-')
+                .next()
+                .and_then(|start| start.parse::<u64>().ok())
+                .unwrap_or(offset);
+            let total = range
+                .rsplit('/')
+                .next()
+                .and_then(|total| total.parse::<u64>().ok());
+            (start, total)
+        } else {
+            // The server ignored the range and sends the whole file.
+            (0, length)
+        };
+        Ok(AttachmentDownload {
+            offset,
+            total,
+            response,
+        })
+    }
+
+    /// Downloads an attachment to a file, resuming from the end of the file if it exists. A file
+    /// that is not shorter than the attachment is only kept if it has the attachment's size,
+    /// otherwise it is downloaded again. Returns the size of the file once it is complete.
+    pub async fn attachment_download_to_file<P: AsRef<Path>>(
+        &self,
+        hub: ID,
+        channel: ID,
+        attachment: ID,
+        path: P,
+        progress: Option<ProgressCallback>,
+    ) -> Result<u64> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(path)
+            .await?;
+        let existing = file.metadata().await?.len();
+        let mut download = match self
+            .attachment_download(hub, channel, attachment, existing)
+            .await
+        {
+            Ok(download) => download,
+            Err(crate::Error::RangeNotSatisfiable { size, .. }) if size == Some(existing) => {
+                // Nothing left to download.
+                return Ok(existing);
+            }
+            Err(crate::Error::RangeNotSatisfiable { .. }) => {
+                self.attachment_download(hub, channel, attachment, 0)
+                    .await?
+            }
+            Err(error) => return Err(error),
+        };
+        file.set_len(download.offset).await?;
+        file.seek(SeekFrom::Start(download.offset)).await?;
+        let mut transferred = download.offset;
+        while let Some(chunk) = download.chunk().await? {
+            file.write_all(&chunk).await?;
+            transferred += chunk.len() as u64;
+            if let Some(progress) = &progress {
+                progress(Progress {
+                    transferred,
+                    total: download.total,
+                });
+            }
+        }
+        file.flush().await?;
+        Ok(transferred)
+    }
+}
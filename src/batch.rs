@@ -0,0 +1,116 @@
+//! Running many requests concurrently.
+//!
+//! A [`Batch`] runs its requests with a limited number in flight, results are returned in the
+//! order the requests were added and a failed request does not stop the others. Requests still
+//! go through the client, so they share its rate limiter.
+
+use std::future::Future;
+
+use futures_util::{future::BoxFuture, stream, FutureExt, StreamExt};
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::{ChannelPermission, HubMember, PermissionSetting, ID},
+};
+
+pub const DEFAULT_CONCURRENCY: usize = 8;
+
+pub struct Batch<'a, T> {
+    client: &'a HttpClient,
+    concurrency: usize,
+    requests: Vec<BoxFuture<'a, Result<T>>>,
+}
+
+impl<'a, T: Send + 'a> Batch<'a, T> {
+    pub fn new(client: &'a HttpClient) -> Self {
+        Self {
+            client,
+            concurrency: DEFAULT_CONCURRENCY,
+            requests: Vec::new(),
+        }
+    }
+
+    /// Sets the number of requests sent at the same time, at least 1.
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    pub fn push<F, Fut>(&mut self, request: F)
+    where
+        F: FnOnce(&'a HttpClient) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'a,
+    {
+        self.requests.push(request(self.client).boxed());
+    }
+
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Runs every request, returning their results in the order they were added.
+    pub async fn run(self) -> Vec<Result<T>> {
+        stream::iter(self.requests)
+            .buffered(self.concurrency)
+            .collect()
+            .await
+    }
+}
+
+impl HttpClient {
+    pub fn batch<'a, T: Send + 'a>(&'a self) -> Batch<'a, T> {
+        Batch::new(self)
+    }
+
+    /// Runs `request` for every item, see [`Batch`].
+    pub async fn batch_map<'a, I, T, F, Fut>(
+        &'a self,
+        items: I,
+        concurrency: usize,
+        request: F,
+    ) -> Vec<Result<T>>
+    where
+        I: IntoIterator,
+        T: Send + 'a,
+        F: Fn(&'a HttpClient, I::Item) -> Fut,
+        Fut: Future<Output = Result<T>> + Send + 'a,
+    {
+        let mut batch = self.batch().concurrency(concurrency);
+        for item in items {
+            batch.push(|client| request(client, item));
+        }
+        batch.run().await
+    }
+
+    pub async fn members_get(
+        &self,
+        hub: ID,
+        members: &[ID],
+        concurrency: usize,
+    ) -> Vec<Result<HubMember>> {
+        self.batch_map(members.iter().copied(), concurrency, |client, member| {
+            client.member_get(hub, member)
+        })
+        .await
+    }
+
+    /// Gets a member's setting for a permission in each of the given channels.
+    pub async fn member_get_channels_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channels: &[ID],
+        permission: ChannelPermission,
+        concurrency: usize,
+    ) -> Vec<Result<PermissionSetting>> {
+        self.batch_map(channels.iter().copied(), concurrency, |client, channel| {
+            client.member_get_channel_permission(hub, member, channel, permission)
+        })
+        .await
+    }
+}
@@ -0,0 +1,23 @@
+extern crate wicrs_api;
+
+use wicrs_api::{
+    error::Result,
+    irc::{run, GatewayConfig},
+};
+
+#[tokio::main]
+pub async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let listen = args
+        .next()
+        .unwrap_or_else(|| "127.0.0.1:6667".to_string())
+        .parse()
+        .expect("invalid listen address");
+    let server_api_url = args
+        .next()
+        .unwrap_or_else(|| "http://localhost:8080/api".to_string());
+    let websocket_url = args
+        .next()
+        .unwrap_or_else(|| "ws://localhost:8080/api".to_string());
+    run(GatewayConfig::new(listen, server_api_url, websocket_url)).await
+}
@@ -0,0 +1,440 @@
+//! Opt-in caching of hub, channel and member lookups.
+//!
+//! [`CachedClient`] wraps an [`HttpClient`]: concurrent lookups of the same item share one
+//! request, results are kept for a per-endpoint TTL and dropped when the item is changed through
+//! the cached client or a `HubUpdated` event for it is passed to
+//! [`CachedClient::handle_event`].
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::{
+        Channel, ChannelPermission, HttpChannelUpdate, HttpHubUpdate, Hub, HubMember,
+        HubPermission, PermissionSetting, WsHubUpdateType, WsServerMessage, ID,
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheConfig {
+    pub hub_ttl: Duration,
+    pub channel_ttl: Duration,
+    pub member_ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            hub_ttl: Duration::from_secs(30),
+            channel_ttl: Duration::from_secs(60),
+            member_ttl: Duration::from_secs(30),
+        }
+    }
+}
+
+struct TtlCache<K, V> {
+    ttl: Duration,
+    entries: Mutex<HashMap<K, (V, Instant)>>,
+    /// One lock per key being fetched, later lookups wait for the first one's result.
+    fetching: Mutex<HashMap<K, Arc<tokio::sync::Mutex<()>>>>,
+    /// Bumped on every invalidation so fetches that started before it are not stored.
+    generation: AtomicU64,
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> TtlCache<K, V> {
+    fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            fetching: Mutex::new(HashMap::new()),
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    fn cached(&self, key: &K) -> Option<V> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(key)
+            .filter(|(_, fetched)| fetched.elapsed() < self.ttl)
+            .map(|(value, _)| value.clone())
+    }
+
+    async fn get<F, Fut>(&self, key: K, fetch: F) -> Result<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V>>,
+    {
+        if let Some(value) = self.cached(&key) {
+            return Ok(value);
+        }
+        let lock = Arc::clone(
+            self.fetching
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_default(),
+        );
+        let result = {
+            let _fetching = lock.lock().await;
+            match self.cached(&key) {
+                Some(value) => Ok(value),
+                None => {
+                    let generation = self.generation.load(Ordering::Acquire);
+                    let result = fetch().await;
+                    if let Ok(value) = &result {
+                        let mut entries = self.entries.lock().unwrap();
+                        if self.generation.load(Ordering::Acquire) == generation {
+                            entries.insert(key.clone(), (value.clone(), Instant::now()));
+                        }
+                    }
+                    result
+                }
+            }
+        };
+        let mut fetching = self.fetching.lock().unwrap();
+        // Only this lookup and the map still hold the lock.
+        if Arc::strong_count(&lock) == 2 {
+            fetching.remove(&key);
+        }
+        result
+    }
+
+    fn invalidate_where<F: Fn(&K) -> bool>(&self, f: F) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.retain(|key, _| !f(key));
+    }
+}
+
+pub struct CachedClient {
+    client: Arc<HttpClient>,
+    hubs: TtlCache<ID, Hub>,
+    channels: TtlCache<(ID, ID), Channel>,
+    members: TtlCache<(ID, ID), HubMember>,
+}
+
+impl CachedClient {
+    pub fn new(client: Arc<HttpClient>, config: CacheConfig) -> Self {
+        Self {
+            client,
+            hubs: TtlCache::new(config.hub_ttl),
+            channels: TtlCache::new(config.channel_ttl),
+            members: TtlCache::new(config.member_ttl),
+        }
+    }
+
+    /// The wrapped client, for calls that are not cached. Changes made through it are not seen
+    /// by the cache until their `HubUpdated` event is handled or the entries expire.
+    pub fn client(&self) -> &Arc<HttpClient> {
+        &self.client
+    }
+
+    pub async fn hub_get(&self, hub: ID) -> Result<Hub> {
+        self.hubs.get(hub, || self.client.hub_get(hub)).await
+    }
+
+    pub async fn channel_get(&self, hub: ID, channel: ID) -> Result<Channel> {
+        self.channels
+            .get((hub, channel), || self.client.channel_get(hub, channel))
+            .await
+    }
+
+    pub async fn member_get(&self, hub: ID, member: ID) -> Result<HubMember> {
+        self.members
+            .get((hub, member), || self.client.member_get(hub, member))
+            .await
+    }
+
+    /// Drops every cached entry of a hub.
+    pub fn invalidate_hub(&self, hub: ID) {
+        self.hubs.invalidate_where(|id| *id == hub);
+        self.channels.invalidate_where(|(hub_id, _)| *hub_id == hub);
+        self.members.invalidate_where(|(hub_id, _)| *hub_id == hub);
+    }
+
+    pub fn invalidate_channel(&self, hub: ID, channel: ID) {
+        self.hubs.invalidate_where(|id| *id == hub);
+        self.channels.invalidate_where(|key| *key == (hub, channel));
+    }
+
+    pub fn invalidate_member(&self, hub: ID, member: ID) {
+        self.hubs.invalidate_where(|id| *id == hub);
+        self.members.invalidate_where(|key| *key == (hub, member));
+    }
+
+    pub fn clear(&self) {
+        self.hubs.invalidate_where(|_| true);
+        self.channels.invalidate_where(|_| true);
+        self.members.invalidate_where(|_| true);
+    }
+
+    /// Drops the entries changed by a `HubUpdated` event.
+    pub fn handle_event(&self, event: &WsServerMessage) {
+        if let WsServerMessage::HubUpdated {
+            hub_id,
+            update_type,
+        } = event
+        {
+            match update_type {
+                WsHubUpdateType::HubDeleted | WsHubUpdateType::HubUpdated => {
+                    self.invalidate_hub(*hub_id)
+                }
+                WsHubUpdateType::UserJoined(member)
+                | WsHubUpdateType::UserLeft(member)
+                | WsHubUpdateType::UserBanned(member)
+                | WsHubUpdateType::UserUnbanned(member)
+                | WsHubUpdateType::UserMuted(member)
+                | WsHubUpdateType::UserUnmuted(member)
+                | WsHubUpdateType::UserKicked(member)
+                | WsHubUpdateType::UserHubPermissionChanged(member)
+                | WsHubUpdateType::UserChannelPermissionChanged(member, _) => {
+                    self.invalidate_member(*hub_id, *member)
+                }
+                WsHubUpdateType::ChannelCreated(channel)
+                | WsHubUpdateType::ChannelDeleted(channel)
+                | WsHubUpdateType::ChannelUpdated(channel) => {
+                    self.invalidate_channel(*hub_id, *channel)
+                }
+            }
+        }
+    }
+}
+
+impl CachedClient {
+    pub async fn hub_update(
+        &self,
+        hub: ID,
+        name: Option<String>,
+        description: Option<String>,
+        default_group: Option<ID>,
+    ) -> Result<HttpHubUpdate> {
+        let result = self
+            .client
+            .hub_update(hub, name, description, default_group)
+            .await;
+        self.invalidate_hub(hub);
+        result
+    }
+
+    pub async fn hub_delete(&self, hub: ID) -> Result<()> {
+        let result = self.client.hub_delete(hub).await;
+        self.invalidate_hub(hub);
+        result
+    }
+
+    pub async fn hub_join(&self, hub: ID) -> Result<()> {
+        let result = self.client.hub_join(hub).await;
+        self.invalidate_member(hub, self.client.user_id);
+        result
+    }
+
+    pub async fn hub_leave(&self, hub: ID) -> Result<()> {
+        let result = self.client.hub_leave(hub).await;
+        self.invalidate_member(hub, self.client.user_id);
+        result
+    }
+
+    pub async fn channel_create(&self, hub: ID, name: String) -> Result<ID> {
+        let result = self.client.channel_create(hub, name).await;
+        self.hubs.invalidate_where(|id| *id == hub);
+        result
+    }
+
+    pub async fn channel_update(
+        &self,
+        hub: ID,
+        channel: ID,
+        update: HttpChannelUpdate,
+    ) -> Result<HttpChannelUpdate> {
+        let result = self.client.channel_update(hub, channel, update).await;
+        self.invalidate_channel(hub, channel);
+        result
+    }
+
+    pub async fn channel_delete(&self, hub: ID, channel: ID) -> Result<()> {
+        let result = self.client.channel_delete(hub, channel).await;
+        self.invalidate_channel(hub, channel);
+        result
+    }
+}
+
+impl CachedClient {
+    pub async fn member_kick(&self, hub: ID, member: ID) -> Result<()> {
+        let result = self.client.member_kick(hub, member).await;
+        self.invalidate_member(hub, member);
+        result
+    }
+
+    pub async fn member_ban(&self, hub: ID, member: ID) -> Result<()> {
+        let result = self.client.member_ban(hub, member).await;
+        self.invalidate_member(hub, member);
+        result
+    }
+
+    pub async fn member_unban(&self, hub: ID, member: ID) -> Result<()> {
+        let result = self.client.member_unban(hub, member).await;
+        self.invalidate_member(hub, member);
+        result
+    }
+
+    pub async fn member_mute(&self, hub: ID, member: ID) -> Result<()> {
+        let result = self.client.member_mute(hub, member).await;
+        self.invalidate_member(hub, member);
+        result
+    }
+
+    pub async fn member_unmute(&self, hub: ID, member: ID) -> Result<()> {
+        let result = self.client.member_unmute(hub, member).await;
+        self.invalidate_member(hub, member);
+        result
+    }
+
+    pub async fn member_set_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        let result = self
+            .client
+            .member_set_hub_permission(hub, member, permission, setting)
+            .await;
+        self.invalidate_member(hub, member);
+        result
+    }
+
+    pub async fn member_set_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        let result = self
+            .client
+            .member_set_channel_permission(hub, member, channel, permission, setting)
+            .await;
+        self.invalidate_member(hub, member);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+
+    use futures_util::future::join_all;
+
+    use super::*;
+    use crate::{protocol::ApiError, Error};
+
+    /// A fetch counting its calls that returns the number of the call.
+    async fn counted(calls: &AtomicUsize, delay: Duration) -> Result<usize> {
+        let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+        tokio::time::sleep(delay).await;
+        Ok(call)
+    }
+
+    #[tokio::test]
+    async fn concurrent_lookups_share_one_fetch() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        let results = join_all(
+            (0..5).map(|_| cache.get("hub", || counted(&calls, Duration::from_millis(20)))),
+        )
+        .await;
+        assert!(results.into_iter().all(|result| result.unwrap() == 1));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(cache.fetching.lock().unwrap().is_empty());
+
+        let other = cache.get("other", || counted(&calls, Duration::ZERO)).await;
+        assert_eq!(other.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn entries_expire_after_the_ttl() {
+        let cache = TtlCache::new(Duration::from_millis(50));
+        let calls = AtomicUsize::new(0);
+        assert_eq!(
+            cache
+                .get("hub", || counted(&calls, Duration::ZERO))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            cache
+                .get("hub", || counted(&calls, Duration::ZERO))
+                .await
+                .unwrap(),
+            1
+        );
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            cache
+                .get("hub", || counted(&calls, Duration::ZERO))
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        let failed = cache
+            .get("hub", || async { Err(ApiError::NotFound.into()) })
+            .await;
+        assert!(matches!(failed, Err(Error::WICRSError(ApiError::NotFound))));
+        let calls = AtomicUsize::new(0);
+        assert_eq!(
+            cache
+                .get("hub", || counted(&calls, Duration::ZERO))
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn invalidation_during_a_fetch_discards_its_result() {
+        let cache = TtlCache::new(Duration::from_secs(60));
+        let calls = AtomicUsize::new(0);
+        let fetch = cache.get("hub", || counted(&calls, Duration::from_millis(50)));
+        let invalidate = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            cache.invalidate_where(|key| *key == "hub");
+        };
+        let (fetched, ()) = futures_util::join!(fetch, invalidate);
+        assert_eq!(fetched.unwrap(), 1);
+        assert!(cache.cached(&"hub").is_none());
+        assert_eq!(
+            cache
+                .get("hub", || counted(&calls, Duration::ZERO))
+                .await
+                .unwrap(),
+            2
+        );
+
+        cache.invalidate_where(|key| *key == "other");
+        assert_eq!(
+            cache
+                .get("hub", || counted(&calls, Duration::ZERO))
+                .await
+                .unwrap(),
+            2
+        );
+    }
+}
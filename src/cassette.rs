@@ -0,0 +1,516 @@
+//! Recording of client sessions and replay without a server.
+//!
+//! A [`Recorder`] added to an [`HttpClient`](crate::http::HttpClient) as middleware stores every
+//! request with its response, [`Recorder::websocket`] wraps a websocket connection to store the
+//! messages sent and received. The [`Cassette`] can be saved as JSON and served back with a
+//! [`Replayer`] and a [`ReplayWebsocket`].
+//!
+//! Bodies are stored as base64 and passed on to the client as they arrive, so binary bodies
+//! such as attachment downloads are recorded as they are and streamed lists are not buffered. An
+//! HTTP interaction is added to the cassette once its response body has been read, a body that
+//! is dropped before its end is recorded up to where it was read.
+
+use std::{
+    fs,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures_util::{Stream, StreamExt};
+use reqwest::{Request, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::{Error, Result},
+    middleware::{BoxFuture, Middleware, Next},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Interaction {
+    Http {
+        method: String,
+        /// Path and query of the request URL.
+        path: String,
+        #[serde(with = "base64_body::option")]
+        request_body: Option<Vec<u8>>,
+        status: u16,
+        /// Every header of the response, in the order they were received.
+        headers: Vec<(String, String)>,
+        #[serde(with = "base64_body")]
+        response_body: Vec<u8>,
+    },
+    /// Message sent by the client over websocket.
+    WsSent {
+        message: Value,
+    },
+    /// Message received from the server over websocket.
+    WsReceived {
+        message: Value,
+    },
+    WsClose {
+        code: u16,
+        reason: String,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Cassette {
+    pub interactions: Vec<Interaction>,
+}
+
+impl Cassette {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        Ok(fs::write(path, serde_json::to_vec_pretty(self)?)?)
+    }
+}
+
+fn request_path(request: &Request) -> String {
+    let url = request.url();
+    match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_string(),
+    }
+}
+
+fn recorded_response<B: Into<reqwest::Body>>(
+    status: u16,
+    headers: &[(String, String)],
+    body: B,
+) -> Result<Response> {
+    let mut response = http::Response::builder().status(status);
+    for (name, value) in headers {
+        response = response.header(name.as_str(), value.as_str());
+    }
+    Ok(response
+        .body(body.into())
+        .map_err(|error| Error::Cassette(error.to_string()))?
+        .into())
+}
+
+/// Bodies as base64 strings.
+mod base64_body {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        base64::decode(String::deserialize(deserializer)?).map_err(D::Error::custom)
+    }
+
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(
+            body: &Option<Vec<u8>>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match body {
+                Some(body) => super::serialize(body, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Vec<u8>>, D::Error> {
+            Option::<String>::deserialize(deserializer)?
+                .map(|body| base64::decode(body).map_err(serde::de::Error::custom))
+                .transpose()
+        }
+    }
+}
+
+/// An HTTP interaction waiting for the end of its response body.
+struct PendingInteraction {
+    recorder: Recorder,
+    method: String,
+    path: String,
+    request_body: Option<Vec<u8>>,
+    status: u16,
+    headers: Vec<(String, String)>,
+    response_body: Vec<u8>,
+}
+
+/// Response body passed on to the client chunk by chunk while a copy is recorded.
+struct RecordingBody<S> {
+    inner: S,
+    pending: Option<PendingInteraction>,
+}
+
+impl<S> RecordingBody<S> {
+    fn finish(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            pending.recorder.record(Interaction::Http {
+                method: pending.method,
+                path: pending.path,
+                request_body: pending.request_body,
+                status: pending.status,
+                headers: pending.headers,
+                response_body: pending.response_body,
+            });
+        }
+    }
+}
+
+impl<S, B> Stream for RecordingBody<S>
+where
+    S: Stream<Item = reqwest::Result<B>> + Unpin,
+    B: AsRef<[u8]>,
+{
+    type Item = reqwest::Result<B>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = self.inner.poll_next_unpin(cx);
+        match &item {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(pending) = &mut self.pending {
+                    pending.response_body.extend_from_slice(chunk.as_ref());
+                }
+            }
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => self.finish(),
+            Poll::Pending => {}
+        }
+        item
+    }
+}
+
+impl<S> Drop for RecordingBody<S> {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// Records the interactions of the clients it is added to, clones share the same cassette.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cassette(&self) -> Cassette {
+        self.cassette.lock().unwrap().clone()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.cassette().save(path)
+    }
+
+    fn record(&self, interaction: Interaction) {
+        self.cassette.lock().unwrap().interactions.push(interaction);
+    }
+
+    /// Wraps a websocket connection so its messages are recorded.
+    #[cfg(feature = "use-tokio")]
+    pub fn websocket<W: WicrsWebsocket>(&self, websocket: W) -> RecordingWebsocket<W> {
+        RecordingWebsocket {
+            recorder: self.clone(),
+            websocket,
+        }
+    }
+}
+
+impl Middleware for Recorder {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let method = request.method().to_string();
+            let path = request_path(&request);
+            let request_body = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .map(<[u8]>::to_vec);
+            let response = next.run(request).await?;
+            let status = response.status().as_u16();
+            let headers = response
+                .headers()
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.to_string(),
+                        String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let body = RecordingBody {
+                inner: response.bytes_stream(),
+                pending: Some(PendingInteraction {
+                    recorder: self.clone(),
+                    method,
+                    path,
+                    request_body,
+                    status,
+                    headers: headers.clone(),
+                    response_body: Vec::new(),
+                }),
+            };
+            recorded_response(status, &headers, reqwest::Body::wrap_stream(body))
+        })
+    }
+}
+
+/// Answers requests with the recorded responses instead of sending them.
+///
+/// Each request gets the first unused recording with the same method and path, a request without
+/// one fails with [`Error::Cassette`].
+#[derive(Debug)]
+pub struct Replayer {
+    interactions: Mutex<Vec<Option<Interaction>>>,
+}
+
+impl Replayer {
+    pub fn new(cassette: &Cassette) -> Self {
+        Self {
+            interactions: Mutex::new(
+                cassette
+                    .interactions
+                    .iter()
+                    .filter(|interaction| matches!(interaction, Interaction::Http { .. }))
+                    .cloned()
+                    .map(Some)
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Number of recorded requests not made yet.
+    pub fn remaining(&self) -> usize {
+        self.interactions.lock().unwrap().iter().flatten().count()
+    }
+
+    fn take(&self, request: &Request) -> Result<Response> {
+        let method = request.method().as_str();
+        let path = request_path(request);
+        let mut interactions = self.interactions.lock().unwrap();
+        let recorded = interactions.iter_mut().find(|interaction| {
+            matches!(
+                interaction,
+                Some(Interaction::Http { method: recorded_method, path: recorded_path, .. })
+                    if recorded_method == method && *recorded_path == path
+            )
+        });
+        match recorded.and_then(Option::take) {
+            Some(Interaction::Http {
+                status,
+                headers,
+                response_body,
+                ..
+            }) => recorded_response(status, &headers, response_body),
+            _ => Err(Error::Cassette(format!(
+                "no recording for {} {}",
+                method, path
+            ))),
+        }
+    }
+}
+
+impl Middleware for Replayer {
+    fn handle<'a>(&'a self, request: Request, _next: Next<'a>) -> BoxFuture<'a, Result<Response>> {
+        let response = self.take(&request);
+        Box::pin(async move { response })
+    }
+}
+
+#[cfg(feature = "use-tokio")]
+pub use self::websocket::{RecordingWebsocket, ReplayWebsocket};
+#[cfg(feature = "use-tokio")]
+use crate::api::WicrsWebsocket;
+
+#[cfg(feature = "use-tokio")]
+mod websocket {
+    use std::{
+        collections::{HashSet, VecDeque},
+        sync::Mutex,
+    };
+
+    use async_trait::async_trait;
+    use serde_json::Value;
+
+    use super::{Interaction, Recorder};
+    use crate::{
+        api::WicrsWebsocket,
+        error::{Error, Result},
+        protocol::{WsClientMessage, WsServerMessage, ID},
+        websocket::{CloseReason, Subscription},
+    };
+
+    /// Websocket connection recording its messages, see [`Recorder::websocket`].
+    pub struct RecordingWebsocket<W> {
+        pub(super) recorder: Recorder,
+        pub(super) websocket: W,
+    }
+
+    impl<W> RecordingWebsocket<W> {
+        pub fn inner(&self) -> &W {
+            &self.websocket
+        }
+
+        fn sent(&self, message: &WsClientMessage) -> Result<()> {
+            self.recorder.record(Interaction::WsSent {
+                message: serde_json::to_value(message)?,
+            });
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl<W: WicrsWebsocket> WicrsWebsocket for RecordingWebsocket<W> {
+        async fn send_message(&self, hub_id: ID, channel_id: ID, message: String) -> Result<()> {
+            self.sent(&WsClientMessage::SendMessage {
+                hub_id,
+                channel_id,
+                message: message.clone(),
+            })?;
+            self.websocket
+                .send_message(hub_id, channel_id, message)
+                .await
+        }
+
+        async fn subscribe(&self, subscription: Subscription) -> Result<()> {
+            self.sent(&subscription.subscribe_message())?;
+            self.websocket.subscribe(subscription).await
+        }
+
+        async fn unsubscribe(&self, subscription: Subscription) -> Result<()> {
+            self.sent(&subscription.unsubscribe_message())?;
+            self.websocket.unsubscribe(subscription).await
+        }
+
+        fn subscriptions(&self) -> Vec<Subscription> {
+            self.websocket.subscriptions()
+        }
+
+        async fn start_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+            self.sent(&WsClientMessage::StartTyping { hub_id, channel_id })?;
+            self.websocket.start_typing(hub_id, channel_id).await
+        }
+
+        async fn stop_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+            self.sent(&WsClientMessage::StopTyping { hub_id, channel_id })?;
+            self.websocket.stop_typing(hub_id, channel_id).await
+        }
+
+        async fn next_event(&self) -> Result<WsServerMessage> {
+            let event = self.websocket.next_event().await?;
+            self.recorder.record(Interaction::WsReceived {
+                message: serde_json::to_value(&event)?,
+            });
+            Ok(event)
+        }
+
+        async fn close(&self, code: u16, reason: &str) -> Result<Option<CloseReason>> {
+            self.recorder.record(Interaction::WsClose {
+                code,
+                reason: reason.to_string(),
+            });
+            self.websocket.close(code, reason).await
+        }
+    }
+
+    /// Websocket connection returning the messages received in a recording, in order. Messages
+    /// sent are kept for comparison with the recording, once out of messages or closed it
+    /// returns [`Error::WsClosed`].
+    pub struct ReplayWebsocket {
+        received: Mutex<VecDeque<Value>>,
+        sent: Mutex<Vec<Interaction>>,
+        subscriptions: Mutex<HashSet<Subscription>>,
+    }
+
+    impl ReplayWebsocket {
+        pub fn new(cassette: &super::Cassette) -> Self {
+            let received = cassette
+                .interactions
+                .iter()
+                .filter_map(|interaction| match interaction {
+                    Interaction::WsReceived { message } => Some(message.clone()),
+                    _ => None,
+                })
+                .collect();
+            Self {
+                received: Mutex::new(received),
+                sent: Mutex::new(Vec::new()),
+                subscriptions: Mutex::new(HashSet::new()),
+            }
+        }
+
+        /// Messages sent during the replay, as `WsSent` and `WsClose` interactions.
+        pub fn sent(&self) -> Vec<Interaction> {
+            self.sent.lock().unwrap().clone()
+        }
+
+        fn record(&self, message: &WsClientMessage) -> Result<()> {
+            self.sent.lock().unwrap().push(Interaction::WsSent {
+                message: serde_json::to_value(message)?,
+            });
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl WicrsWebsocket for ReplayWebsocket {
+        async fn send_message(&self, hub_id: ID, channel_id: ID, message: String) -> Result<()> {
+            self.record(&WsClientMessage::SendMessage {
+                hub_id,
+                channel_id,
+                message,
+            })
+        }
+
+        async fn subscribe(&self, subscription: Subscription) -> Result<()> {
+            self.record(&subscription.subscribe_message())?;
+            self.subscriptions.lock().unwrap().insert(subscription);
+            Ok(())
+        }
+
+        async fn unsubscribe(&self, subscription: Subscription) -> Result<()> {
+            self.record(&subscription.unsubscribe_message())?;
+            self.subscriptions.lock().unwrap().remove(&subscription);
+            Ok(())
+        }
+
+        fn subscriptions(&self) -> Vec<Subscription> {
+            self.subscriptions.lock().unwrap().iter().copied().collect()
+        }
+
+        async fn start_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+            self.record(&WsClientMessage::StartTyping { hub_id, channel_id })
+        }
+
+        async fn stop_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+            self.record(&WsClientMessage::StopTyping { hub_id, channel_id })
+        }
+
+        async fn next_event(&self) -> Result<WsServerMessage> {
+            let message = self.received.lock().unwrap().pop_front();
+            match message {
+                Some(message) => Ok(serde_json::from_value(message)?),
+                None => Err(Error::WsClosed),
+            }
+        }
+
+        async fn close(&self, code: u16, reason: &str) -> Result<Option<CloseReason>> {
+            self.sent.lock().unwrap().push(Interaction::WsClose {
+                code,
+                reason: reason.to_string(),
+            });
+            self.received.lock().unwrap().clear();
+            self.subscriptions.lock().unwrap().clear();
+            Ok(Some(CloseReason {
+                code,
+                reason: reason.to_string(),
+            }))
+        }
+    }
+}
@@ -3,7 +3,7 @@ use thiserror::Error;
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
-    WICRSError(#[from] wicrs_server::error::ApiError),
+    WICRSError(#[from] crate::protocol::ApiError),
     #[error(transparent)]
     Tungstenite(#[from] tungstenite::Error),
     #[cfg(feature = "use-tokio")]
@@ -15,10 +15,96 @@ pub enum Error {
     TokioMpscSend,
     #[error("websocket connection closed")]
     WsClosed,
+    #[error("websocket client was shut down")]
+    WsShutdown,
+    #[error("no response from the websocket server for {0:?}")]
+    HeartbeatTimeout(std::time::Duration),
+    #[error("the websocket stream does not support read timeouts, enable the matching TLS feature or disable heartbeats")]
+    ReadTimeoutUnsupported,
     #[error(transparent)]
     Reqwest(#[from] reqwest::Error),
     #[error(transparent)]
     Url(#[from] url::ParseError),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("cassette: {0}")]
+    Cassette(String),
+    #[error("unknown configuration format: {0}")]
+    UnknownConfigFormat(String),
+    #[error("channel #{0} is in the configuration more than once")]
+    DuplicateChannel(String),
+    #[error("the hub has more than one channel named #{0}")]
+    AmbiguousChannel(String),
+    #[error("the plan has no channel named #{0}")]
+    UnknownChannel(String),
+    #[error("server protocol version {server} is not compatible with client version {client}")]
+    IncompatibleServer {
+        server: crate::version::ProtocolVersion,
+        client: crate::version::ProtocolVersion,
+    },
+    #[error("{feature} needs a newer server than protocol version {server}")]
+    Unsupported {
+        feature: crate::version::Feature,
+        server: crate::version::ProtocolVersion,
+    },
+    #[error("the server has no bytes of the attachment after {offset}, its size is {size:?}")]
+    RangeNotSatisfiable { offset: u64, size: Option<u64> },
+    #[error("invalid protocol version: {0}")]
+    InvalidProtocolVersion(String),
+    #[error("rate must be a positive number of requests per second, got {0}")]
+    InvalidRate(f64),
+    #[error("burst must allow at least one request")]
+    InvalidBurst,
+    #[error("no response was scripted for {0}")]
+    Unscripted(&'static str),
+    #[cfg(feature = "config-toml")]
+    #[error(transparent)]
+    Toml(#[from] toml::de::Error),
+    #[cfg(feature = "config-yaml")]
+    #[error(transparent)]
+    Yaml(#[from] serde_yaml::Error),
+    #[cfg(feature = "webhook-server")]
+    #[error(transparent)]
+    Hyper(#[from] hyper::Error),
+}
+
+impl Error {
+    /// Name of the error variant, used to label error metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::WICRSError(_) => "WICRSError",
+            Error::Tungstenite(_) => "Tungstenite",
+            #[cfg(feature = "use-tokio")]
+            Error::TokioTungstenite(_) => "TokioTungstenite",
+            Error::Json(_) => "Json",
+            Error::TokioMpscSend => "TokioMpscSend",
+            Error::WsClosed => "WsClosed",
+            Error::WsShutdown => "WsShutdown",
+            Error::HeartbeatTimeout(_) => "HeartbeatTimeout",
+            Error::ReadTimeoutUnsupported => "ReadTimeoutUnsupported",
+            Error::Reqwest(_) => "Reqwest",
+            Error::Url(_) => "Url",
+            Error::Io(_) => "Io",
+            Error::Cassette(_) => "Cassette",
+            Error::UnknownConfigFormat(_) => "UnknownConfigFormat",
+            Error::DuplicateChannel(_) => "DuplicateChannel",
+            Error::AmbiguousChannel(_) => "AmbiguousChannel",
+            Error::UnknownChannel(_) => "UnknownChannel",
+            Error::IncompatibleServer { .. } => "IncompatibleServer",
+            Error::Unsupported { .. } => "Unsupported",
+            Error::RangeNotSatisfiable { .. } => "RangeNotSatisfiable",
+            Error::InvalidProtocolVersion(_) => "InvalidProtocolVersion",
+            Error::InvalidRate(_) => "InvalidRate",
+            Error::InvalidBurst => "InvalidBurst",
+            Error::Unscripted(_) => "Unscripted",
+            #[cfg(feature = "config-toml")]
+            Error::Toml(_) => "Toml",
+            #[cfg(feature = "config-yaml")]
+            Error::Yaml(_) => "Yaml",
+            #[cfg(feature = "webhook-server")]
+            Error::Hyper(_) => "Hyper",
+        }
+    }
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
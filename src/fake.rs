@@ -0,0 +1,644 @@
+//! In-memory implementations of the traits in [`crate::api`] for tests.
+//!
+//! [`FakeApi`] records every call with its arguments as JSON and answers from responses scripted
+//! per method. [`FakeWebsocket`] records commands and returns events pushed by the test.
+
+use std::{
+    any::TypeId,
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    api::WicrsApi,
+    error::{Error, Result},
+    protocol::{
+        Channel, ChannelPermission, HttpChannelUpdate, HttpHubUpdate, HttpMemberStatus, Hub,
+        HubMember, HubPermission, Message, PermissionSetting, ID,
+    },
+};
+
+/// A call made to a [`FakeApi`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    /// Name of the [`WicrsApi`] method.
+    pub method: &'static str,
+    pub args: Vec<Value>,
+}
+
+type Responder = Arc<dyn Fn(&Call) -> Result<Value> + Send + Sync>;
+
+/// Scriptable [`WicrsApi`].
+///
+/// A call is answered by the first response queued for its method, then by the responder set
+/// with [`FakeApi::on`]. Calls without a response succeed for methods returning `()` and fail with
+/// [`Error::Unscripted`] naming the method for the others.
+pub struct FakeApi {
+    user_id: ID,
+    calls: Mutex<Vec<Call>>,
+    queued: Mutex<HashMap<&'static str, VecDeque<Result<Value>>>>,
+    responders: Mutex<HashMap<&'static str, Responder>>,
+}
+
+impl FakeApi {
+    pub fn new(user_id: ID) -> Self {
+        Self {
+            user_id,
+            calls: Mutex::new(Vec::new()),
+            queued: Mutex::new(HashMap::new()),
+            responders: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Answers every call to `method` with the result of `responder`.
+    pub fn on<F>(&self, method: &'static str, responder: F)
+    where
+        F: Fn(&Call) -> Result<Value> + Send + Sync + 'static,
+    {
+        self.responders
+            .lock()
+            .unwrap()
+            .insert(method, Arc::new(responder));
+    }
+
+    /// Answers every call to `method` with `value`.
+    pub fn respond<T: Serialize>(&self, method: &'static str, value: T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        self.on(method, move |_| Ok(value.clone()));
+        Ok(())
+    }
+
+    /// Answers the next call to `method` with `value`.
+    pub fn respond_once<T: Serialize>(&self, method: &'static str, value: T) -> Result<()> {
+        let value = serde_json::to_value(value)?;
+        self.queue(method, Ok(value));
+        Ok(())
+    }
+
+    /// Fails the next call to `method` with `error`.
+    pub fn fail_once<E: Into<Error>>(&self, method: &'static str, error: E) {
+        self.queue(method, Err(error.into()));
+    }
+
+    fn queue(&self, method: &'static str, result: Result<Value>) {
+        self.queued
+            .lock()
+            .unwrap()
+            .entry(method)
+            .or_default()
+            .push_back(result);
+    }
+
+    pub fn calls(&self) -> Vec<Call> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    pub fn calls_to(&self, method: &str) -> Vec<Call> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| call.method == method)
+            .cloned()
+            .collect()
+    }
+
+    pub fn clear_calls(&self) {
+        self.calls.lock().unwrap().clear();
+    }
+
+    fn call<R: DeserializeOwned + 'static>(
+        &self,
+        method: &'static str,
+        args: Vec<Value>,
+    ) -> Result<R> {
+        let call = Call { method, args };
+        self.calls.lock().unwrap().push(call.clone());
+        let queued = self
+            .queued
+            .lock()
+            .unwrap()
+            .get_mut(method)
+            .and_then(VecDeque::pop_front);
+        let value = match queued {
+            Some(result) => result?,
+            None => {
+                let responder = self.responders.lock().unwrap().get(method).cloned();
+                match responder {
+                    Some(responder) => responder(&call)?,
+                    None if TypeId::of::<R>() == TypeId::of::<()>() => Value::Null,
+                    None => return Err(Error::Unscripted(method)),
+                }
+            }
+        };
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+macro_rules! call {
+    ($fake:expr, $method:literal $(, $arg:expr)*) => {
+        $fake.call($method, vec![$(serde_json::to_value(&$arg)?),*])
+    };
+}
+
+#[async_trait]
+impl WicrsApi for FakeApi {
+    fn user_id(&self) -> ID {
+        self.user_id
+    }
+
+    async fn hub_create(&self, name: String) -> Result<ID> {
+        call!(self, "hub_create", name)
+    }
+
+    async fn hub_get(&self, hub: ID) -> Result<Hub> {
+        call!(self, "hub_get", hub)
+    }
+
+    async fn hub_update(
+        &self,
+        hub: ID,
+        name: Option<String>,
+        description: Option<String>,
+        default_group: Option<ID>,
+    ) -> Result<HttpHubUpdate> {
+        call!(self, "hub_update", hub, name, description, default_group)
+    }
+
+    async fn hub_delete(&self, hub: ID) -> Result<()> {
+        call!(self, "hub_delete", hub)
+    }
+
+    async fn hub_join(&self, hub: ID) -> Result<()> {
+        call!(self, "hub_join", hub)
+    }
+
+    async fn hub_leave(&self, hub: ID) -> Result<()> {
+        call!(self, "hub_leave", hub)
+    }
+
+    async fn message_get(&self, hub: ID, channel: ID, message: ID) -> Result<Message> {
+        call!(self, "message_get", hub, channel, message)
+    }
+
+    async fn messages_get_after(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: ID,
+        max: usize,
+    ) -> Result<Vec<Message>> {
+        call!(self, "messages_get_after", hub, channel, from, max)
+    }
+
+    async fn messages_get_before(
+        &self,
+        hub: ID,
+        channel: ID,
+        to: ID,
+        max: usize,
+    ) -> Result<Vec<Message>> {
+        call!(self, "messages_get_before", hub, channel, to, max)
+    }
+
+    async fn messages_get_last(&self, hub: ID, channel: ID, max: usize) -> Result<Vec<Message>> {
+        call!(self, "messages_get_last", hub, channel, max)
+    }
+
+    async fn messages_get_between(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        max: usize,
+        new_to_old: bool,
+    ) -> Result<Vec<Message>> {
+        call!(
+            self,
+            "messages_get_between",
+            hub,
+            channel,
+            from,
+            to,
+            max,
+            new_to_old
+        )
+    }
+
+    async fn message_send(&self, hub: ID, channel: ID, message: String) -> Result<ID> {
+        call!(self, "message_send", hub, channel, message)
+    }
+
+    async fn channel_get(&self, hub: ID, channel: ID) -> Result<Channel> {
+        call!(self, "channel_get", hub, channel)
+    }
+
+    async fn channel_create(&self, hub: ID, name: String) -> Result<ID> {
+        call!(self, "channel_create", hub, name)
+    }
+
+    async fn channel_update(
+        &self,
+        hub: ID,
+        channel: ID,
+        update: HttpChannelUpdate,
+    ) -> Result<HttpChannelUpdate> {
+        call!(self, "channel_update", hub, channel, update)
+    }
+
+    async fn channel_delete(&self, hub: ID, channel: ID) -> Result<()> {
+        call!(self, "channel_delete", hub, channel)
+    }
+
+    async fn member_status(&self, hub: ID, member: ID) -> Result<HttpMemberStatus> {
+        call!(self, "member_status", hub, member)
+    }
+
+    async fn member_get(&self, hub: ID, member: ID) -> Result<HubMember> {
+        call!(self, "member_get", hub, member)
+    }
+
+    async fn member_kick(&self, hub: ID, member: ID) -> Result<()> {
+        call!(self, "member_kick", hub, member)
+    }
+
+    async fn member_ban(&self, hub: ID, member: ID) -> Result<()> {
+        call!(self, "member_ban", hub, member)
+    }
+
+    async fn member_unban(&self, hub: ID, member: ID) -> Result<()> {
+        call!(self, "member_unban", hub, member)
+    }
+
+    async fn member_mute(&self, hub: ID, member: ID) -> Result<()> {
+        call!(self, "member_mute", hub, member)
+    }
+
+    async fn member_unmute(&self, hub: ID, member: ID) -> Result<()> {
+        call!(self, "member_unmute", hub, member)
+    }
+
+    async fn member_get_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+    ) -> Result<PermissionSetting> {
+        call!(self, "member_get_hub_permission", hub, member, permission)
+    }
+
+    async fn member_set_hub_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        permission: HubPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        call!(
+            self,
+            "member_set_hub_permission",
+            hub,
+            member,
+            permission,
+            setting
+        )
+    }
+
+    async fn member_get_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+    ) -> Result<PermissionSetting> {
+        call!(
+            self,
+            "member_get_channel_permission",
+            hub,
+            member,
+            channel,
+            permission
+        )
+    }
+
+    async fn member_set_channel_permission(
+        &self,
+        hub: ID,
+        member: ID,
+        channel: ID,
+        permission: ChannelPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        call!(
+            self,
+            "member_set_channel_permission",
+            hub,
+            member,
+            channel,
+            permission,
+            setting
+        )
+    }
+}
+
+#[cfg(feature = "use-tokio")]
+pub use self::websocket::{FakeWebsocket, WsCommand};
+
+#[cfg(feature = "use-tokio")]
+mod websocket {
+    use std::{collections::HashSet, sync::Mutex};
+
+    use async_trait::async_trait;
+    use tokio::sync::mpsc;
+
+    use crate::{
+        api::WicrsWebsocket,
+        error::{Error, Result},
+        protocol::{WsServerMessage, ID},
+        websocket::{CloseReason, Subscription},
+    };
+
+    /// A command sent through a [`FakeWebsocket`].
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum WsCommand {
+        SendMessage {
+            hub_id: ID,
+            channel_id: ID,
+            message: String,
+        },
+        Subscribe(Subscription),
+        Unsubscribe(Subscription),
+        StartTyping {
+            hub_id: ID,
+            channel_id: ID,
+        },
+        StopTyping {
+            hub_id: ID,
+            channel_id: ID,
+        },
+        Close {
+            code: u16,
+            reason: String,
+        },
+    }
+
+    /// [`WicrsWebsocket`] returning the events pushed to it, once closed and out of events it
+    /// returns [`Error::WsClosed`].
+    pub struct FakeWebsocket {
+        commands: Mutex<Vec<WsCommand>>,
+        subscriptions: Mutex<HashSet<Subscription>>,
+        sender: Mutex<Option<mpsc::UnboundedSender<Result<WsServerMessage>>>>,
+        receiver: tokio::sync::Mutex<mpsc::UnboundedReceiver<Result<WsServerMessage>>>,
+    }
+
+    impl Default for FakeWebsocket {
+        fn default() -> Self {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            Self {
+                commands: Mutex::new(Vec::new()),
+                subscriptions: Mutex::new(HashSet::new()),
+                sender: Mutex::new(Some(sender)),
+                receiver: tokio::sync::Mutex::new(receiver),
+            }
+        }
+    }
+
+    impl FakeWebsocket {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queues an event, ignored once the connection is closed.
+        pub fn push_event(&self, event: WsServerMessage) {
+            self.push(Ok(event));
+        }
+
+        pub fn push_error<E: Into<Error>>(&self, error: E) {
+            self.push(Err(error.into()));
+        }
+
+        fn push(&self, event: Result<WsServerMessage>) {
+            if let Some(sender) = self.sender.lock().unwrap().as_ref() {
+                let _ = sender.send(event);
+            }
+        }
+
+        pub fn commands(&self) -> Vec<WsCommand> {
+            self.commands.lock().unwrap().clone()
+        }
+
+        pub fn clear_commands(&self) {
+            self.commands.lock().unwrap().clear();
+        }
+
+        fn record(&self, command: WsCommand) -> Result<()> {
+            if self.sender.lock().unwrap().is_none() {
+                return Err(Error::WsClosed);
+            }
+            self.commands.lock().unwrap().push(command);
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl WicrsWebsocket for FakeWebsocket {
+        async fn send_message(&self, hub_id: ID, channel_id: ID, message: String) -> Result<()> {
+            self.record(WsCommand::SendMessage {
+                hub_id,
+                channel_id,
+                message,
+            })
+        }
+
+        async fn subscribe(&self, subscription: Subscription) -> Result<()> {
+            self.record(WsCommand::Subscribe(subscription))?;
+            self.subscriptions.lock().unwrap().insert(subscription);
+            Ok(())
+        }
+
+        async fn unsubscribe(&self, subscription: Subscription) -> Result<()> {
+            self.record(WsCommand::Unsubscribe(subscription))?;
+            self.subscriptions.lock().unwrap().remove(&subscription);
+            Ok(())
+        }
+
+        fn subscriptions(&self) -> Vec<Subscription> {
+            self.subscriptions.lock().unwrap().iter().copied().collect()
+        }
+
+        async fn start_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+            self.record(WsCommand::StartTyping { hub_id, channel_id })
+        }
+
+        async fn stop_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
+            self.record(WsCommand::StopTyping { hub_id, channel_id })
+        }
+
+        async fn next_event(&self) -> Result<WsServerMessage> {
+            self.receiver
+                .lock()
+                .await
+                .recv()
+                .await
+                .unwrap_or(Err(Error::WsClosed))
+        }
+
+        /// Records the close and answers with the same code and reason.
+        async fn close(&self, code: u16, reason: &str) -> Result<Option<CloseReason>> {
+            self.record(WsCommand::Close {
+                code,
+                reason: reason.to_string(),
+            })?;
+            self.subscriptions.lock().unwrap().clear();
+            self.sender.lock().unwrap().take();
+            Ok(Some(CloseReason {
+                code,
+                reason: reason.to_string(),
+            }))
+        }
+    }
+}
+
+#[cfg(all(test, feature = "use-tokio"))]
+mod tests {
+    use super::*;
+    use crate::{
+        api::WicrsWebsocket,
+        protocol::{ApiError, WsServerMessage},
+        websocket::Subscription,
+    };
+
+    #[tokio::test]
+    async fn queued_responses_come_before_the_responder() {
+        let api = FakeApi::new(ID::new_v4());
+        let (first, second, later) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        api.respond("channel_create", later).unwrap();
+        api.respond_once("channel_create", first).unwrap();
+        api.respond_once("channel_create", second).unwrap();
+        let hub = ID::new_v4();
+        for expected in [first, second, later, later] {
+            let id = api.channel_create(hub, "name".to_string()).await.unwrap();
+            assert_eq!(id, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_once() {
+        let api = FakeApi::new(ID::new_v4());
+        let hub = ID::new_v4();
+        api.fail_once("hub_delete", ApiError::NotFound);
+        assert!(matches!(
+            api.hub_delete(hub).await,
+            Err(Error::WICRSError(ApiError::NotFound))
+        ));
+        api.hub_delete(hub).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn records_calls_with_their_arguments() {
+        let api = FakeApi::new(ID::new_v4());
+        let (hub, channel) = (ID::new_v4(), ID::new_v4());
+        api.hub_join(hub).await.unwrap();
+        api.channel_delete(hub, channel).await.unwrap();
+        api.hub_leave(hub).await.unwrap();
+
+        let json = |id: ID| serde_json::to_value(id).unwrap();
+        assert_eq!(
+            api.calls(),
+            [
+                Call {
+                    method: "hub_join",
+                    args: vec![json(hub)],
+                },
+                Call {
+                    method: "channel_delete",
+                    args: vec![json(hub), json(channel)],
+                },
+                Call {
+                    method: "hub_leave",
+                    args: vec![json(hub)],
+                },
+            ]
+        );
+        assert_eq!(api.calls_to("channel_delete").len(), 1);
+        api.clear_calls();
+        assert!(api.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn unscripted_calls_name_the_method() {
+        let api = FakeApi::new(ID::new_v4());
+        // A response for a misspelled method does not answer the real one.
+        api.respond("hub_gett", "ignored").unwrap();
+        match api.hub_get(ID::new_v4()).await {
+            Err(error @ Error::Unscripted("hub_get")) => {
+                assert_eq!(error.to_string(), "no response was scripted for hub_get");
+            }
+            other => panic!("{:?}", other.map(|hub| hub.id)),
+        }
+        match api
+            .member_get_channel_permission(
+                ID::new_v4(),
+                ID::new_v4(),
+                ID::new_v4(),
+                ChannelPermission::Read,
+            )
+            .await
+        {
+            Err(Error::Unscripted("member_get_channel_permission")) => {}
+            other => panic!("{:?}", other),
+        }
+        api.hub_leave(ID::new_v4()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn websocket_records_commands_until_closed() {
+        let websocket = FakeWebsocket::new();
+        let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+        websocket
+            .subscribe(Subscription::Hub(hub_id))
+            .await
+            .unwrap();
+        websocket
+            .send_message(hub_id, channel_id, "hi".to_string())
+            .await
+            .unwrap();
+        websocket.push_event(WsServerMessage::Success);
+        assert!(matches!(
+            websocket.next_event().await,
+            Ok(WsServerMessage::Success)
+        ));
+        assert_eq!(websocket.subscriptions(), [Subscription::Hub(hub_id)]);
+
+        websocket.close(1000, "done").await.unwrap();
+        assert!(websocket.subscriptions().is_empty());
+        assert!(matches!(
+            websocket
+                .send_message(hub_id, channel_id, "late".to_string())
+                .await,
+            Err(Error::WsClosed)
+        ));
+        assert!(matches!(websocket.next_event().await, Err(Error::WsClosed)));
+        assert_eq!(
+            websocket.commands(),
+            [
+                WsCommand::Subscribe(Subscription::Hub(hub_id)),
+                WsCommand::SendMessage {
+                    hub_id,
+                    channel_id,
+                    message: "hi".to_string(),
+                },
+                WsCommand::Close {
+                    code: 1000,
+                    reason: "done".to_string(),
+                },
+            ]
+        );
+    }
+}
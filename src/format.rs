@@ -0,0 +1,660 @@
+//! The wicrs message markup.
+//!
+//! | Markup              | Meaning                   |
+//! |---------------------|---------------------------|
+//! | `**text**`          | bold                      |
+//! | `*text*`, `_text_`  | italics                   |
+//! | `` `code` ``        | inline code               |
+//! | `[text](url)`       | link                      |
+//! | `<@user_id>`        | mention of a user         |
+//! | `<#channel_id>`     | reference to a channel    |
+//!
+//! A backslash escapes the next character. Markup that is not closed is kept as plain text.
+//! [`parse`] turns a message into [`Node`]s which can be rendered with [`to_plain`],
+//! [`to_ansi`] and [`to_html`], [`MessageBuilder`] composes messages with user input escaped.
+
+use std::{collections::HashMap, fmt::Write};
+
+use uuid::Uuid;
+
+use crate::protocol::ID;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Node {
+    Text(String),
+    Bold(Vec<Node>),
+    Italic(Vec<Node>),
+    Code(String),
+    Link { text: Vec<Node>, url: String },
+    Mention(ID),
+    Channel(ID),
+}
+
+/// Characters that have to be escaped to be shown as they are.
+const SPECIAL: &[char] = &['\\', '*', '_', '`', '[', ']', '(', ')', '<', '>'];
+
+/// Deepest nesting of bold, italics and links, deeper markup is kept as text.
+const MAX_DEPTH: usize = 8;
+
+/// Length of the longest text [`Uuid::parse_str`] accepts, a URN.
+const MAX_ID_LENGTH: usize = 45;
+
+pub fn parse(input: &str) -> Vec<Node> {
+    Parser::new(input).nodes(0, None, 0)
+}
+
+/// Markup found at a position.
+#[derive(Debug, Clone, Copy)]
+enum Markup {
+    Bold,
+    Italic(&'static str),
+    Link,
+    Code,
+    Mention,
+    Channel,
+}
+
+/// Parses in two passes, [`Parser::group`] first finds where every delimiter that is opened is
+/// closed and [`Parser::nodes`] then builds the nodes. Where a search for a delimiter ends only
+/// depends on the position, the delimiter and the depth, so it is remembered for every position
+/// the search passes. Openings that are never closed do not make the text after them be
+/// searched again for every opening, which keeps parsing linear.
+struct Parser<'a> {
+    input: &'a str,
+    /// Position after the closing delimiter for a position, delimiter and depth, or `None` if
+    /// the end of the input is reached first.
+    groups: HashMap<(usize, &'static str, usize), Option<usize>>,
+    /// Position after the closing character of raw text for a position and character.
+    raw: HashMap<(usize, char), Option<usize>>,
+    /// Characters looked at so far, counted to test that parsing is linear.
+    steps: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Self {
+            input,
+            groups: HashMap::new(),
+            raw: HashMap::new(),
+            steps: 0,
+        }
+    }
+
+    /// Finds the end of a group of nodes closed by `close` starting at `from`, returns the
+    /// position after `close` or `None` if it is never found.
+    fn group(&mut self, from: usize, close: &'static str, depth: usize) -> Option<usize> {
+        let mut visited = Vec::new();
+        let mut pos = from;
+        let end = loop {
+            self.steps += 1;
+            if let Some(end) = self.groups.get(&(pos, close, depth)) {
+                break *end;
+            }
+            visited.push(pos);
+            let rest = &self.input[pos..];
+            if rest.is_empty() {
+                break None;
+            }
+            if let Some(end) = self.bold_in_italics(pos, close, depth) {
+                pos = end;
+                continue;
+            }
+            if rest.starts_with(close) {
+                break Some(pos + close.len());
+            }
+            pos = match self.markup(pos, depth) {
+                Some((_, end)) => end,
+                None => self.text_char(pos).1,
+            };
+        };
+        for pos in visited {
+            self.groups.insert((pos, close, depth), end);
+        }
+        end
+    }
+
+    /// Like [`Parser::group`] but fails for empty groups.
+    fn non_empty_group(&mut self, from: usize, close: &'static str, depth: usize) -> Option<usize> {
+        self.group(from, close, depth)
+            .filter(|end| *end > from + close.len())
+    }
+
+    /// Bold inside italics, `*a **b***`, which would otherwise be closed by the first `*`.
+    fn bold_in_italics(&mut self, pos: usize, close: &'static str, depth: usize) -> Option<usize> {
+        if close == "*" && self.input[pos..].starts_with("**") && depth < MAX_DEPTH {
+            self.non_empty_group(pos + 2, "**", depth + 1)
+        } else {
+            None
+        }
+    }
+
+    /// The markup starting at `pos` and the position after it.
+    fn markup(&mut self, pos: usize, depth: usize) -> Option<(Markup, usize)> {
+        if depth >= MAX_DEPTH {
+            return None;
+        }
+        let rest = &self.input[pos..];
+        let c = rest.chars().next()?;
+        if rest.starts_with("**") {
+            self.non_empty_group(pos + 2, "**", depth + 1)
+                .map(|end| (Markup::Bold, end))
+        } else if c == '*' || (c == '_' && !self.after_word(pos)) {
+            let close = if c == '*' { "*" } else { "_" };
+            self.non_empty_group(pos + 1, close, depth + 1)
+                .map(|end| (Markup::Italic(close), end))
+        } else if c == '[' {
+            let text_end = self.group(pos + 1, "](", depth + 1)?;
+            self.raw_end(text_end, ')').map(|end| (Markup::Link, end))
+        } else if c == '`' {
+            self.raw_end(pos + 1, '`').map(|end| (Markup::Code, end))
+        } else if rest.starts_with("<@") || rest.starts_with("<#") {
+            let end = self.raw_end(pos + 2, '>')?;
+            // Longer text can not be an ID, it is not read to keep long text linear.
+            if end - pos > 2 * MAX_ID_LENGTH {
+                return None;
+            }
+            Uuid::parse_str(&self.raw(pos + 2, '>')).ok()?;
+            let markup = if rest.starts_with("<@") {
+                Markup::Mention
+            } else {
+                Markup::Channel
+            };
+            Some((markup, end))
+        } else {
+            None
+        }
+    }
+
+    /// Builds the nodes from `from` up to `close`, which has to be known to be found.
+    fn nodes(&mut self, from: usize, close: Option<&'static str>, depth: usize) -> Vec<Node> {
+        let mut nodes = Vec::new();
+        let mut pos = from;
+        while pos < self.input.len() {
+            self.steps += 1;
+            if let Some(close) = close {
+                if let Some(end) = self.bold_in_italics(pos, close, depth) {
+                    nodes.push(Node::Bold(self.nodes(pos + 2, Some("**"), depth + 1)));
+                    pos = end;
+                    continue;
+                }
+                if self.input[pos..].starts_with(close) {
+                    break;
+                }
+            }
+            match self.markup(pos, depth) {
+                Some((markup, end)) => {
+                    let node = match markup {
+                        Markup::Bold => Node::Bold(self.nodes(pos + 2, Some("**"), depth + 1)),
+                        Markup::Italic(close) => {
+                            Node::Italic(self.nodes(pos + 1, Some(close), depth + 1))
+                        }
+                        Markup::Link => {
+                            let text_end = self.group(pos + 1, "](", depth + 1).unwrap_or(end);
+                            Node::Link {
+                                text: self.nodes(pos + 1, Some("]("), depth + 1),
+                                url: self.raw(text_end, ')'),
+                            }
+                        }
+                        Markup::Code => Node::Code(self.raw(pos + 1, '`')),
+                        Markup::Mention | Markup::Channel => {
+                            let id = Uuid::parse_str(&self.raw(pos + 2, '>')).unwrap_or_default();
+                            match markup {
+                                Markup::Mention => Node::Mention(id),
+                                _ => Node::Channel(id),
+                            }
+                        }
+                    };
+                    nodes.push(node);
+                    pos = end;
+                }
+                None => {
+                    let (c, end) = self.text_char(pos);
+                    push_char(&mut nodes, c);
+                    pos = end;
+                }
+            }
+        }
+        nodes
+    }
+
+    /// A character that is not markup and the position after it, a backslash escapes the next
+    /// character.
+    fn text_char(&self, pos: usize) -> (char, usize) {
+        let mut chars = self.input[pos..].chars();
+        let c = chars.next().unwrap_or_default();
+        match chars.next() {
+            Some(escaped) if c == '\\' => (escaped, pos + c.len_utf8() + escaped.len_utf8()),
+            _ => (c, pos + c.len_utf8()),
+        }
+    }
+
+    /// Whether the character at `pos` directly follows a letter or digit, `_` does not start
+    /// italics inside words like `snake_case`.
+    fn after_word(&self, pos: usize) -> bool {
+        matches!(self.input[..pos].chars().next_back(), Some(c) if c.is_alphanumeric())
+    }
+
+    /// Finds the end of raw text, with backslash escapes, closed by `close`, returns the
+    /// position after `close`.
+    fn raw_end(&mut self, from: usize, close: char) -> Option<usize> {
+        let mut visited = Vec::new();
+        let mut pos = from;
+        let end = loop {
+            self.steps += 1;
+            if let Some(end) = self.raw.get(&(pos, close)) {
+                break *end;
+            }
+            visited.push(pos);
+            let mut chars = self.input[pos..].chars();
+            match chars.next() {
+                None => break None,
+                Some(c) if c == close => break Some(pos + c.len_utf8()),
+                Some('\\') => {
+                    pos += 1 + chars.next().map_or(0, char::len_utf8);
+                }
+                Some(c) => pos += c.len_utf8(),
+            }
+        };
+        for pos in visited {
+            self.raw.insert((pos, close), end);
+        }
+        end
+    }
+
+    /// Reads raw text, with backslash escapes, until `close`.
+    fn raw(&self, from: usize, close: char) -> String {
+        let mut text = String::new();
+        let mut chars = self.input[from..].chars();
+        while let Some(c) = chars.next() {
+            if c == close {
+                break;
+            }
+            if c == '\\' {
+                match chars.next() {
+                    Some(escaped) => text.push(escaped),
+                    None => text.push(c),
+                }
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+}
+
+fn push_char(nodes: &mut Vec<Node>, c: char) {
+    match nodes.last_mut() {
+        Some(Node::Text(text)) => text.push(c),
+        _ => nodes.push(Node::Text(c.to_string())),
+    }
+}
+
+/// Escapes `text` so that it is shown as is.
+pub fn escape(text: &str) -> String {
+    escape_chars(text, SPECIAL)
+}
+
+fn escape_chars(text: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Names shown for mentions and channel references.
+pub trait Names {
+    fn user_name(&self, _user_id: ID) -> Option<String> {
+        None
+    }
+
+    fn channel_name(&self, _channel_id: ID) -> Option<String> {
+        None
+    }
+}
+
+/// Shows IDs instead of names.
+impl Names for () {}
+
+fn user_name(names: &dyn Names, id: ID) -> String {
+    format!("@{}", names.user_name(id).unwrap_or_else(|| id.to_string()))
+}
+
+fn channel_name(names: &dyn Names, id: ID) -> String {
+    format!(
+        "#{}",
+        names.channel_name(id).unwrap_or_else(|| id.to_string())
+    )
+}
+
+/// Renders nodes back to markup.
+pub fn to_markup(nodes: &[Node]) -> String {
+    let mut output = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(&escape(text)),
+            Node::Bold(children) => {
+                let _ = write!(output, "**{}**", to_markup(children));
+            }
+            Node::Italic(children) => {
+                let _ = write!(output, "*{}*", to_markup(children));
+            }
+            Node::Code(code) => {
+                let _ = write!(output, "`{}`", escape_chars(code, &['\\', '`']));
+            }
+            Node::Link { text, url } => {
+                let _ = write!(
+                    output,
+                    "[{}]({})",
+                    to_markup(text),
+                    escape_chars(url, &['\\', ')'])
+                );
+            }
+            Node::Mention(id) => {
+                let _ = write!(output, "<@{}>", id);
+            }
+            Node::Channel(id) => {
+                let _ = write!(output, "<#{}>", id);
+            }
+        }
+    }
+    output
+}
+
+/// Renders nodes without formatting, links are followed by their URL.
+pub fn to_plain(nodes: &[Node], names: &dyn Names) -> String {
+    let mut output = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) | Node::Code(text) => output.push_str(text),
+            Node::Bold(children) | Node::Italic(children) => {
+                output.push_str(&to_plain(children, names))
+            }
+            Node::Link { text, url } => {
+                let text = to_plain(text, names);
+                if &text == url {
+                    output.push_str(url);
+                } else {
+                    let _ = write!(output, "{} ({})", text, url);
+                }
+            }
+            Node::Mention(id) => output.push_str(&user_name(names, *id)),
+            Node::Channel(id) => output.push_str(&channel_name(names, *id)),
+        }
+    }
+    output
+}
+
+/// Renders nodes with ANSI escape codes for terminals, control characters in the message are
+/// dropped so it can not change the terminal's state.
+pub fn to_ansi(nodes: &[Node], names: &dyn Names) -> String {
+    let mut output = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(&strip_control(text)),
+            Node::Bold(children) => {
+                let _ = write!(output, "\x1b[1m{}\x1b[22m", to_ansi(children, names));
+            }
+            Node::Italic(children) => {
+                let _ = write!(output, "\x1b[3m{}\x1b[23m", to_ansi(children, names));
+            }
+            Node::Code(code) => {
+                let _ = write!(output, "\x1b[36m{}\x1b[39m", strip_control(code));
+            }
+            Node::Link { text, url } => {
+                let _ = write!(
+                    output,
+                    "\x1b[4m{}\x1b[24m ({})",
+                    to_ansi(text, names),
+                    strip_control(url)
+                );
+            }
+            Node::Mention(id) => {
+                let _ = write!(
+                    output,
+                    "\x1b[1;33m{}\x1b[22;39m",
+                    strip_control(&user_name(names, *id))
+                );
+            }
+            Node::Channel(id) => {
+                let _ = write!(
+                    output,
+                    "\x1b[34m{}\x1b[39m",
+                    strip_control(&channel_name(names, *id))
+                );
+            }
+        }
+    }
+    output
+}
+
+fn strip_control(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect()
+}
+
+/// Renders nodes as HTML, links with schemes other than `http`, `https` and `mailto` are
+/// rendered as their text only.
+pub fn to_html(nodes: &[Node], names: &dyn Names) -> String {
+    let mut output = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(&escape_html(text)),
+            Node::Bold(children) => {
+                let _ = write!(output, "<strong>{}</strong>", to_html(children, names));
+            }
+            Node::Italic(children) => {
+                let _ = write!(output, "<em>{}</em>", to_html(children, names));
+            }
+            Node::Code(code) => {
+                let _ = write!(output, "<code>{}</code>", escape_html(code));
+            }
+            Node::Link { text, url } => {
+                let text = to_html(text, names);
+                let lowercase = url.trim_start().to_lowercase();
+                if ["http://", "https://", "mailto:"]
+                    .iter()
+                    .any(|scheme| lowercase.starts_with(scheme))
+                {
+                    let _ = write!(
+                        output,
+                        "<a href=\"{}\" rel=\"nofollow noopener noreferrer\">{}</a>",
+                        escape_html(url),
+                        text
+                    );
+                } else {
+                    output.push_str(&text);
+                }
+            }
+            Node::Mention(id) => {
+                let _ = write!(
+                    output,
+                    "<span class=\"mention\" data-user-id=\"{}\">{}</span>",
+                    id,
+                    escape_html(&user_name(names, *id))
+                );
+            }
+            Node::Channel(id) => {
+                let _ = write!(
+                    output,
+                    "<span class=\"channel\" data-channel-id=\"{}\">{}</span>",
+                    id,
+                    escape_html(&channel_name(names, *id))
+                );
+            }
+        }
+    }
+    output
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Composes a formatted message, all text given to it is escaped.
+#[derive(Debug, Clone, Default)]
+pub struct MessageBuilder {
+    nodes: Vec<Node>,
+}
+
+impl MessageBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        for c in text.chars() {
+            push_char(&mut self.nodes, c);
+        }
+        self
+    }
+
+    pub fn bold(self, text: &str) -> Self {
+        self.node(Node::Bold(vec![Node::Text(text.to_string())]))
+    }
+
+    pub fn italic(self, text: &str) -> Self {
+        self.node(Node::Italic(vec![Node::Text(text.to_string())]))
+    }
+
+    pub fn code(self, code: &str) -> Self {
+        self.node(Node::Code(code.to_string()))
+    }
+
+    pub fn link(self, text: &str, url: &str) -> Self {
+        self.node(Node::Link {
+            text: vec![Node::Text(text.to_string())],
+            url: url.to_string(),
+        })
+    }
+
+    pub fn mention(self, user_id: ID) -> Self {
+        self.node(Node::Mention(user_id))
+    }
+
+    pub fn channel(self, channel_id: ID) -> Self {
+        self.node(Node::Channel(channel_id))
+    }
+
+    /// Adds an already built node, for nested formatting.
+    pub fn node(mut self, node: Node) -> Self {
+        match node {
+            Node::Text(text) => return self.text(&text),
+            Node::Bold(children) | Node::Italic(children) if children.is_empty() => {}
+            node => self.nodes.push(node),
+        }
+        self
+    }
+
+    pub fn nodes(&self) -> &[Node] {
+        &self.nodes
+    }
+
+    /// The message as markup, ready to be sent.
+    pub fn build(&self) -> String {
+        to_markup(&self.nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Node {
+        Node::Text(text.to_string())
+    }
+
+    #[test]
+    fn parses_nested_markup() {
+        assert_eq!(
+            parse("*a **b***"),
+            vec![Node::Italic(vec![text("a "), Node::Bold(vec![text("b")])])]
+        );
+        assert_eq!(
+            parse("[**bold** _it_](https://example.com/a\\)b)"),
+            vec![Node::Link {
+                text: vec![
+                    Node::Bold(vec![text("bold")]),
+                    text(" "),
+                    Node::Italic(vec![text("it")]),
+                ],
+                url: "https://example.com/a)b".to_string(),
+            }]
+        );
+        let id = ID::new_v4();
+        assert_eq!(
+            parse(&format!("hi **<@{}>** in <#{}>", id, id)),
+            vec![
+                text("hi "),
+                Node::Bold(vec![Node::Mention(id)]),
+                text(" in "),
+                Node::Channel(id),
+            ]
+        );
+        assert_eq!(parse("`*a*`"), vec![Node::Code("*a*".to_string())]);
+    }
+
+    #[test]
+    fn keeps_unclosed_markup_as_text() {
+        for input in &["[a", "[a](b", "*a", "**a", "_a", "`a", "<@a>", "**", "a**b"] {
+            assert_eq!(parse(input), vec![text(input)], "{}", input);
+        }
+        assert_eq!(parse("snake_case_name"), vec![text("snake_case_name")]);
+        assert_eq!(parse("\\*a\\*"), vec![text("*a*")]);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let input = format!("{}a{}", "[".repeat(20), "](x)".repeat(20));
+        let mut nodes = parse(&input);
+        let mut depth = 0;
+        while let Some(Node::Link { text, .. }) = nodes.first() {
+            nodes = text.clone();
+            depth += 1;
+        }
+        assert_eq!(depth, MAX_DEPTH);
+    }
+
+    #[test]
+    fn parses_long_unclosed_input_in_linear_time() {
+        let steps = |input: &str| {
+            let mut parser = Parser::new(input);
+            let nodes = parser.nodes(0, None, 0);
+            (nodes, parser.steps)
+        };
+        for pattern in &["[", "*", "**", "_", "<@", "*_[**", "[*_", "**[_*"] {
+            let short = pattern.repeat(500);
+            let long = pattern.repeat(5_000);
+            let (nodes, short_steps) = steps(&short);
+            let (_, long_steps) = steps(&long);
+            // Quadratic parsing would take about 100 times the steps for 10 times the input.
+            assert!(
+                long_steps <= 12 * short_steps,
+                "{:?}: {} steps for {} bytes, {} for {}",
+                pattern,
+                short_steps,
+                short.len(),
+                long_steps,
+                long.len()
+            );
+            if pattern.len() <= 2 {
+                assert_eq!(nodes, vec![text(&short)], "{:?}", pattern);
+            }
+        }
+    }
+}
@@ -0,0 +1,491 @@
+//! Handles binding IDs to a client.
+//!
+//! A handle holds a reference to a [`WicrsApi`] client and the IDs of a hub, channel, member or
+//! message, so follow-up calls do not need the IDs again:
+//! `client.hub(hub_id).channel(channel_id).send("hi".to_string())`. Handles are cheap to copy,
+//! nothing is fetched until a method is called.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    api::WicrsApi,
+    error::Result,
+    http::HttpClient,
+    protocol::{
+        Channel, ChannelPermission, HttpChannelUpdate, HttpHubUpdate, HttpMemberStatus, Hub,
+        HubMember, HubPermission, Message, PermissionSetting, ID,
+    },
+};
+
+macro_rules! handle {
+    ($name:ident { $($field:ident),* }) => {
+        pub struct $name<'a, A: ?Sized = HttpClient> {
+            api: &'a A,
+            $($field: ID,)*
+        }
+
+        impl<A: ?Sized> Clone for $name<'_, A> {
+            fn clone(&self) -> Self {
+                *self
+            }
+        }
+
+        impl<A: ?Sized> Copy for $name<'_, A> {}
+
+        impl<A: ?Sized> fmt::Debug for $name<'_, A> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.debug_struct(stringify!($name))
+                    $(.field(stringify!($field), &self.$field))*
+                    .finish()
+            }
+        }
+
+        impl<'a, A: ?Sized> $name<'a, A> {
+            pub fn api(&self) -> &'a A {
+                self.api
+            }
+        }
+    };
+}
+
+handle!(HubHandle { hub });
+handle!(ChannelHandle { hub, channel });
+handle!(MemberHandle { hub, member });
+handle!(MessageHandle {
+    hub,
+    channel,
+    message
+});
+
+impl<'a, A: WicrsApi + ?Sized> HubHandle<'a, A> {
+    pub fn new(api: &'a A, hub: ID) -> Self {
+        Self { api, hub }
+    }
+
+    /// Creates a hub and returns its handle.
+    pub async fn create(api: &'a A, name: String) -> Result<HubHandle<'a, A>> {
+        Ok(Self::new(api, api.hub_create(name).await?))
+    }
+
+    pub fn id(&self) -> ID {
+        self.hub
+    }
+
+    pub fn channel(&self, channel: ID) -> ChannelHandle<'a, A> {
+        ChannelHandle {
+            api: self.api,
+            hub: self.hub,
+            channel,
+        }
+    }
+
+    pub fn member(&self, member: ID) -> MemberHandle<'a, A> {
+        MemberHandle {
+            api: self.api,
+            hub: self.hub,
+            member,
+        }
+    }
+
+    /// Handle for the user the client is making requests as.
+    pub fn me(&self) -> MemberHandle<'a, A> {
+        self.member(self.api.user_id())
+    }
+
+    pub async fn get(&self) -> Result<Hub> {
+        self.api.hub_get(self.hub).await
+    }
+
+    /// Handles for the channels of the hub.
+    pub async fn channels(&self) -> Result<Vec<ChannelHandle<'a, A>>> {
+        Ok(self
+            .get()
+            .await?
+            .channels
+            .keys()
+            .map(|channel| self.channel(*channel))
+            .collect())
+    }
+
+    /// Handles for the members of the hub.
+    pub async fn members(&self) -> Result<Vec<MemberHandle<'a, A>>> {
+        Ok(self
+            .get()
+            .await?
+            .members
+            .keys()
+            .map(|member| self.member(*member))
+            .collect())
+    }
+
+    pub async fn update(
+        &self,
+        name: Option<String>,
+        description: Option<String>,
+        default_group: Option<ID>,
+    ) -> Result<HttpHubUpdate> {
+        self.api
+            .hub_update(self.hub, name, description, default_group)
+            .await
+    }
+
+    pub async fn delete(self) -> Result<()> {
+        self.api.hub_delete(self.hub).await
+    }
+
+    pub async fn join(&self) -> Result<()> {
+        self.api.hub_join(self.hub).await
+    }
+
+    pub async fn leave(&self) -> Result<()> {
+        self.api.hub_leave(self.hub).await
+    }
+
+    pub async fn create_channel(&self, name: String) -> Result<ChannelHandle<'a, A>> {
+        Ok(self.channel(self.api.channel_create(self.hub, name).await?))
+    }
+}
+
+impl HttpClient {
+    /// Handle for the hub with the given ID.
+    pub fn hub(&self, hub: ID) -> HubHandle<'_> {
+        HubHandle::new(self, hub)
+    }
+}
+
+impl<'a, A: WicrsApi + ?Sized> ChannelHandle<'a, A> {
+    pub fn id(&self) -> ID {
+        self.channel
+    }
+
+    pub fn hub(&self) -> HubHandle<'a, A> {
+        HubHandle::new(self.api, self.hub)
+    }
+
+    pub fn message(&self, message: ID) -> MessageHandle<'a, A> {
+        MessageHandle {
+            api: self.api,
+            hub: self.hub,
+            channel: self.channel,
+            message,
+        }
+    }
+
+    pub async fn get(&self) -> Result<Channel> {
+        self.api.channel_get(self.hub, self.channel).await
+    }
+
+    pub async fn update(&self, update: HttpChannelUpdate) -> Result<HttpChannelUpdate> {
+        self.api
+            .channel_update(self.hub, self.channel, update)
+            .await
+    }
+
+    pub async fn delete(self) -> Result<()> {
+        self.api.channel_delete(self.hub, self.channel).await
+    }
+
+    pub async fn send(&self, message: String) -> Result<MessageHandle<'a, A>> {
+        Ok(self.message(
+            self.api
+                .message_send(self.hub, self.channel, message)
+                .await?,
+        ))
+    }
+
+    /// The last `max` messages of the channel.
+    pub async fn history(&self, max: usize) -> Result<Vec<Message>> {
+        self.api
+            .messages_get_last(self.hub, self.channel, max)
+            .await
+    }
+
+    pub async fn history_after(&self, from: ID, max: usize) -> Result<Vec<Message>> {
+        self.api
+            .messages_get_after(self.hub, self.channel, from, max)
+            .await
+    }
+
+    pub async fn history_before(&self, to: ID, max: usize) -> Result<Vec<Message>> {
+        self.api
+            .messages_get_before(self.hub, self.channel, to, max)
+            .await
+    }
+
+    pub async fn history_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        max: usize,
+        new_to_old: bool,
+    ) -> Result<Vec<Message>> {
+        self.api
+            .messages_get_between(self.hub, self.channel, from, to, max, new_to_old)
+            .await
+    }
+}
+
+impl<'a, A: WicrsApi + ?Sized> MemberHandle<'a, A> {
+    pub fn id(&self) -> ID {
+        self.member
+    }
+
+    pub fn hub(&self) -> HubHandle<'a, A> {
+        HubHandle::new(self.api, self.hub)
+    }
+
+    pub async fn get(&self) -> Result<HubMember> {
+        self.api.member_get(self.hub, self.member).await
+    }
+
+    pub async fn status(&self) -> Result<HttpMemberStatus> {
+        self.api.member_status(self.hub, self.member).await
+    }
+
+    pub async fn kick(&self) -> Result<()> {
+        self.api.member_kick(self.hub, self.member).await
+    }
+
+    pub async fn ban(&self) -> Result<()> {
+        self.api.member_ban(self.hub, self.member).await
+    }
+
+    pub async fn unban(&self) -> Result<()> {
+        self.api.member_unban(self.hub, self.member).await
+    }
+
+    pub async fn mute(&self) -> Result<()> {
+        self.api.member_mute(self.hub, self.member).await
+    }
+
+    pub async fn unmute(&self) -> Result<()> {
+        self.api.member_unmute(self.hub, self.member).await
+    }
+
+    pub async fn hub_permission(&self, permission: HubPermission) -> Result<PermissionSetting> {
+        self.api
+            .member_get_hub_permission(self.hub, self.member, permission)
+            .await
+    }
+
+    pub async fn set_hub_permission(
+        &self,
+        permission: HubPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        self.api
+            .member_set_hub_permission(self.hub, self.member, permission, setting)
+            .await
+    }
+
+    pub async fn channel_permission(
+        &self,
+        channel: ID,
+        permission: ChannelPermission,
+    ) -> Result<PermissionSetting> {
+        self.api
+            .member_get_channel_permission(self.hub, self.member, channel, permission)
+            .await
+    }
+
+    pub async fn set_channel_permission(
+        &self,
+        channel: ID,
+        permission: ChannelPermission,
+        setting: PermissionSetting,
+    ) -> Result<()> {
+        self.api
+            .member_set_channel_permission(self.hub, self.member, channel, permission, setting)
+            .await
+    }
+}
+
+impl<'a, A: WicrsApi + ?Sized> MessageHandle<'a, A> {
+    pub fn id(&self) -> ID {
+        self.message
+    }
+
+    pub fn hub(&self) -> HubHandle<'a, A> {
+        HubHandle::new(self.api, self.hub)
+    }
+
+    pub fn channel(&self) -> ChannelHandle<'a, A> {
+        self.hub().channel(self.channel)
+    }
+
+    pub async fn get(&self) -> Result<Message> {
+        self.api
+            .message_get(self.hub, self.channel, self.message)
+            .await
+    }
+
+    /// Handle for the sender of the message, this fetches the message.
+    pub async fn sender(&self) -> Result<MemberHandle<'a, A>> {
+        Ok(self.hub().member(self.get().await?.sender))
+    }
+
+    /// The next `max` messages after this one.
+    pub async fn after(&self, max: usize) -> Result<Vec<Message>> {
+        self.channel().history_after(self.message, max).await
+    }
+
+    /// The `max` messages before this one.
+    pub async fn before(&self, max: usize) -> Result<Vec<Message>> {
+        self.channel().history_before(self.message, max).await
+    }
+}
+
+#[cfg(all(test, feature = "use-tokio"))]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::fake::{Call, FakeApi};
+
+    fn id(id: ID) -> Value {
+        json!(id)
+    }
+
+    fn call(method: &'static str, args: Vec<Value>) -> Call {
+        Call { method, args }
+    }
+
+    #[tokio::test]
+    async fn hub_and_channel_handles_pass_their_ids() {
+        let api = FakeApi::new(ID::new_v4());
+        let (hub, channel, created, sent) =
+            (ID::new_v4(), ID::new_v4(), ID::new_v4(), ID::new_v4());
+        api.respond("channel_create", created).unwrap();
+        api.respond("message_send", sent).unwrap();
+        api.respond("messages_get_last", Vec::<Message>::new())
+            .unwrap();
+        api.respond("messages_get_after", Vec::<Message>::new())
+            .unwrap();
+        api.respond("messages_get_before", Vec::<Message>::new())
+            .unwrap();
+        let handle = HubHandle::new(&api, hub);
+        handle.join().await.unwrap();
+        let new_channel = handle.create_channel("news".to_string()).await.unwrap();
+        assert_eq!((new_channel.hub().id(), new_channel.id()), (hub, created));
+
+        let channel = handle.channel(channel);
+        let message = channel.send("hi".to_string()).await.unwrap();
+        assert_eq!(
+            (message.hub().id(), message.channel().id(), message.id()),
+            (hub, channel.id(), sent)
+        );
+        channel.history(5).await.unwrap();
+        message.after(3).await.unwrap();
+        message.before(4).await.unwrap();
+        channel.delete().await.unwrap();
+
+        let channel = channel.id();
+        assert_eq!(
+            api.calls(),
+            [
+                call("hub_join", vec![id(hub)]),
+                call("channel_create", vec![id(hub), json!("news")]),
+                call("message_send", vec![id(hub), id(channel), json!("hi")]),
+                call("messages_get_last", vec![id(hub), id(channel), json!(5)]),
+                call(
+                    "messages_get_after",
+                    vec![id(hub), id(channel), id(sent), json!(3)]
+                ),
+                call(
+                    "messages_get_before",
+                    vec![id(hub), id(channel), id(sent), json!(4)]
+                ),
+                call("channel_delete", vec![id(hub), id(channel)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn member_handles_pass_their_ids() {
+        let user = ID::new_v4();
+        let api = FakeApi::new(user);
+        let (hub, member, channel) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        api.respond("member_get_channel_permission", Some(true))
+            .unwrap();
+        let handle = HubHandle::new(&api, hub).member(member);
+        handle.kick().await.unwrap();
+        handle
+            .set_hub_permission(HubPermission::Mute, Some(false))
+            .await
+            .unwrap();
+        let setting = handle
+            .channel_permission(channel, ChannelPermission::Read)
+            .await
+            .unwrap();
+        assert_eq!(setting, Some(true));
+        handle
+            .set_channel_permission(channel, ChannelPermission::Write, None)
+            .await
+            .unwrap();
+        HubHandle::new(&api, hub).me().unmute().await.unwrap();
+
+        assert_eq!(
+            api.calls(),
+            [
+                call("member_kick", vec![id(hub), id(member)]),
+                call(
+                    "member_set_hub_permission",
+                    vec![
+                        id(hub),
+                        id(member),
+                        json!(HubPermission::Mute),
+                        json!(false)
+                    ]
+                ),
+                call(
+                    "member_get_channel_permission",
+                    vec![
+                        id(hub),
+                        id(member),
+                        id(channel),
+                        json!(ChannelPermission::Read)
+                    ]
+                ),
+                call(
+                    "member_set_channel_permission",
+                    vec![
+                        id(hub),
+                        id(member),
+                        id(channel),
+                        json!(ChannelPermission::Write),
+                        Value::Null
+                    ]
+                ),
+                call("member_unmute", vec![id(hub), id(user)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn message_handles_pass_their_ids() {
+        let api = FakeApi::new(ID::new_v4());
+        let (hub, channel, message, sender) =
+            (ID::new_v4(), ID::new_v4(), ID::new_v4(), ID::new_v4());
+        api.respond(
+            "message_get",
+            Message {
+                id: message,
+                hub_id: hub,
+                channel_id: channel,
+                sender,
+                created: Utc::now(),
+                content: "hi".to_string(),
+            },
+        )
+        .unwrap();
+        let handle = HubHandle::new(&api, hub).channel(channel).message(message);
+        let member = handle.sender().await.unwrap();
+        assert_eq!((member.hub().id(), member.id()), (hub, sender));
+        assert_eq!(
+            api.calls(),
+            [call("message_get", vec![id(hub), id(channel), id(message)])]
+        );
+    }
+}
@@ -1,23 +1,51 @@
-use crate::error::Result;
+use crate::{
+    error::Result,
+    middleware::{Chain, Middleware, Next},
+    version::{Feature, ProtocolVersion, VERSION_HEADER},
+};
 use chrono::{DateTime, Utc};
 use reqwest::{
     header::{HeaderMap, HeaderValue},
-    Body, Client, ClientBuilder, Method, Url,
+    Body, Client, ClientBuilder, Method, Request, RequestBuilder, Url,
 };
 use serde::{de::DeserializeOwned, Serialize};
-use std::fmt::Display;
+use std::{fmt::Display, future::Future, sync::RwLock, time::Instant};
 
-use wicrs_server::prelude::{
+use crate::protocol::{
     Channel, ChannelPermission, HttpChannelUpdate, HttpHubUpdate, HttpMemberStatus,
     HttpMessagesAfterQuery, HttpMessagesBeforeQuery, HttpMessagesBetweenQuery, HttpSetPermission,
     Hub, HubMember, HubPermission, Message, PermissionSetting, Response, ID, HttpLastMessagesQuery,
 };
 
+/// Replaces every UUID segment of an API path with `{id}`, the IDs are returned in order.
+pub fn route_template(path: &str) -> (String, Vec<ID>) {
+    let mut ids = Vec::new();
+    let route = path
+        .split('/')
+        .map(|segment| match segment.parse::<ID>() {
+            Ok(id) => {
+                ids.push(id);
+                "{id}"
+            }
+            Err(_) => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    (route, ids)
+}
+
 #[derive(Debug)]
 pub struct HttpClient {
     pub server_api_url: String,
     pub user_id: ID,
     client: Client,
+    middleware: Chain,
+    server_version: RwLock<Option<ProtocolVersion>>,
+    #[cfg(feature = "use-tokio")]
+    rate_limiter: Option<std::sync::Arc<crate::rate_limit::RateLimiter>>,
+    /// Held while asking the server for its version, so concurrent calls send one request.
+    #[cfg(feature = "use-tokio")]
+    negotiating: tokio::sync::Mutex<()>,
 }
 
 impl HttpClient {
@@ -28,6 +56,10 @@ impl HttpClient {
             "authorization",
             HeaderValue::from_str(&auth_string).unwrap(),
         );
+        headers.insert(
+            VERSION_HEADER,
+            HeaderValue::from_str(&ProtocolVersion::CURRENT.to_string()).unwrap(),
+        );
         #[cfg(feature = "use-tokio")]
         let client = ClientBuilder::new()
             .default_headers(headers)
@@ -39,9 +71,32 @@ impl HttpClient {
             server_api_url,
             user_id,
             client,
+            middleware: Chain::default(),
+            server_version: RwLock::new(None),
+            #[cfg(feature = "use-tokio")]
+            rate_limiter: None,
+            #[cfg(feature = "use-tokio")]
+            negotiating: tokio::sync::Mutex::new(()),
         })
     }
 
+    /// Limits the requests sent by this client, the limiter can be shared with other clients.
+    #[cfg(feature = "use-tokio")]
+    pub fn with_rate_limiter(
+        mut self,
+        rate_limiter: std::sync::Arc<crate::rate_limit::RateLimiter>,
+    ) -> Self {
+        self.rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Adds a middleware run for every request after the ones already added, see
+    /// [`crate::middleware`].
+    pub fn with_middleware<M: Middleware>(mut self, middleware: M) -> Self {
+        self.middleware.0.push(std::sync::Arc::new(middleware));
+        self
+    }
+
     pub async fn request<S, R>(&self, method: Method, url: S) -> Result<R>
     where
         S: Display,
@@ -54,16 +109,7 @@ impl HttpClient {
                 Url::parse(&format!("{}{}", self.server_api_url, url))?,
             )
             .build()?;
-        let response = self
-            .client
-            .execute(request)
-            .await?
-            .json::<Response<R>>()
-            .await?;
-        match response {
-            Response::Success(result) => Ok(result),
-            Response::Error(error) => Err(error.into()),
-        }
+        self.execute(request).await
     }
 
     pub async fn request_norec<S>(&self, method: Method, url: S) -> Result<()>
@@ -89,16 +135,7 @@ impl HttpClient {
             .body(data)
             .header("content-type", HeaderValue::from_static("application/json"))
             .build()?;
-        let response = self
-            .client
-            .execute(request)
-            .await?
-            .json::<Response<R>>()
-            .await?;
-        match response {
-            Response::Success(result) => Ok(result),
-            Response::Error(error) => Err(error.into()),
-        }
+        self.execute(request).await
     }
 
     pub async fn send_json<S, D, R>(&self, method: Method, url: S, data: D) -> Result<R>
@@ -118,6 +155,129 @@ impl HttpClient {
         self.send_json::<_, _, String>(method, url, data).await?;
         Ok(())
     }
+
+    /// Builder for a request to an API path, for requests the helpers above can not make.
+    pub(crate) fn request_builder<S: Display>(
+        &self,
+        method: Method,
+        url: S,
+    ) -> Result<RequestBuilder> {
+        Ok(self.client.request(
+            method,
+            Url::parse(&format!("{}{}", self.server_api_url, url))?,
+        ))
+    }
+
+    /// Sends a request without reading the response, for responses that are not JSON. Like
+    /// every request it goes through [`HttpClient::observe`], so it is rate limited and traced.
+    pub(crate) async fn execute_raw(&self, request: Request) -> Result<reqwest::Response> {
+        self.observe(request, |response| async move { Ok(response) })
+            .await
+    }
+
+    /// Sends a request through the middleware, checking the server version on the first
+    /// response.
+    async fn send_request(&self, request: Request) -> Result<reqwest::Response> {
+        let response = Next::new(&self.client, &self.middleware.0)
+            .run(request)
+            .await?;
+        if self.server_version().is_none() {
+            let version = ProtocolVersion::from_headers(response.headers())?.check_compatible()?;
+            *self.server_version.write().unwrap() = Some(version);
+        }
+        Ok(response)
+    }
+
+    /// Protocol version of the server, known after the first response.
+    pub fn server_version(&self) -> Option<ProtocolVersion> {
+        *self.server_version.read().unwrap()
+    }
+
+    /// Protocol version of the server, asking the server if no request has been made yet. Fails
+    /// with [`crate::Error::IncompatibleServer`] if the client does not support it.
+    pub async fn negotiate(&self) -> Result<ProtocolVersion> {
+        if let Some(version) = self.server_version() {
+            return Ok(version);
+        }
+        #[cfg(feature = "use-tokio")]
+        let _negotiating = self.negotiating.lock().await;
+        if let Some(version) = self.server_version() {
+            return Ok(version);
+        }
+        let request = self.request_builder(Method::GET, "/version")?.build()?;
+        self.execute_raw(request).await?;
+        Ok(self.server_version().unwrap_or(ProtocolVersion::LEGACY))
+    }
+
+    /// Fails with [`crate::Error::Unsupported`] if the server does not have a feature.
+    pub async fn require(&self, feature: Feature) -> Result<()> {
+        self.negotiate().await?.require(feature)
+    }
+
+    #[cfg(feature = "use-tokio")]
+    async fn rate_limit(&self) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await;
+        }
+    }
+
+    /// Route of an API URL with the IDs replaced by `{id}`, along with the IDs.
+    pub(crate) fn route(&self, url: &Url) -> (String, Vec<ID>) {
+        let base_path = Url::parse(&self.server_api_url)
+            .map(|base| base.path().trim_end_matches('/').to_string())
+            .unwrap_or_default();
+        let path = url.path();
+        route_template(path.strip_prefix(&base_path).unwrap_or(path))
+    }
+
+    pub(crate) async fn execute<R>(&self, request: Request) -> Result<R>
+    where
+        R: DeserializeOwned,
+    {
+        self.observe(request, |response| async move {
+            match response.json::<Response<R>>().await? {
+                Response::Success(result) => Ok(result),
+                Response::Error(error) => Err(error.into()),
+            }
+        })
+        .await
+    }
+
+    /// Sends a request and reads its response with `read` in an `http_request` span, recording
+    /// the latency and outcome in the metrics. Waiting for the rate limiter is not timed.
+    pub(crate) async fn observe<T, F, Fut>(&self, request: Request, read: F) -> Result<T>
+    where
+        F: FnOnce(reqwest::Response) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        #[cfg(feature = "use-tokio")]
+        self.rate_limit().await;
+        let start = Instant::now();
+        let method = request.method().clone();
+        #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+        let (route, ids) = self.route(request.url());
+        let sent = async {
+            let response = self.send_request(request).await?;
+            #[cfg(feature = "tracing")]
+            crate::trace::record_status(response.status().as_u16());
+            read(response).await
+        };
+        #[cfg(feature = "tracing")]
+        let result = {
+            use tracing::Instrument;
+            let span = crate::trace::http_span(&method, &route, &ids);
+            crate::trace::timed(sent).instrument(span).await
+        };
+        #[cfg(not(feature = "tracing"))]
+        let result = sent.await;
+        crate::metrics::http_request(
+            method.as_str(),
+            &route,
+            start.elapsed(),
+            result.as_ref().err(),
+        );
+        result
+    }
 }
 
 impl HttpClient {
@@ -354,21 +514,29 @@ impl HttpClient {
         .await
     }
 
+    /// Servers older than [`Feature::ChannelInPermissionRoute`] have no channel in the route,
+    /// `channel` is not sent to them.
     pub async fn member_set_channel_permission(
         &self,
         hub: ID,
         member: ID,
+        channel: ID,
         permission: ChannelPermission,
         setting: PermissionSetting,
     ) -> Result<()> {
-        self.send_json_norec(
-            Method::PUT,
+        let url = if self
+            .negotiate()
+            .await?
+            .supports(Feature::ChannelInPermissionRoute)
+        {
             format!(
-                "/member/{}/{}/channel_permission/{}",
-                hub, member, permission
-            ),
-            HttpSetPermission { setting },
-        )
-        .await
+                "/member/{}/{}/channel_permission/{}/{}",
+                hub, member, channel, permission
+            )
+        } else {
+            format!("/member/{}/{}/channel_permission/{}", hub, member, permission)
+        };
+        self.send_json_norec(Method::PUT, url, HttpSetPermission { setting })
+            .await
     }
 }
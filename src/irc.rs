@@ -0,0 +1,595 @@
+//! IRC gateway that lets regular IRC clients talk to a WICRS server.
+//!
+//! Hubs are exposed as `#<hub_id>` and channels as `#<hub_id>/<channel_id>`. Clients authenticate
+//! by sending their WICRS user ID with `PASS` (or as their nickname if no password is given).
+
+use std::{fmt::Display, net::SocketAddr, str::FromStr, sync::Arc};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+};
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::{WsHubUpdateType, WsServerMessage, ID},
+    websocket::asyncws::WebsocketClient,
+};
+
+#[derive(Debug, Clone)]
+pub struct GatewayConfig {
+    pub listen: SocketAddr,
+    pub server_api_url: String,
+    pub websocket_url: String,
+    pub server_name: String,
+}
+
+impl GatewayConfig {
+    pub fn new(listen: SocketAddr, server_api_url: String, websocket_url: String) -> Self {
+        Self {
+            listen,
+            server_api_url,
+            websocket_url,
+            server_name: "wicrs".to_string(),
+        }
+    }
+}
+
+/// Accepts IRC connections on the configured address until accepting fails, returning that
+/// error. A client that fails only ends its own connection, the error is logged with `tracing`
+/// when the feature is enabled.
+pub async fn run(config: GatewayConfig) -> Result<()> {
+    let listener = TcpListener::bind(config.listen).await?;
+    let config = Arc::new(config);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let config = Arc::clone(&config);
+        tokio::spawn(async move {
+            if let Err(error) = serve(stream, config).await {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(peer = %peer, error = %error, "irc client failed");
+                #[cfg(not(feature = "tracing"))]
+                let _ = (peer, error);
+            }
+        });
+    }
+}
+
+/// A single line of the IRC protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IrcMessage {
+    pub prefix: Option<String>,
+    pub command: String,
+    pub params: Vec<String>,
+}
+
+impl IrcMessage {
+    pub fn new<C: Into<String>>(prefix: Option<String>, command: C, params: Vec<String>) -> Self {
+        Self {
+            prefix,
+            command: command.into(),
+            params,
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut rest = line.trim_end_matches(&['\r', '\n'][..]).trim_start();
+        let prefix = if let Some(stripped) = rest.strip_prefix(':') {
+            let (prefix, remainder) = stripped.split_once(' ')?;
+            rest = remainder.trim_start();
+            Some(prefix.to_string())
+        } else {
+            None
+        };
+        let (command, mut rest) = match rest.split_once(' ') {
+            Some((command, remainder)) => (command, remainder),
+            None => (rest, ""),
+        };
+        if command.is_empty() {
+            return None;
+        }
+        let mut params = Vec::new();
+        loop {
+            rest = rest.trim_start();
+            if rest.is_empty() {
+                break;
+            }
+            if let Some(trailing) = rest.strip_prefix(':') {
+                params.push(trailing.to_string());
+                break;
+            }
+            match rest.split_once(' ') {
+                Some((param, remainder)) => {
+                    params.push(param.to_string());
+                    rest = remainder;
+                }
+                None => {
+                    params.push(rest.to_string());
+                    break;
+                }
+            }
+        }
+        Some(Self {
+            prefix,
+            command: command.to_ascii_uppercase(),
+            params,
+        })
+    }
+}
+
+/// Replaces the characters that would end an IRC line, so text from the server can not inject
+/// commands.
+pub fn sanitize(text: &str) -> String {
+    text.replace(&['\r', '\n', '\0'][..], " ")
+}
+
+/// Writes the message as a single line, line breaks and NUL in any part are replaced with spaces.
+impl Display for IrcMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{} ", sanitize(prefix))?;
+        }
+        write!(f, "{}", sanitize(&self.command))?;
+        if let Some((last, params)) = self.params.split_last() {
+            for param in params {
+                write!(f, " {}", sanitize(param))?;
+            }
+            let last = sanitize(last);
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(f, " :{}", last)?;
+            } else {
+                write!(f, " {}", last)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An IRC channel name mapped onto a WICRS hub or channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum IrcTarget {
+    Hub(ID),
+    Channel(ID, ID),
+}
+
+impl IrcTarget {
+    pub fn hub_id(&self) -> ID {
+        match self {
+            IrcTarget::Hub(hub_id) | IrcTarget::Channel(hub_id, _) => *hub_id,
+        }
+    }
+}
+
+impl Display for IrcTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IrcTarget::Hub(hub_id) => write!(f, "#{}", hub_id),
+            IrcTarget::Channel(hub_id, channel_id) => write!(f, "#{}/{}", hub_id, channel_id),
+        }
+    }
+}
+
+impl FromStr for IrcTarget {
+    type Err = ();
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let name = s.strip_prefix('#').ok_or(())?;
+        match name.split_once('/') {
+            Some((hub, channel)) => Ok(IrcTarget::Channel(
+                hub.parse().map_err(|_| ())?,
+                channel.parse().map_err(|_| ())?,
+            )),
+            None => Ok(IrcTarget::Hub(name.parse().map_err(|_| ())?)),
+        }
+    }
+}
+
+struct Session {
+    config: Arc<GatewayConfig>,
+    out: UnboundedSender<String>,
+    nick: String,
+    user_id: ID,
+}
+
+impl Session {
+    fn send(&self, message: IrcMessage) -> bool {
+        self.out.send(message.to_string()).is_ok()
+    }
+
+    fn numeric(&self, code: u16, params: Vec<String>) {
+        let mut all = vec![self.nick.clone()];
+        all.extend(params);
+        self.send(IrcMessage::new(
+            Some(self.config.server_name.clone()),
+            format!("{:03}", code),
+            all,
+        ));
+    }
+
+    fn own_prefix(&self) -> String {
+        format!("{}!{}@{}", self.nick, self.user_id, self.config.server_name)
+    }
+
+    fn welcome(&self) {
+        self.numeric(
+            1,
+            vec![format!(
+                "Welcome to the WICRS IRC gateway {}",
+                self.own_prefix()
+            )],
+        );
+        self.numeric(2, vec![format!("Your host is {}", self.config.server_name)]);
+        self.numeric(3, vec!["This server relays a WICRS server".to_string()]);
+        self.numeric(
+            4,
+            vec![
+                self.config.server_name.clone(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ],
+        );
+        self.numeric(422, vec!["MOTD File is missing".to_string()]);
+    }
+}
+
+/// Serves a single IRC client over `stream` until it quits or disconnects.
+pub async fn serve<S>(stream: S, config: Arc<GatewayConfig>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (read, mut write) = tokio::io::split(stream);
+    let mut lines = BufReader::new(read).lines();
+    let (out, mut out_recv) = unbounded_channel::<String>();
+    let writer = tokio::spawn(async move {
+        while let Some(line) = out_recv.recv().await {
+            write.write_all(line.as_bytes()).await?;
+            write.write_all(b"\r\n").await?;
+            write.flush().await?;
+        }
+        Ok::<_, std::io::Error>(())
+    });
+
+    let mut pass = None;
+    let mut nick = None;
+    let mut user = false;
+    while nick.is_none() || !user {
+        let line = match lines.next_line().await? {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        let message = match IrcMessage::parse(&line) {
+            Some(message) => message,
+            None => continue,
+        };
+        match message.command.as_str() {
+            "PASS" => pass = message.params.first().cloned(),
+            "NICK" => nick = message.params.first().cloned(),
+            "USER" => user = true,
+            "PING" => {
+                let _ = out.send(
+                    IrcMessage::new(
+                        Some(config.server_name.clone()),
+                        "PONG",
+                        message.params.clone(),
+                    )
+                    .to_string(),
+                );
+            }
+            "QUIT" => return Ok(()),
+            _ => {}
+        }
+    }
+    let nick = nick.unwrap_or_default();
+    let user_id = match pass.as_deref().unwrap_or(&nick).parse::<ID>() {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            let _ = out.send(
+                IrcMessage::new(
+                    Some(config.server_name.clone()),
+                    "464",
+                    vec![nick, "Password must be a WICRS user ID".to_string()],
+                )
+                .to_string(),
+            );
+            drop(out);
+            let _ = writer.await;
+            return Ok(());
+        }
+    };
+
+    let session = Session {
+        config: Arc::clone(&config),
+        out: out.clone(),
+        nick,
+        user_id,
+    };
+    let http = HttpClient::new(user_id, config.server_api_url.clone())?;
+    let ws = WebsocketClient::new(user_id, &config.websocket_url).await?;
+    session.welcome();
+
+    let relay_out = out.clone();
+    let server_name = config.server_name.clone();
+    let ws_loop = tokio::spawn(Arc::clone(&ws).start_loop(move |client, message| {
+        relay(&relay_out, &server_name, client.user_id, message)
+    }));
+    ws.wait_for_loop().await;
+
+    let result = command_loop(&session, &http, &ws, &mut lines).await;
+    ws_loop.abort();
+    drop(session);
+    drop(out);
+    let _ = writer.await;
+    result
+}
+
+async fn command_loop<R>(
+    session: &Session,
+    http: &HttpClient,
+    ws: &WebsocketClient,
+    lines: &mut tokio::io::Lines<BufReader<R>>,
+) -> Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    while let Some(line) = lines.next_line().await? {
+        let message = match IrcMessage::parse(&line) {
+            Some(message) => message,
+            None => continue,
+        };
+        match message.command.as_str() {
+            "PING" => {
+                session.send(IrcMessage::new(
+                    Some(session.config.server_name.clone()),
+                    "PONG",
+                    message.params,
+                ));
+            }
+            "JOIN" => {
+                for name in message.params.first().map_or("", |p| p.as_str()).split(',') {
+                    join(session, http, ws, name).await;
+                }
+            }
+            "PART" => {
+                for name in message.params.first().map_or("", |p| p.as_str()).split(',') {
+                    part(session, http, ws, name).await;
+                }
+            }
+            "PRIVMSG" => {
+                if let [target, text, ..] = message.params.as_slice() {
+                    privmsg(session, ws, target, text).await;
+                } else {
+                    session.numeric(412, vec!["No text to send".to_string()]);
+                }
+            }
+            "QUIT" => break,
+            "CAP" | "MODE" | "WHO" | "USER" | "PASS" | "NICK" | "PONG" => {}
+            other => session.numeric(421, vec![other.to_string(), "Unknown command".to_string()]),
+        }
+    }
+    Ok(())
+}
+
+async fn join(session: &Session, http: &HttpClient, ws: &WebsocketClient, name: &str) {
+    let target = match name.parse::<IrcTarget>() {
+        Ok(target) => target,
+        Err(_) => {
+            session.numeric(403, vec![name.to_string(), "No such channel".to_string()]);
+            return;
+        }
+    };
+    // Joining a hub the user is already a member of fails, so the join only counts as failed if
+    // the user is not a member afterwards.
+    if let Err(error) = http.hub_join(target.hub_id()).await {
+        let status = http.member_status(target.hub_id(), session.user_id).await;
+        if !matches!(status, Ok(status) if status.member) {
+            session.numeric(403, vec![name.to_string(), error.to_string()]);
+            return;
+        }
+    }
+    let subscribed = match target {
+        IrcTarget::Hub(hub_id) => ws.subscribe_hub(hub_id).await,
+        IrcTarget::Channel(hub_id, channel_id) => ws.subscribe_channel(hub_id, channel_id).await,
+    };
+    if let Err(error) = subscribed {
+        session.numeric(403, vec![name.to_string(), error.to_string()]);
+        return;
+    }
+    session.send(IrcMessage::new(
+        Some(session.own_prefix()),
+        "JOIN",
+        vec![target.to_string()],
+    ));
+    let topic = match target {
+        IrcTarget::Hub(hub_id) => http.hub_get(hub_id).await.map(|hub| hub.description),
+        IrcTarget::Channel(hub_id, channel_id) => http
+            .channel_get(hub_id, channel_id)
+            .await
+            .map(|channel| channel.description),
+    };
+    if let Ok(topic) = topic {
+        if !topic.is_empty() {
+            session.numeric(332, vec![target.to_string(), topic]);
+        }
+    }
+    if let Ok(hub) = http.hub_get(target.hub_id()).await {
+        let names = hub
+            .members
+            .keys()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>();
+        for chunk in names.chunks(16) {
+            session.numeric(
+                353,
+                vec!["=".to_string(), target.to_string(), chunk.join(" ")],
+            );
+        }
+    }
+    session.numeric(
+        366,
+        vec![target.to_string(), "End of /NAMES list".to_string()],
+    );
+}
+
+async fn part(session: &Session, http: &HttpClient, ws: &WebsocketClient, name: &str) {
+    let result = match name.parse::<IrcTarget>() {
+        Ok(IrcTarget::Hub(hub_id)) => match ws.unsubscribe_hub(hub_id).await {
+            Ok(()) => http.hub_leave(hub_id).await,
+            Err(error) => Err(error),
+        },
+        Ok(IrcTarget::Channel(hub_id, channel_id)) => {
+            ws.unsubscribe_channel(hub_id, channel_id).await
+        }
+        Err(_) => {
+            session.numeric(403, vec![name.to_string(), "No such channel".to_string()]);
+            return;
+        }
+    };
+    match result {
+        Ok(()) => {
+            session.send(IrcMessage::new(
+                Some(session.own_prefix()),
+                "PART",
+                vec![name.to_string()],
+            ));
+        }
+        Err(error) => session.numeric(442, vec![name.to_string(), error.to_string()]),
+    }
+}
+
+async fn privmsg(session: &Session, ws: &WebsocketClient, target: &str, text: &str) {
+    match target.parse::<IrcTarget>() {
+        Ok(IrcTarget::Channel(hub_id, channel_id)) => {
+            if let Err(error) = ws.send_message(hub_id, channel_id, text.to_string()).await {
+                session.numeric(404, vec![target.to_string(), error.to_string()]);
+            }
+        }
+        Ok(IrcTarget::Hub(_)) => session.numeric(
+            404,
+            vec![
+                target.to_string(),
+                "Messages can only be sent to channels".to_string(),
+            ],
+        ),
+        Err(_) => session.numeric(
+            401,
+            vec![target.to_string(), "No such nick/channel".to_string()],
+        ),
+    }
+}
+
+/// Forwards a websocket event to the IRC client, returns `Some` once the client is gone.
+fn relay(
+    out: &UnboundedSender<String>,
+    server_name: &str,
+    user_id: ID,
+    message: WsServerMessage,
+) -> Option<()> {
+    let lines = match message {
+        WsServerMessage::ChatMessage {
+            sender_id,
+            hub_id,
+            channel_id,
+            message,
+            ..
+        } if sender_id != user_id => {
+            let prefix = format!("{}!{}@{}", sender_id, sender_id, server_name);
+            let target = IrcTarget::Channel(hub_id, channel_id).to_string();
+            message
+                .lines()
+                .map(|line| {
+                    IrcMessage::new(
+                        Some(prefix.clone()),
+                        "PRIVMSG",
+                        vec![target.clone(), line.to_string()],
+                    )
+                })
+                .collect()
+        }
+        WsServerMessage::HubUpdated {
+            hub_id,
+            update_type: WsHubUpdateType::UserJoined(member),
+        } if member != user_id => vec![IrcMessage::new(
+            Some(format!("{}!{}@{}", member, member, server_name)),
+            "JOIN",
+            vec![IrcTarget::Hub(hub_id).to_string()],
+        )],
+        WsServerMessage::HubUpdated {
+            hub_id,
+            update_type: WsHubUpdateType::UserLeft(member),
+        } if member != user_id => vec![IrcMessage::new(
+            Some(format!("{}!{}@{}", member, member, server_name)),
+            "PART",
+            vec![IrcTarget::Hub(hub_id).to_string()],
+        )],
+        _ => Vec::new(),
+    };
+    for line in lines {
+        if out.send(line.to_string()).is_err() {
+            return Some(());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_prefix_command_and_params() {
+        let message = IrcMessage::parse(":nick!user@host privmsg #hub :hello there\r\n").unwrap();
+        assert_eq!(message.prefix.as_deref(), Some("nick!user@host"));
+        assert_eq!(message.command, "PRIVMSG");
+        assert_eq!(message.params, vec!["#hub", "hello there"]);
+    }
+
+    #[test]
+    fn parses_messages_without_prefix_or_trailing() {
+        let message = IrcMessage::parse("USER  guest 0 *  ").unwrap();
+        assert_eq!(message.prefix, None);
+        assert_eq!(message.params, vec!["guest", "0", "*"]);
+        assert_eq!(
+            IrcMessage::parse("PING").unwrap().params,
+            Vec::<String>::new()
+        );
+        assert_eq!(IrcMessage::parse(""), None);
+        assert_eq!(IrcMessage::parse(":prefix-only"), None);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for line in [
+            ":server 001 nick :Welcome to the gateway",
+            "JOIN #hub",
+            "PRIVMSG #hub ::starts with a colon",
+            "TOPIC #hub :",
+        ] {
+            assert_eq!(IrcMessage::parse(line).unwrap().to_string(), line);
+        }
+    }
+
+    #[test]
+    fn display_strips_line_breaks() {
+        let message = IrcMessage::new(
+            Some("evil\r\nQUIT".to_string()),
+            "PRIVMSG",
+            vec!["#hub".to_string(), "hi\r\nKILL nick\0".to_string()],
+        );
+        assert_eq!(
+            message.to_string(),
+            ":evil  QUIT PRIVMSG #hub :hi  KILL nick "
+        );
+    }
+
+    #[test]
+    fn targets_round_trip() {
+        let hub = ID::new_v4();
+        let channel = ID::new_v4();
+        for target in [IrcTarget::Hub(hub), IrcTarget::Channel(hub, channel)] {
+            assert_eq!(target.to_string().parse::<IrcTarget>(), Ok(target));
+        }
+        assert!("hub".parse::<IrcTarget>().is_err());
+        assert!("#not-an-id".parse::<IrcTarget>().is_err());
+    }
+}
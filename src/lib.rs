@@ -1,6 +1,47 @@
 pub use error::{Error, Result};
+#[cfg(feature = "wicrs-server")]
 pub use wicrs_server;
 
+pub mod api;
+#[cfg(feature = "use-tokio")]
+pub mod attachment;
+#[cfg(feature = "use-tokio")]
+pub mod batch;
+#[cfg(feature = "use-tokio")]
+pub mod cache;
+pub mod cassette;
 pub mod error;
+pub mod fake;
+pub mod format;
+pub mod handle;
 pub mod http;
+#[cfg(feature = "use-tokio")]
+pub mod irc;
+pub mod metrics;
+pub mod middleware;
+#[cfg(feature = "notify")]
+pub mod notify;
+#[cfg(feature = "use-tokio")]
+pub mod outbox;
+pub mod protocol;
+#[cfg(feature = "use-tokio")]
+pub mod rate_limit;
+pub mod read_state;
+pub mod reconcile;
+#[cfg(feature = "webhook-relay")]
+pub mod relay;
+#[cfg(feature = "use-tokio")]
+pub mod resolve;
+#[cfg(any(feature = "webhook-relay", feature = "webhook-server"))]
+pub mod signature;
+pub mod spec;
+#[cfg(feature = "use-tokio")]
+pub mod stream;
+#[cfg(feature = "use-tokio")]
+pub mod subscriptions;
+#[cfg(feature = "tracing")]
+pub mod trace;
+pub mod version;
+#[cfg(feature = "webhook-server")]
+pub mod webhook;
 pub mod websocket;
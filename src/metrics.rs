@@ -0,0 +1,380 @@
+//! Client metrics, recorded through a pluggable [`Recorder`].
+//!
+//! Nothing is recorded until a recorder is installed with [`set_recorder`]. [`Registry`] is an
+//! in-memory recorder that can render its contents in the Prometheus text format.
+//!
+//! Recorded metrics:
+//! - `wicrs_http_requests_total{method, route}`
+//! - `wicrs_http_errors_total{method, route, error}`
+//! - `wicrs_http_request_duration_seconds{method, route}`
+//! - `wicrs_ws_connects_total`
+//! - `wicrs_ws_reconnects_total`
+//! - `wicrs_ws_errors_total{error}`
+//! - `wicrs_ws_messages_sent_total{kind}`
+//! - `wicrs_ws_messages_received_total{kind}`
+//! - `wicrs_ws_ack_duration_seconds`
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use crate::{
+    error::Result,
+    protocol::{WsClientMessage, WsServerMessage},
+    websocket::MessageKind,
+    Error,
+};
+
+pub type Labels = Vec<(&'static str, String)>;
+
+pub trait Recorder: Send + Sync {
+    fn increment_counter(&self, name: &'static str, labels: Labels, value: u64);
+    fn record_histogram(&self, name: &'static str, labels: Labels, value: f64);
+}
+
+static RECORDER: RwLock<Option<Arc<dyn Recorder>>> = RwLock::new(None);
+
+/// Installs the recorder that receives all client metrics, replacing the previous one.
+pub fn set_recorder(recorder: Arc<dyn Recorder>) {
+    *RECORDER.write().unwrap() = Some(recorder);
+}
+
+pub fn clear_recorder() {
+    *RECORDER.write().unwrap() = None;
+}
+
+fn recorder() -> Option<Arc<dyn Recorder>> {
+    RECORDER.read().unwrap().clone()
+}
+
+pub(crate) fn http_request(method: &str, route: &str, latency: Duration, error: Option<&Error>) {
+    if let Some(recorder) = recorder() {
+        let labels = vec![("method", method.to_string()), ("route", route.to_string())];
+        recorder.increment_counter("wicrs_http_requests_total", labels.clone(), 1);
+        recorder.record_histogram(
+            "wicrs_http_request_duration_seconds",
+            labels.clone(),
+            latency.as_secs_f64(),
+        );
+        if let Some(error) = error {
+            let mut labels = labels;
+            labels.push(("error", error.kind().to_string()));
+            recorder.increment_counter("wicrs_http_errors_total", labels, 1);
+        }
+    }
+}
+
+pub(crate) fn ws_connect<T>(result: &Result<T>) {
+    if let Some(recorder) = recorder() {
+        match result {
+            Ok(_) => recorder.increment_counter("wicrs_ws_connects_total", Vec::new(), 1),
+            Err(error) => ws_error(recorder.as_ref(), error),
+        }
+    }
+}
+
+/// Counts a websocket reconnect, for applications that reconnect with their own logic.
+pub fn record_ws_reconnect() {
+    if let Some(recorder) = recorder() {
+        recorder.increment_counter("wicrs_ws_reconnects_total", Vec::new(), 1);
+    }
+}
+
+pub(crate) fn ws_sent(message: &WsClientMessage, result: &Result<()>) {
+    if let Some(recorder) = recorder() {
+        match result {
+            Ok(()) => ws_message(
+                recorder.as_ref(),
+                "wicrs_ws_messages_sent_total",
+                message.kind(),
+            ),
+            Err(error) => ws_error(recorder.as_ref(), error),
+        }
+    }
+}
+
+pub(crate) fn ws_received(result: &Result<WsServerMessage>) {
+    if let Some(recorder) = recorder() {
+        match result {
+            Ok(message) => ws_message(
+                recorder.as_ref(),
+                "wicrs_ws_messages_received_total",
+                message.kind(),
+            ),
+            Err(error) => ws_error(recorder.as_ref(), error),
+        }
+    }
+}
+
+fn ws_error(recorder: &dyn Recorder, error: &Error) {
+    recorder.increment_counter(
+        "wicrs_ws_errors_total",
+        vec![("error", error.kind().to_string())],
+        1,
+    );
+}
+
+fn ws_message(recorder: &dyn Recorder, name: &'static str, kind: &'static str) {
+    recorder.increment_counter(name, vec![("kind", kind.to_string())], 1);
+}
+
+#[cfg(feature = "use-tokio")]
+pub(crate) fn ws_ack(latency: Duration) {
+    if let Some(recorder) = recorder() {
+        recorder.record_histogram(
+            "wicrs_ws_ack_duration_seconds",
+            Vec::new(),
+            latency.as_secs_f64(),
+        );
+    }
+}
+
+/// Upper bounds of the histogram buckets used by [`Registry`], in seconds.
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+type Key = (&'static str, Labels);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    /// Number of observations in each bucket of [`DEFAULT_BUCKETS`], not cumulative.
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; DEFAULT_BUCKETS.len()];
+        }
+        if let Some(bucket) = DEFAULT_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// In-memory recorder keeping every counter and histogram.
+#[derive(Debug, Default)]
+pub struct Registry {
+    counters: Mutex<BTreeMap<Key, u64>>,
+    histograms: Mutex<BTreeMap<Key, Histogram>>,
+}
+
+impl Registry {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Value of a counter, labels must be given in the order they are recorded in.
+    pub fn counter(&self, name: &'static str, labels: &[(&'static str, &str)]) -> u64 {
+        let key = (name, owned(labels));
+        self.counters
+            .lock()
+            .unwrap()
+            .get(&key)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn histogram(
+        &self,
+        name: &'static str,
+        labels: &[(&'static str, &str)],
+    ) -> Option<Histogram> {
+        let key = (name, owned(labels));
+        self.histograms.lock().unwrap().get(&key).cloned()
+    }
+
+    /// Renders every metric in the Prometheus text exposition format.
+    pub fn render_prometheus(&self) -> String {
+        let mut output = String::new();
+        let mut last_name = "";
+        for ((name, labels), value) in self.counters.lock().unwrap().iter() {
+            if *name != last_name {
+                let _ = writeln!(output, "# TYPE {} counter", name);
+                last_name = name;
+            }
+            let _ = writeln!(output, "{}{} {}", name, render_labels(labels, None), value);
+        }
+        for ((name, labels), histogram) in self.histograms.lock().unwrap().iter() {
+            if *name != last_name {
+                let _ = writeln!(output, "# TYPE {} histogram", name);
+                last_name = name;
+            }
+            let mut cumulative = 0;
+            for (bound, count) in DEFAULT_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    output,
+                    "{}_bucket{} {}",
+                    name,
+                    render_labels(labels, Some(&bound.to_string())),
+                    cumulative
+                );
+            }
+            let _ = writeln!(
+                output,
+                "{}_bucket{} {}",
+                name,
+                render_labels(labels, Some("+Inf")),
+                histogram.count
+            );
+            let _ = writeln!(
+                output,
+                "{}_sum{} {}",
+                name,
+                render_labels(labels, None),
+                histogram.sum
+            );
+            let _ = writeln!(
+                output,
+                "{}_count{} {}",
+                name,
+                render_labels(labels, None),
+                histogram.count
+            );
+        }
+        output
+    }
+}
+
+impl Recorder for Registry {
+    fn increment_counter(&self, name: &'static str, labels: Labels, value: u64) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, labels))
+            .or_insert(0) += value;
+    }
+
+    fn record_histogram(&self, name: &'static str, labels: Labels, value: f64) {
+        self.histograms
+            .lock()
+            .unwrap()
+            .entry((name, labels))
+            .or_default()
+            .observe(value);
+    }
+}
+
+fn owned(labels: &[(&'static str, &str)]) -> Labels {
+    labels
+        .iter()
+        .map(|(name, value)| (*name, value.to_string()))
+        .collect()
+}
+
+fn render_labels(labels: &[(&'static str, String)], le: Option<&str>) -> String {
+    let mut rendered = labels
+        .iter()
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect::<Vec<_>>();
+    if let Some(le) = le {
+        rendered.push(format!("le=\"{}\"", le));
+    }
+    if rendered.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", rendered.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::ID;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let registry = Registry::new();
+        let labels = || vec![("route", "/hub/{id}".to_string())];
+        registry.increment_counter("wicrs_http_requests_total", labels(), 2);
+        registry.increment_counter("wicrs_ws_connects_total", Vec::new(), 1);
+        registry.record_histogram("wicrs_http_request_duration_seconds", labels(), 0.02);
+        registry.record_histogram("wicrs_http_request_duration_seconds", labels(), 20.0);
+        let rendered = registry.render_prometheus();
+        let lines = rendered.lines().collect::<Vec<_>>();
+        assert_eq!(
+            lines[..4],
+            [
+                "# TYPE wicrs_http_requests_total counter",
+                "wicrs_http_requests_total{route=\"/hub/{id}\"} 2",
+                "# TYPE wicrs_ws_connects_total counter",
+                "wicrs_ws_connects_total 1",
+            ]
+        );
+        assert_eq!(
+            lines[4],
+            "# TYPE wicrs_http_request_duration_seconds histogram"
+        );
+        assert!(lines.contains(
+            &"wicrs_http_request_duration_seconds_bucket{route=\"/hub/{id}\",le=\"0.01\"} 0"
+        ));
+        assert!(lines.contains(
+            &"wicrs_http_request_duration_seconds_bucket{route=\"/hub/{id}\",le=\"0.025\"} 1"
+        ));
+        assert!(lines.contains(
+            &"wicrs_http_request_duration_seconds_bucket{route=\"/hub/{id}\",le=\"10\"} 1"
+        ));
+        assert!(lines.contains(
+            &"wicrs_http_request_duration_seconds_bucket{route=\"/hub/{id}\",le=\"+Inf\"} 2"
+        ));
+        assert!(
+            lines.contains(&"wicrs_http_request_duration_seconds_sum{route=\"/hub/{id}\"} 20.02")
+        );
+        assert!(lines.contains(&"wicrs_http_request_duration_seconds_count{route=\"/hub/{id}\"} 2"));
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        let registry = Registry::new();
+        registry.increment_counter("errors", vec![("error", "a\"b\\c\nd".to_string())], 1);
+        assert_eq!(
+            registry.render_prometheus(),
+            "# TYPE errors counter\nerrors{error=\"a\\\"b\\\\c\\nd\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn message_kinds_match_the_serialized_variant() {
+        let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+        let messages = [
+            WsServerMessage::Success,
+            WsServerMessage::HubUpdated {
+                hub_id,
+                update_type: crate::protocol::WsHubUpdateType::HubDeleted,
+            },
+            WsServerMessage::UserStartedTyping {
+                user_id: ID::new_v4(),
+                hub_id,
+                channel_id,
+            },
+        ];
+        for message in &messages {
+            let value = serde_json::to_value(message).unwrap();
+            assert_eq!(crate::websocket::variant_name(&value), Some(message.kind()));
+        }
+        let message = WsClientMessage::SendMessage {
+            hub_id,
+            channel_id,
+            message: String::new(),
+        };
+        let value = serde_json::to_value(&message).unwrap();
+        assert_eq!(crate::websocket::variant_name(&value), Some(message.kind()));
+    }
+}
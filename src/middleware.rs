@@ -0,0 +1,79 @@
+//! Hooks run around every request sent by [`HttpClient`](crate::http::HttpClient).
+//!
+//! Each [`Middleware`] gets the request and a [`Next`] that runs the rest of the chain, it can
+//! change the request, look at or replace the response, call `next` more than once (for retries)
+//! or not at all (to answer the request itself). Middleware run in the order they were added,
+//! the last one calls the server.
+
+use std::{fmt, future::Future, pin::Pin, sync::Arc};
+
+use reqwest::{header::HeaderMap, Client, Request, Response};
+
+use crate::error::Result;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>) -> BoxFuture<'a, Result<Response>>;
+}
+
+/// The rest of the middleware chain.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    client: &'a Client,
+    middleware: &'a [Arc<dyn Middleware>],
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(client: &'a Client, middleware: &'a [Arc<dyn Middleware>]) -> Self {
+        Self { client, middleware }
+    }
+
+    pub fn run(self, request: Request) -> BoxFuture<'a, Result<Response>> {
+        match self.middleware.split_first() {
+            Some((middleware, rest)) => middleware.handle(
+                request,
+                Next {
+                    client: self.client,
+                    middleware: rest,
+                },
+            ),
+            None => Box::pin(async move { Ok(self.client.execute(request).await?) }),
+        }
+    }
+}
+
+impl fmt::Debug for Next<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("remaining", &self.middleware.len())
+            .finish()
+    }
+}
+
+/// Middleware of a client.
+#[derive(Clone, Default)]
+pub(crate) struct Chain(pub(crate) Vec<Arc<dyn Middleware>>);
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chain").field("len", &self.0.len()).finish()
+    }
+}
+
+/// Adds headers to every request, replacing headers with the same name.
+#[derive(Debug, Clone, Default)]
+pub struct SetHeaders(pub HeaderMap);
+
+impl Middleware for SetHeaders {
+    fn handle<'a>(
+        &'a self,
+        mut request: Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, Result<Response>> {
+        for (name, value) in &self.0 {
+            request.headers_mut().insert(name, value.clone());
+        }
+        next.run(request)
+    }
+}
@@ -0,0 +1,422 @@
+//! Notifications for mentions, names, keywords and patterns in chat messages.
+//!
+//! A [`Notifier`] checks every `ChatMessage` against its [`NotifyConfig`] and passes the
+//! resulting [`Notification`]s to its sinks, which can forward them to desktop notifications or
+//! anything else. The user is mentioned by `<@user_id>`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::{WsServerMessage, ID};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Mute {
+    /// No notifications at all.
+    All,
+    /// Only notify when the user is mentioned by ID.
+    ExceptMentions,
+}
+
+/// Daily period in local time during which only mentions notify, `start` may be after `end`
+/// for periods spanning midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+    /// Whether mentions still notify during quiet hours.
+    pub allow_mentions: bool,
+}
+
+impl QuietHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NotifyConfig {
+    /// Names the user goes by, matched as whole words ignoring case.
+    pub names: Vec<String>,
+    /// Words or phrases matched anywhere ignoring case.
+    pub keywords: Vec<String>,
+    pub patterns: Vec<Regex>,
+    pub muted_hubs: HashMap<ID, Mute>,
+    /// Channel settings take precedence over the settings of their hub.
+    pub muted_channels: HashMap<(ID, ID), Mute>,
+    pub quiet_hours: Option<QuietHours>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum Trigger {
+    Mention,
+    Name(String),
+    Keyword(String),
+    Pattern(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Notification {
+    pub hub_id: ID,
+    pub channel_id: ID,
+    pub message_id: ID,
+    pub sender_id: ID,
+    pub message: String,
+    pub triggers: Vec<Trigger>,
+    pub received: DateTime<Utc>,
+}
+
+impl Notification {
+    pub fn is_mention(&self) -> bool {
+        self.triggers.contains(&Trigger::Mention)
+    }
+}
+
+pub trait NotificationSink: Send + Sync {
+    fn notify(&self, notification: &Notification);
+}
+
+impl<F> NotificationSink for F
+where
+    F: Fn(&Notification) + Send + Sync,
+{
+    fn notify(&self, notification: &Notification) {
+        self(notification)
+    }
+}
+
+pub struct Notifier {
+    user_id: ID,
+    config: RwLock<NotifyConfig>,
+    sinks: RwLock<Vec<Arc<dyn NotificationSink>>>,
+}
+
+impl Notifier {
+    pub fn new(user_id: ID, config: NotifyConfig) -> Self {
+        Self {
+            user_id,
+            config: RwLock::new(config),
+            sinks: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn add_sink(&self, sink: Arc<dyn NotificationSink>) {
+        self.sinks.write().unwrap().push(sink);
+    }
+
+    pub fn config(&self) -> NotifyConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn update_config<F: FnOnce(&mut NotifyConfig)>(&self, f: F) {
+        f(&mut self.config.write().unwrap());
+    }
+
+    /// Sets or clears (`None`) the mute setting of a hub or, if `channel_id` is given, a channel.
+    pub fn set_mute(&self, hub_id: ID, channel_id: Option<ID>, mute: Option<Mute>) {
+        let mut config = self.config.write().unwrap();
+        match (channel_id, mute) {
+            (Some(channel_id), Some(mute)) => {
+                config.muted_channels.insert((hub_id, channel_id), mute);
+            }
+            (Some(channel_id), None) => {
+                config.muted_channels.remove(&(hub_id, channel_id));
+            }
+            (None, Some(mute)) => {
+                config.muted_hubs.insert(hub_id, mute);
+            }
+            (None, None) => {
+                config.muted_hubs.remove(&hub_id);
+            }
+        }
+    }
+
+    /// Checks a message and passes the notification, if any, to every sink.
+    pub fn handle(&self, message: &WsServerMessage) -> Option<Notification> {
+        let notification = self.check_at(message, Local::now().time())?;
+        for sink in self.sinks.read().unwrap().iter() {
+            sink.notify(&notification);
+        }
+        Some(notification)
+    }
+
+    /// Builds the notification for a message received at `time` (local time) without passing it
+    /// to the sinks.
+    pub fn check_at(&self, message: &WsServerMessage, time: NaiveTime) -> Option<Notification> {
+        let (sender_id, hub_id, channel_id, message_id, content) = match message {
+            WsServerMessage::ChatMessage {
+                sender_id,
+                hub_id,
+                channel_id,
+                message_id,
+                message,
+            } if *sender_id != self.user_id => {
+                (*sender_id, *hub_id, *channel_id, *message_id, message)
+            }
+            _ => return None,
+        };
+        let config = self.config.read().unwrap();
+        let mut triggers = self.triggers(&config, content);
+        if triggers.is_empty() {
+            return None;
+        }
+        let mute = config
+            .muted_channels
+            .get(&(hub_id, channel_id))
+            .or_else(|| config.muted_hubs.get(&hub_id));
+        let quiet = config
+            .quiet_hours
+            .filter(|quiet_hours| quiet_hours.contains(time));
+        let mentions_only = match (mute, quiet) {
+            (Some(Mute::All), _) => return None,
+            (_, Some(quiet_hours)) if !quiet_hours.allow_mentions => return None,
+            (Some(Mute::ExceptMentions), _) | (_, Some(_)) => true,
+            (None, None) => false,
+        };
+        if mentions_only {
+            triggers.retain(|trigger| *trigger == Trigger::Mention);
+            if triggers.is_empty() {
+                return None;
+            }
+        }
+        Some(Notification {
+            hub_id,
+            channel_id,
+            message_id,
+            sender_id,
+            message: content.clone(),
+            triggers,
+            received: Utc::now(),
+        })
+    }
+
+    fn triggers(&self, config: &NotifyConfig, content: &str) -> Vec<Trigger> {
+        let mut triggers = Vec::new();
+        if content.contains(&format!("<@{}>", self.user_id)) {
+            triggers.push(Trigger::Mention);
+        }
+        let lowercase = content.to_lowercase();
+        for name in &config.names {
+            if contains_word(&lowercase, &name.to_lowercase()) {
+                triggers.push(Trigger::Name(name.clone()));
+            }
+        }
+        for keyword in config.keywords.iter().filter(|keyword| !keyword.is_empty()) {
+            if lowercase.contains(&keyword.to_lowercase()) {
+                triggers.push(Trigger::Keyword(keyword.clone()));
+            }
+        }
+        for pattern in &config.patterns {
+            if pattern.is_match(content) {
+                triggers.push(Trigger::Pattern(pattern.as_str().to_string()));
+            }
+        }
+        triggers
+    }
+}
+
+/// Whether `word` appears in `text` without letters or digits directly around it.
+fn contains_word(text: &str, word: &str) -> bool {
+    if word.is_empty() {
+        return false;
+    }
+    text.match_indices(word).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + word.len()..].chars().next();
+        !matches!(before, Some(c) if c.is_alphanumeric())
+            && !matches!(after, Some(c) if c.is_alphanumeric())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn chat_message(hub_id: ID, channel_id: ID, message: &str) -> WsServerMessage {
+        WsServerMessage::ChatMessage {
+            sender_id: ID::new_v4(),
+            hub_id,
+            channel_id,
+            message_id: ID::new_v4(),
+            message: message.to_string(),
+        }
+    }
+
+    fn config() -> NotifyConfig {
+        NotifyConfig {
+            names: vec!["Alice".to_string()],
+            keywords: vec!["deploy".to_string()],
+            patterns: vec![Regex::new(r"#\d+").unwrap()],
+            ..NotifyConfig::default()
+        }
+    }
+
+    /// Triggers of a message sent to a new channel at noon.
+    fn triggers(notifier: &Notifier, message: &str) -> Vec<Trigger> {
+        notifier
+            .check_at(
+                &chat_message(ID::new_v4(), ID::new_v4(), message),
+                time(12, 0),
+            )
+            .map(|notification| notification.triggers)
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn collects_every_trigger_in_order() {
+        let user_id = ID::new_v4();
+        let notifier = Notifier::new(user_id, config());
+        let message = format!("<@{}> alice, DEPLOYING fixes #12", user_id);
+        assert_eq!(
+            triggers(&notifier, &message),
+            vec![
+                Trigger::Mention,
+                Trigger::Name("Alice".to_string()),
+                Trigger::Keyword("deploy".to_string()),
+                Trigger::Pattern(r"#\d+".to_string()),
+            ]
+        );
+        assert!(triggers(&notifier, "nothing to see").is_empty());
+    }
+
+    #[test]
+    fn names_only_match_whole_words() {
+        let notifier = Notifier::new(ID::new_v4(), config());
+        assert!(triggers(&notifier, "malice").is_empty());
+        assert!(triggers(&notifier, "alice2").is_empty());
+        assert_eq!(
+            triggers(&notifier, "(Alice)"),
+            vec![Trigger::Name("Alice".to_string())]
+        );
+    }
+
+    #[test]
+    fn empty_keywords_and_names_match_nothing() {
+        let config = NotifyConfig {
+            names: vec![String::new()],
+            keywords: vec![String::new()],
+            ..NotifyConfig::default()
+        };
+        let notifier = Notifier::new(ID::new_v4(), config);
+        assert!(triggers(&notifier, "any message").is_empty());
+    }
+
+    #[test]
+    fn own_messages_do_not_notify() {
+        let user_id = ID::new_v4();
+        let notifier = Notifier::new(user_id, config());
+        let message = WsServerMessage::ChatMessage {
+            sender_id: user_id,
+            hub_id: ID::new_v4(),
+            channel_id: ID::new_v4(),
+            message_id: ID::new_v4(),
+            message: "alice".to_string(),
+        };
+        assert!(notifier.check_at(&message, time(12, 0)).is_none());
+    }
+
+    #[test]
+    fn channel_mutes_take_precedence_over_hub_mutes() {
+        let user_id = ID::new_v4();
+        let (hub_id, channel_id, other_channel) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        let notifier = Notifier::new(user_id, config());
+        notifier.set_mute(hub_id, None, Some(Mute::All));
+        notifier.set_mute(hub_id, Some(channel_id), Some(Mute::ExceptMentions));
+        let check = |channel_id, message: &str| {
+            notifier
+                .check_at(&chat_message(hub_id, channel_id, message), time(12, 0))
+                .map(|notification| notification.triggers)
+        };
+        let mention = format!("alice <@{}>", user_id);
+
+        assert_eq!(check(channel_id, &mention), Some(vec![Trigger::Mention]));
+        assert_eq!(check(channel_id, "alice"), None);
+        assert_eq!(check(other_channel, &mention), None);
+
+        notifier.set_mute(hub_id, None, None);
+        assert!(check(other_channel, "alice").is_some());
+    }
+
+    #[test]
+    fn quiet_hours_span_midnight() {
+        let quiet_hours = QuietHours {
+            start: time(22, 0),
+            end: time(7, 0),
+            allow_mentions: true,
+        };
+        assert!(quiet_hours.contains(time(23, 30)));
+        assert!(quiet_hours.contains(time(6, 59)));
+        assert!(!quiet_hours.contains(time(7, 0)));
+        assert!(!quiet_hours.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_only_let_mentions_through_if_allowed() {
+        let user_id = ID::new_v4();
+        let mut quiet_hours = QuietHours {
+            start: time(22, 0),
+            end: time(7, 0),
+            allow_mentions: true,
+        };
+        let notifier = Notifier::new(
+            user_id,
+            NotifyConfig {
+                quiet_hours: Some(quiet_hours),
+                ..config()
+            },
+        );
+        let message = chat_message(ID::new_v4(), ID::new_v4(), &format!("alice <@{}>", user_id));
+        let triggers_at = |time| {
+            notifier
+                .check_at(&message, time)
+                .map(|notification| notification.triggers)
+        };
+
+        assert_eq!(triggers_at(time(23, 0)), Some(vec![Trigger::Mention]));
+        assert_eq!(
+            triggers_at(time(12, 0)).map(|triggers| triggers.len()),
+            Some(2)
+        );
+
+        quiet_hours.allow_mentions = false;
+        notifier.update_config(|config| config.quiet_hours = Some(quiet_hours));
+        assert_eq!(triggers_at(time(23, 0)), None);
+    }
+
+    #[test]
+    fn handle_passes_notifications_to_sinks() {
+        let notifier = Notifier::new(ID::new_v4(), config());
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let seen = Arc::clone(&seen);
+            move |notification: &Notification| {
+                seen.lock().unwrap().push(notification.message.clone())
+            }
+        };
+        notifier.add_sink(Arc::new(sink));
+        let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+        assert!(notifier
+            .handle(&chat_message(hub_id, channel_id, "quiet"))
+            .is_none());
+        assert!(notifier
+            .handle(&chat_message(hub_id, channel_id, "ping #1"))
+            .is_some());
+        assert_eq!(*seen.lock().unwrap(), vec!["ping #1".to_string()]);
+    }
+}
@@ -0,0 +1,340 @@
+//! Optimistic message sending.
+//!
+//! [`Outbox::queue`] records a pending local message that can be shown right away, it is then
+//! sent over HTTP (which, unlike the websocket, returns the new message's ID) and reconciled with
+//! the server's `ChatMessage` echo by that ID or, if the echo arrives first, by its content.
+//! Sent messages whose echo does not arrive within the echo timeout, for example because the
+//! channel is not subscribed, are dropped by [`Outbox::expire`].
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::{WsServerMessage, ID},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeliveryState {
+    Pending,
+    Sent,
+    Failed(String),
+    /// Sent, but the echo did not arrive within the echo timeout so it is no longer tracked.
+    Expired,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutgoingMessage {
+    /// ID given to the message before the server assigned one.
+    pub local_id: ID,
+    pub hub_id: ID,
+    pub channel_id: ID,
+    pub content: String,
+    pub created: DateTime<Utc>,
+    /// ID assigned by the server once the message was sent.
+    pub message_id: Option<ID>,
+    /// When the server accepted the message.
+    pub sent: Option<DateTime<Utc>>,
+    pub state: DeliveryState,
+}
+
+/// How long sent messages wait for their echo by default.
+pub const DEFAULT_ECHO_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct Outbox {
+    client: Arc<HttpClient>,
+    messages: Mutex<Vec<OutgoingMessage>>,
+    updates: broadcast::Sender<OutgoingMessage>,
+    echo_timeout: Duration,
+}
+
+impl Outbox {
+    pub fn new(client: Arc<HttpClient>) -> Self {
+        let (updates, _) = broadcast::channel(64);
+        Self {
+            client,
+            messages: Mutex::new(Vec::new()),
+            updates,
+            echo_timeout: DEFAULT_ECHO_TIMEOUT,
+        }
+    }
+
+    /// Sets how long sent messages wait for their echo before [`Outbox::expire`] drops them.
+    pub fn with_echo_timeout(mut self, echo_timeout: Duration) -> Self {
+        self.echo_timeout = echo_timeout;
+        self
+    }
+
+    /// Receives every message whose delivery state changed.
+    pub fn updates(&self) -> broadcast::Receiver<OutgoingMessage> {
+        self.updates.subscribe()
+    }
+
+    /// Messages that have not been reconciled with their echo yet, oldest first.
+    pub fn messages(&self) -> Vec<OutgoingMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    pub fn message(&self, local_id: ID) -> Option<OutgoingMessage> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|message| message.local_id == local_id)
+            .cloned()
+    }
+
+    pub fn failed(&self) -> Vec<OutgoingMessage> {
+        self.messages
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|message| matches!(message.state, DeliveryState::Failed(_)))
+            .cloned()
+            .collect()
+    }
+
+    /// Stops tracking a message, for messages that were given up on or whose echo will never
+    /// arrive because the channel is not subscribed.
+    pub fn remove(&self, local_id: ID) -> Option<OutgoingMessage> {
+        let mut messages = self.messages.lock().unwrap();
+        let index = messages
+            .iter()
+            .position(|message| message.local_id == local_id)?;
+        Some(messages.remove(index))
+    }
+
+    /// Stops tracking sent messages whose echo did not arrive within the echo timeout and
+    /// returns them as [`DeliveryState::Expired`], they are also sent to the update
+    /// subscribers. This is done whenever a message is queued or an event is handled.
+    pub fn expire(&self) -> Vec<OutgoingMessage> {
+        let cutoff = match chrono::Duration::from_std(self.echo_timeout) {
+            Ok(timeout) => Utc::now() - timeout,
+            Err(_) => return Vec::new(),
+        };
+        let mut messages = self.messages.lock().unwrap();
+        let (mut expired, kept): (Vec<_>, _) =
+            messages.drain(..).partition(|message: &OutgoingMessage| {
+                message.state == DeliveryState::Sent
+                    && matches!(message.sent, Some(sent) if sent < cutoff)
+            });
+        *messages = kept;
+        drop(messages);
+        for message in &mut expired {
+            message.state = DeliveryState::Expired;
+            self.notify(message.clone());
+        }
+        expired
+    }
+
+    /// Adds a pending message without sending it.
+    pub fn queue(&self, hub_id: ID, channel_id: ID, content: String) -> OutgoingMessage {
+        self.expire();
+        let message = OutgoingMessage {
+            local_id: Uuid::new_v4(),
+            hub_id,
+            channel_id,
+            content,
+            created: Utc::now(),
+            message_id: None,
+            sent: None,
+            state: DeliveryState::Pending,
+        };
+        self.messages.lock().unwrap().push(message.clone());
+        self.notify(message.clone());
+        message
+    }
+
+    /// Queues a message and sends it, the returned message has its final delivery state.
+    pub async fn send(&self, hub_id: ID, channel_id: ID, content: String) -> OutgoingMessage {
+        let message = self.queue(hub_id, channel_id, content);
+        let result = self.deliver(message.local_id).await;
+        // Messages that are gone were reconciled with their echo while being sent.
+        self.message(message.local_id)
+            .unwrap_or_else(|| OutgoingMessage {
+                message_id: result.ok().flatten(),
+                sent: Some(Utc::now()),
+                state: DeliveryState::Sent,
+                ..message
+            })
+    }
+
+    /// Sends a failed message again.
+    pub async fn retry(&self, local_id: ID) -> Result<Option<ID>> {
+        let retry = self.update(local_id, |message| {
+            if let DeliveryState::Failed(_) = message.state {
+                message.state = DeliveryState::Pending;
+                true
+            } else {
+                false
+            }
+        });
+        if retry == Some(true) {
+            self.deliver(local_id).await
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn retry_failed(&self) -> Vec<Result<Option<ID>>> {
+        let mut results = Vec::new();
+        for message in self.failed() {
+            results.push(self.retry(message.local_id).await);
+        }
+        results
+    }
+
+    /// Sends a pending message, returning the ID assigned by the server. Messages that are
+    /// unknown, already sent or reconciled by their echo in the meantime are left alone.
+    pub async fn deliver(&self, local_id: ID) -> Result<Option<ID>> {
+        let message = match self.message(local_id) {
+            Some(message) if message.state == DeliveryState::Pending => message,
+            _ => return Ok(None),
+        };
+        let result = self
+            .client
+            .message_send(message.hub_id, message.channel_id, message.content)
+            .await;
+        match result {
+            Ok(message_id) => {
+                self.update(local_id, |message| {
+                    message.message_id = Some(message_id);
+                    message.sent = Some(Utc::now());
+                    message.state = DeliveryState::Sent;
+                });
+                Ok(Some(message_id))
+            }
+            Err(error) => {
+                self.update(local_id, |message| {
+                    message.state = DeliveryState::Failed(error.to_string());
+                });
+                Err(error)
+            }
+        }
+    }
+
+    /// Reconciles a websocket event with the local messages, returning the local message it is
+    /// the echo of so that it can be replaced by the server's copy.
+    pub fn handle_event(&self, event: &WsServerMessage) -> Option<OutgoingMessage> {
+        self.expire();
+        let (hub_id, channel_id, message_id, content) = match event {
+            WsServerMessage::ChatMessage {
+                sender_id,
+                hub_id,
+                channel_id,
+                message_id,
+                message,
+            } if *sender_id == self.client.user_id => (hub_id, channel_id, message_id, message),
+            _ => return None,
+        };
+        let mut messages = self.messages.lock().unwrap();
+        let index = messages
+            .iter()
+            .position(|message| message.message_id == Some(*message_id))
+            .or_else(|| {
+                messages.iter().position(|message| {
+                    message.state == DeliveryState::Pending
+                        && message.message_id.is_none()
+                        && message.hub_id == *hub_id
+                        && message.channel_id == *channel_id
+                        && &message.content == content
+                })
+            })?;
+        let mut message = messages.remove(index);
+        drop(messages);
+        message.message_id = Some(*message_id);
+        message.sent.get_or_insert_with(Utc::now);
+        message.state = DeliveryState::Sent;
+        self.notify(message.clone());
+        Some(message)
+    }
+
+    fn update<T, F>(&self, local_id: ID, f: F) -> Option<T>
+    where
+        F: FnOnce(&mut OutgoingMessage) -> T,
+    {
+        let mut messages = self.messages.lock().unwrap();
+        let message = messages
+            .iter_mut()
+            .find(|message| message.local_id == local_id)?;
+        let result = f(message);
+        let message = message.clone();
+        drop(messages);
+        self.notify(message);
+        Some(result)
+    }
+
+    fn notify(&self, message: OutgoingMessage) {
+        let _ = self.updates.send(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outbox(echo_timeout: Duration) -> Outbox {
+        let client = HttpClient::new(Uuid::new_v4(), "http://127.0.0.1:9/api".to_string()).unwrap();
+        Outbox::new(Arc::new(client)).with_echo_timeout(echo_timeout)
+    }
+
+    fn sent(outbox: &Outbox, local_id: ID, ago: chrono::Duration) {
+        outbox.update(local_id, |message| {
+            message.message_id = Some(Uuid::new_v4());
+            message.sent = Some(Utc::now() - ago);
+            message.state = DeliveryState::Sent;
+        });
+    }
+
+    #[test]
+    fn expires_sent_messages_without_an_echo() {
+        let outbox = outbox(Duration::from_secs(60));
+        let (hub_id, channel_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let old = outbox.queue(hub_id, channel_id, "old".to_string());
+        let recent = outbox.queue(hub_id, channel_id, "recent".to_string());
+        let pending = outbox.queue(hub_id, channel_id, "pending".to_string());
+        let failed = outbox.queue(hub_id, channel_id, "failed".to_string());
+        sent(&outbox, old.local_id, chrono::Duration::seconds(61));
+        sent(&outbox, recent.local_id, chrono::Duration::seconds(1));
+        outbox.update(failed.local_id, |message| {
+            message.state = DeliveryState::Failed("error".to_string());
+        });
+
+        let mut updates = outbox.updates();
+        let expired = outbox.expire();
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].local_id, old.local_id);
+        assert_eq!(expired[0].state, DeliveryState::Expired);
+        let update = updates.try_recv().unwrap();
+        assert_eq!(
+            (update.local_id, update.state),
+            (old.local_id, DeliveryState::Expired)
+        );
+        assert!(updates.try_recv().is_err());
+        let kept = outbox
+            .messages()
+            .iter()
+            .map(|message| message.local_id)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kept,
+            vec![recent.local_id, pending.local_id, failed.local_id]
+        );
+    }
+
+    #[test]
+    fn handling_events_expires_messages() {
+        let outbox = outbox(Duration::from_secs(0));
+        let message = outbox.queue(Uuid::new_v4(), Uuid::new_v4(), "hello".to_string());
+        sent(&outbox, message.local_id, chrono::Duration::seconds(1));
+        assert!(outbox.handle_event(&WsServerMessage::Success).is_none());
+        assert!(outbox.messages().is_empty());
+    }
+}
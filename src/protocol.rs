@@ -0,0 +1,259 @@
+//! Types of the server's HTTP and websocket protocol.
+//!
+//! They have the same JSON representation as the types of `wicrs_server`, which is only a
+//! dependency with the `wicrs-server` feature so that clients build without the server and its
+//! dependencies. With the feature every type converts to and from the server's type with
+//! [`From`], and the tests check the fixtures in `tests/fixtures/protocol` against both.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "wicrs-server")]
+mod server;
+
+pub type ID = uuid::Uuid;
+
+/// `Some(true)` allows, `Some(false)` denies and `None` inherits the setting.
+pub type PermissionSetting = Option<bool>;
+
+#[derive(Debug, Clone, Serialize, Deserialize, thiserror::Error, PartialEq, Eq)]
+pub enum ApiError {
+    #[error("not found")]
+    NotFound,
+    #[error("muted")]
+    Muted,
+    #[error("other {0}")]
+    Other(String),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Response<T> {
+    Success(T),
+    Error(ApiError),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum HubPermission {
+    All,
+    ReadChannels,
+    WriteChannels,
+    Administrate,
+    ManageChannels,
+    Mute,
+    Unmute,
+    Kick,
+    Ban,
+    Unban,
+}
+
+/// The name used in request paths.
+impl fmt::Display for HubPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum ChannelPermission {
+    Write,
+    Read,
+    Manage,
+    All,
+}
+
+/// The name used in request paths.
+impl fmt::Display for ChannelPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Channel {
+    pub id: ID,
+    pub hub_id: ID,
+    pub description: String,
+    pub name: String,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HubMember {
+    pub user_id: ID,
+    pub joined: DateTime<Utc>,
+    pub hub: ID,
+    pub nickname: String,
+    pub groups: Vec<ID>,
+    pub hub_permissions: HashMap<HubPermission, PermissionSetting>,
+    pub channel_permissions: HashMap<ID, HashMap<ChannelPermission, PermissionSetting>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionGroup {
+    pub id: ID,
+    pub name: String,
+    pub members: Vec<ID>,
+    pub hub_permissions: HashMap<HubPermission, PermissionSetting>,
+    pub channel_permissions: HashMap<ID, HashMap<ChannelPermission, PermissionSetting>>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hub {
+    pub channels: HashMap<ID, Channel>,
+    pub members: HashMap<ID, HubMember>,
+    pub bans: HashSet<ID>,
+    pub mutes: HashSet<ID>,
+    pub description: String,
+    pub owner: ID,
+    pub groups: HashMap<ID, PermissionGroup>,
+    pub default_group: ID,
+    pub name: String,
+    pub id: ID,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    pub id: ID,
+    pub hub_id: ID,
+    pub channel_id: ID,
+    pub sender: ID,
+    pub created: DateTime<Utc>,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpHubUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub default_group: Option<ID>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpChannelUpdate {
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpSetPermission {
+    pub setting: PermissionSetting,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpMemberStatus {
+    pub muted: bool,
+    pub banned: bool,
+    pub member: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpMessagesAfterQuery {
+    pub from: ID,
+    pub max: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpMessagesBeforeQuery {
+    pub to: ID,
+    pub max: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpLastMessagesQuery {
+    pub max: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpMessagesBetweenQuery {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub max: usize,
+    pub new_to_old: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WsClientMessage {
+    SubscribeHub {
+        hub_id: ID,
+    },
+    UnsubscribeHub {
+        hub_id: ID,
+    },
+    SubscribeChannel {
+        hub_id: ID,
+        channel_id: ID,
+    },
+    UnsubscribeChannel {
+        hub_id: ID,
+        channel_id: ID,
+    },
+    StartTyping {
+        hub_id: ID,
+        channel_id: ID,
+    },
+    StopTyping {
+        hub_id: ID,
+        channel_id: ID,
+    },
+    SendMessage {
+        hub_id: ID,
+        channel_id: ID,
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WsHubUpdateType {
+    HubDeleted,
+    HubUpdated,
+    UserJoined(ID),
+    UserLeft(ID),
+    UserBanned(ID),
+    UserUnbanned(ID),
+    UserMuted(ID),
+    UserUnmuted(ID),
+    UserKicked(ID),
+    UserHubPermissionChanged(ID),
+    /// Member and channel.
+    UserChannelPermissionChanged(ID, ID),
+    ChannelCreated(ID),
+    ChannelDeleted(ID),
+    ChannelUpdated(ID),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum WsServerMessage {
+    Error(ApiError),
+    InvalidCommand,
+    NotSigned,
+    CommandFailed,
+    ChatMessage {
+        sender_id: ID,
+        hub_id: ID,
+        channel_id: ID,
+        message_id: ID,
+        message: String,
+    },
+    HubUpdated {
+        hub_id: ID,
+        update_type: WsHubUpdateType,
+    },
+    Success,
+    UserStartedTyping {
+        user_id: ID,
+        hub_id: ID,
+        channel_id: ID,
+    },
+    UserStoppedTyping {
+        user_id: ID,
+        hub_id: ID,
+        channel_id: ID,
+    },
+}
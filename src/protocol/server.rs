@@ -0,0 +1,344 @@
+//! Conversions between these types and the ones of `wicrs_server`.
+//!
+//! Enums are converted variant by variant, so a variant added to either side fails to compile
+//! instead of being lost. The tests check that converted values are written as the same JSON as
+//! the fixtures in `tests/fixtures/protocol`.
+
+use std::{collections::HashMap, hash::Hash};
+
+use wicrs_server::{error as server_error, prelude as server};
+
+use super::*;
+
+fn map<K, V, K2, V2>(map: HashMap<K, V>) -> HashMap<K2, V2>
+where
+    K2: From<K> + Eq + Hash,
+    V2: From<V>,
+{
+    map.into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect()
+}
+
+fn nested_map<K, V, K2, V2>(permissions: HashMap<ID, HashMap<K, V>>) -> HashMap<ID, HashMap<K2, V2>>
+where
+    K2: From<K> + Eq + Hash,
+    V2: From<V>,
+{
+    permissions
+        .into_iter()
+        .map(|(id, permissions)| (id, map(permissions)))
+        .collect()
+}
+
+/// Converts structs both ways field by field, each field with the given function.
+macro_rules! convert_structs {
+    ($($name:ident { $($field:ident: $convert:expr),* $(,)? })*) => {$(
+        impl From<$name> for server::$name {
+            fn from(value: $name) -> Self {
+                Self { $($field: $convert(value.$field)),* }
+            }
+        }
+
+        impl From<server::$name> for $name {
+            fn from(value: server::$name) -> Self {
+                Self { $($field: $convert(value.$field)),* }
+            }
+        }
+    )*};
+}
+
+/// Converts enums without fields both ways.
+macro_rules! convert_unit_enums {
+    ($($name:ident { $($variant:ident),* $(,)? })*) => {$(
+        impl From<$name> for server::$name {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => Self::$variant),*
+                }
+            }
+        }
+
+        impl From<server::$name> for $name {
+            fn from(value: server::$name) -> Self {
+                match value {
+                    $(server::$name::$variant => Self::$variant),*
+                }
+            }
+        }
+    )*};
+}
+
+convert_unit_enums! {
+    HubPermission {
+        All,
+        ReadChannels,
+        WriteChannels,
+        Administrate,
+        ManageChannels,
+        Mute,
+        Unmute,
+        Kick,
+        Ban,
+        Unban,
+    }
+    ChannelPermission { Write, Read, Manage, All }
+}
+
+convert_structs! {
+    Channel {
+        id: From::from,
+        hub_id: From::from,
+        description: From::from,
+        name: From::from,
+        created: From::from,
+    }
+    HubMember {
+        user_id: From::from,
+        joined: From::from,
+        hub: From::from,
+        nickname: From::from,
+        groups: From::from,
+        hub_permissions: map,
+        channel_permissions: nested_map,
+    }
+    PermissionGroup {
+        id: From::from,
+        name: From::from,
+        members: From::from,
+        hub_permissions: map,
+        channel_permissions: nested_map,
+        created: From::from,
+    }
+    Hub {
+        channels: map,
+        members: map,
+        bans: From::from,
+        mutes: From::from,
+        description: From::from,
+        owner: From::from,
+        groups: map,
+        default_group: From::from,
+        name: From::from,
+        id: From::from,
+        created: From::from,
+    }
+    Message {
+        id: From::from,
+        hub_id: From::from,
+        channel_id: From::from,
+        sender: From::from,
+        created: From::from,
+        content: From::from,
+    }
+    HttpHubUpdate {
+        name: From::from,
+        description: From::from,
+        default_group: From::from,
+    }
+    HttpChannelUpdate {
+        name: From::from,
+        description: From::from,
+    }
+    HttpSetPermission {
+        setting: From::from,
+    }
+    HttpMemberStatus {
+        muted: From::from,
+        banned: From::from,
+        member: From::from,
+    }
+    HttpMessagesAfterQuery {
+        from: From::from,
+        max: From::from,
+    }
+    HttpMessagesBeforeQuery {
+        to: From::from,
+        max: From::from,
+    }
+    HttpLastMessagesQuery {
+        max: From::from,
+    }
+    HttpMessagesBetweenQuery {
+        from: From::from,
+        to: From::from,
+        max: From::from,
+        new_to_old: From::from,
+    }
+}
+
+impl From<ApiError> for server_error::ApiError {
+    fn from(error: ApiError) -> Self {
+        match error {
+            ApiError::NotFound => Self::NotFound,
+            ApiError::Muted => Self::Muted,
+            ApiError::Other(message) => Self::Other(message),
+        }
+    }
+}
+
+impl From<server_error::ApiError> for ApiError {
+    fn from(error: server_error::ApiError) -> Self {
+        match error {
+            server_error::ApiError::NotFound => Self::NotFound,
+            server_error::ApiError::Muted => Self::Muted,
+            server_error::ApiError::Other(message) => Self::Other(message),
+        }
+    }
+}
+
+impl From<server_error::ApiError> for crate::Error {
+    fn from(error: server_error::ApiError) -> Self {
+        Self::WICRSError(error.into())
+    }
+}
+
+impl<T, S: From<T>> From<Response<T>> for server::Response<S> {
+    fn from(response: Response<T>) -> Self {
+        match response {
+            Response::Success(value) => Self::Success(value.into()),
+            Response::Error(error) => Self::Error(error.into()),
+        }
+    }
+}
+
+impl<S, T: From<S>> From<server::Response<S>> for Response<T> {
+    fn from(response: server::Response<S>) -> Self {
+        match response {
+            server::Response::Success(value) => Self::Success(value.into()),
+            server::Response::Error(error) => Self::Error(error.into()),
+        }
+    }
+}
+
+/// Converts the websocket enums both ways, the variants with fields have the same field names on
+/// both sides.
+macro_rules! convert_messages {
+    ($($name:ident {
+        $($variant:ident $({ $($field:ident),* })? $(( $($tuple:ident),* ))?),* $(,)?
+    })*) => {$(
+        impl From<$name> for server::$name {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant $({ $($field),* })? $(( $($tuple),* ))? => {
+                        Self::$variant $({ $($field: $field.into()),* })?
+                            $(( $($tuple.into()),* ))?
+                    })*
+                }
+            }
+        }
+
+        impl From<server::$name> for $name {
+            fn from(value: server::$name) -> Self {
+                match value {
+                    $(server::$name::$variant $({ $($field),* })? $(( $($tuple),* ))? => {
+                        Self::$variant $({ $($field: $field.into()),* })?
+                            $(( $($tuple.into()),* ))?
+                    })*
+                }
+            }
+        }
+    )*};
+}
+
+convert_messages! {
+    WsClientMessage {
+        SubscribeHub { hub_id },
+        UnsubscribeHub { hub_id },
+        SubscribeChannel { hub_id, channel_id },
+        UnsubscribeChannel { hub_id, channel_id },
+        StartTyping { hub_id, channel_id },
+        StopTyping { hub_id, channel_id },
+        SendMessage { hub_id, channel_id, message },
+    }
+    WsHubUpdateType {
+        HubDeleted,
+        HubUpdated,
+        UserJoined(user),
+        UserLeft(user),
+        UserBanned(user),
+        UserUnbanned(user),
+        UserMuted(user),
+        UserUnmuted(user),
+        UserKicked(user),
+        UserHubPermissionChanged(user),
+        UserChannelPermissionChanged(user, channel),
+        ChannelCreated(channel),
+        ChannelDeleted(channel),
+        ChannelUpdated(channel),
+    }
+    WsServerMessage {
+        Error(error),
+        InvalidCommand,
+        NotSigned,
+        CommandFailed,
+        ChatMessage { sender_id, hub_id, channel_id, message_id, message },
+        HubUpdated { hub_id, update_type },
+        Success,
+        UserStartedTyping { user_id, hub_id, channel_id },
+        UserStoppedTyping { user_id, hub_id, channel_id },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::Value;
+
+    use super::*;
+
+    fn fixture(name: &str) -> Vec<Value> {
+        let path = format!(
+            "{}/tests/fixtures/protocol/{}.json",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    /// Checks that every value of a fixture is written the same way after converting it to the
+    /// server's type and back.
+    fn compatible<C, S>(name: &str)
+    where
+        C: Serialize + DeserializeOwned + From<S>,
+        S: Serialize + DeserializeOwned + From<C>,
+    {
+        for value in fixture(name) {
+            let client: C = serde_json::from_value(value.clone()).unwrap();
+            let server = S::from(client);
+            assert_eq!(serde_json::to_value(&server).unwrap(), value, "{}", name);
+            let server: S = serde_json::from_value(value.clone()).unwrap();
+            let client = C::from(server);
+            assert_eq!(serde_json::to_value(&client).unwrap(), value, "{}", name);
+        }
+    }
+
+    #[test]
+    fn api_errors() {
+        compatible::<ApiError, server_error::ApiError>("api_error");
+        let error = crate::Error::from(server_error::ApiError::Muted);
+        assert!(matches!(error, crate::Error::WICRSError(ApiError::Muted)));
+    }
+
+    #[test]
+    fn permission_settings() {
+        compatible::<HttpSetPermission, server::HttpSetPermission>("set_permission");
+    }
+
+    #[test]
+    fn response_envelopes() {
+        compatible::<Response<Value>, server::Response<Value>>("response");
+        compatible::<Response<Message>, server::Response<server::Message>>("response_message");
+    }
+
+    #[test]
+    fn hubs() {
+        compatible::<Hub, server::Hub>("hub");
+    }
+
+    #[test]
+    fn websocket_messages() {
+        compatible::<WsClientMessage, server::WsClientMessage>("ws_client_message");
+        compatible::<WsServerMessage, server::WsServerMessage>("ws_server_message");
+    }
+}
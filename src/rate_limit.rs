@@ -0,0 +1,124 @@
+//! Client side rate limiting.
+
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
+
+/// Longest time [`TokenBucket::take`] asks to wait at once, very low rates would otherwise need
+/// waits too long for a [`Duration`].
+const MAX_WAIT: Duration = Duration::from_secs(60 * 60);
+
+/// Checks that `rate` is a usable number of requests per second.
+pub(crate) fn check_rate(rate: f64) -> Result<()> {
+    if rate.is_finite() && rate > 0.0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidRate(rate))
+    }
+}
+
+/// Checks that `burst` lets at least one request through.
+pub(crate) fn check_burst(burst: u32) -> Result<()> {
+    if burst > 0 {
+        Ok(())
+    } else {
+        Err(Error::InvalidBurst)
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(burst: u32) -> Self {
+        Self {
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is available, otherwise returns how long until the next one is.
+    pub(crate) fn take(&mut self, burst: u32, rate: f64) -> std::result::Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = ((1.0 - self.tokens) / rate).min(MAX_WAIT.as_secs_f64());
+            Err(Duration::from_secs_f64(wait))
+        }
+    }
+}
+
+/// Limits requests to `rate` per second with bursts of up to `burst` requests, waiting requests
+/// are let through in order.
+#[derive(Debug)]
+pub struct RateLimiter {
+    burst: u32,
+    rate: f64,
+    bucket: tokio::sync::Mutex<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Fails with [`Error::InvalidRate`] unless `rate` is positive and finite, and with
+    /// [`Error::InvalidBurst`] if `burst` is 0.
+    pub fn new(burst: u32, rate: f64) -> Result<Self> {
+        check_rate(rate)?;
+        check_burst(burst)?;
+        Ok(Self {
+            burst,
+            rate,
+            bucket: tokio::sync::Mutex::new(TokenBucket::new(burst)),
+        })
+    }
+
+    /// Waits until a request may be sent.
+    pub async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        while let Err(wait) = bucket.take(self.burst, self.rate) {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_unusable_rates() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                RateLimiter::new(1, rate),
+                Err(Error::InvalidRate(_))
+            ));
+        }
+        assert!(RateLimiter::new(1, 0.5).is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_bursts() {
+        assert!(matches!(RateLimiter::new(0, 1.0), Err(Error::InvalidBurst)));
+    }
+
+    #[test]
+    fn waits_for_the_next_token() {
+        let mut bucket = TokenBucket::new(1);
+        assert!(bucket.take(1, 2.0).is_ok());
+        let wait = bucket.take(1, 2.0).unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn caps_waits_for_tiny_rates() {
+        let mut bucket = TokenBucket::new(1);
+        assert!(bucket.take(1, f64::MIN_POSITIVE).is_ok());
+        assert_eq!(bucket.take(1, f64::MIN_POSITIVE).unwrap_err(), MAX_WAIT);
+    }
+}
@@ -0,0 +1,349 @@
+//! Read markers and unread counts per channel.
+//!
+//! The last read message of every channel is kept in a JSON file so that unread counts survive
+//! restarts, messages are counted from `ChatMessage` events and from history fetched after the
+//! marker. A message mentions the user if it contains `<@user_id>`.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::{Message, WsServerMessage, ID},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelReadState {
+    pub hub_id: ID,
+    pub channel_id: ID,
+    /// Last message the user has read.
+    pub last_read: Option<ID>,
+    pub unread: usize,
+    pub mentions: usize,
+}
+
+/// Unread counts of events are saved at most this often, moved markers are saved right away.
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub struct ReadState {
+    user_id: ID,
+    path: Option<PathBuf>,
+    channels: Mutex<HashMap<(ID, ID), ChannelReadState>>,
+    last_saved: Mutex<Instant>,
+    /// Whether counts changed since the last save.
+    unsaved: AtomicBool,
+}
+
+impl ReadState {
+    /// Read state that is only kept in memory.
+    pub fn new(user_id: ID) -> Self {
+        Self {
+            user_id,
+            path: None,
+            channels: Mutex::new(HashMap::new()),
+            last_saved: Mutex::new(Instant::now()),
+            unsaved: AtomicBool::new(false),
+        }
+    }
+
+    /// Loads the read state stored at `path`, it is saved there whenever a marker moves, at
+    /// most every [`SAVE_INTERVAL`] when events are counted and when dropped.
+    pub fn open(user_id: ID, path: PathBuf) -> Result<Self> {
+        let channels = if path.exists() {
+            serde_json::from_slice::<Vec<ChannelReadState>>(&fs::read(&path)?)?
+                .into_iter()
+                .map(|state| ((state.hub_id, state.channel_id), state))
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        Ok(Self {
+            user_id,
+            path: Some(path),
+            channels: Mutex::new(channels),
+            last_saved: Mutex::new(Instant::now()),
+            unsaved: AtomicBool::new(false),
+        })
+    }
+
+    /// Writes the read state to its file. If that fails the state stays unsaved, so it is tried
+    /// again with the next change or when dropped.
+    pub fn save(&self) -> Result<()> {
+        if let Some(path) = &self.path {
+            // Cleared before taking the snapshot so changes made while writing are saved later.
+            self.unsaved.store(false, Ordering::SeqCst);
+            let written = serde_json::to_vec_pretty(&self.channels())
+                .map_err(Into::into)
+                .and_then(|json| {
+                    let temp = path.with_extension("tmp");
+                    fs::write(&temp, json)?;
+                    fs::rename(temp, path)?;
+                    Ok(())
+                });
+            match written {
+                Ok(()) => *self.last_saved.lock().unwrap() = Instant::now(),
+                Err(error) => {
+                    self.unsaved.store(true, Ordering::SeqCst);
+                    return Err(error);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves changed counts if the last save was at least [`SAVE_INTERVAL`] ago.
+    fn save_debounced(&self) -> Result<()> {
+        self.unsaved.store(true, Ordering::SeqCst);
+        if self.last_saved.lock().unwrap().elapsed() >= SAVE_INTERVAL {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn channels(&self) -> Vec<ChannelReadState> {
+        self.channels.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn channel(&self, hub_id: ID, channel_id: ID) -> ChannelReadState {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(&(hub_id, channel_id))
+            .cloned()
+            .unwrap_or_else(|| ChannelReadState {
+                hub_id,
+                channel_id,
+                ..Default::default()
+            })
+    }
+
+    pub fn unread(&self, hub_id: ID, channel_id: ID) -> usize {
+        self.channel(hub_id, channel_id).unread
+    }
+
+    pub fn mentions(&self, hub_id: ID, channel_id: ID) -> usize {
+        self.channel(hub_id, channel_id).mentions
+    }
+
+    /// Unread messages in all channels of a hub.
+    pub fn hub_unread(&self, hub_id: ID) -> usize {
+        self.hub_sum(hub_id, |state| state.unread)
+    }
+
+    pub fn hub_mentions(&self, hub_id: ID) -> usize {
+        self.hub_sum(hub_id, |state| state.mentions)
+    }
+
+    fn hub_sum<F: Fn(&ChannelReadState) -> usize>(&self, hub_id: ID, f: F) -> usize {
+        self.channels
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|state| state.hub_id == hub_id)
+            .map(f)
+            .sum()
+    }
+
+    /// Moves the read marker of a channel to `message_id`, clearing its unread counts.
+    pub fn mark_read(&self, hub_id: ID, channel_id: ID, message_id: ID) -> Result<()> {
+        self.channels.lock().unwrap().insert(
+            (hub_id, channel_id),
+            ChannelReadState {
+                hub_id,
+                channel_id,
+                last_read: Some(message_id),
+                unread: 0,
+                mentions: 0,
+            },
+        );
+        self.save()
+    }
+
+    /// Counts a `ChatMessage` event, messages sent by the user move the marker instead.
+    pub fn handle_event(&self, event: &WsServerMessage) -> Result<()> {
+        if let WsServerMessage::ChatMessage {
+            sender_id,
+            hub_id,
+            channel_id,
+            message_id,
+            message,
+        } = event
+        {
+            if *sender_id == self.user_id {
+                self.mark_read(*hub_id, *channel_id, *message_id)?;
+            } else {
+                let mention = self.mentions_user(message);
+                let mut channels = self.channels.lock().unwrap();
+                let state =
+                    channels
+                        .entry((*hub_id, *channel_id))
+                        .or_insert_with(|| ChannelReadState {
+                            hub_id: *hub_id,
+                            channel_id: *channel_id,
+                            ..Default::default()
+                        });
+                state.unread += 1;
+                if mention {
+                    state.mentions += 1;
+                }
+                drop(channels);
+                self.save_debounced()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the unread counts of a channel with those of the messages after its marker.
+    pub fn set_history(&self, hub_id: ID, channel_id: ID, messages: &[Message]) -> Result<()> {
+        let mut messages = messages;
+        // Everything up to the user's own last message has been read.
+        if let Some(index) = messages.iter().rposition(|m| m.sender == self.user_id) {
+            self.mark_read(hub_id, channel_id, messages[index].id)?;
+            messages = &messages[index + 1..];
+        }
+        let unread = messages.len();
+        let mentions = messages
+            .iter()
+            .filter(|message| self.mentions_user(&message.content))
+            .count();
+        let mut channels = self.channels.lock().unwrap();
+        let state = channels
+            .entry((hub_id, channel_id))
+            .or_insert_with(|| ChannelReadState {
+                hub_id,
+                channel_id,
+                ..Default::default()
+            });
+        state.unread = unread;
+        state.mentions = mentions;
+        drop(channels);
+        self.save()
+    }
+
+    /// Fetches up to `max` messages after the channel's marker to count the ones missed while
+    /// offline, channels without a marker are left alone.
+    pub async fn catch_up(
+        &self,
+        client: &HttpClient,
+        hub_id: ID,
+        channel_id: ID,
+        max: usize,
+    ) -> Result<()> {
+        if let Some(last_read) = self.channel(hub_id, channel_id).last_read {
+            let messages = client
+                .messages_get_after(hub_id, channel_id, last_read, max)
+                .await?;
+            self.set_history(hub_id, channel_id, &messages)?;
+        }
+        Ok(())
+    }
+
+    fn mentions_user(&self, content: &str) -> bool {
+        content.contains(&format!("<@{}>", self.user_id))
+    }
+}
+
+impl Drop for ReadState {
+    fn drop(&mut self) {
+        if self.unsaved.load(Ordering::SeqCst) {
+            let _ = self.save();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat_message(sender_id: ID, hub_id: ID, channel_id: ID, message: &str) -> WsServerMessage {
+        WsServerMessage::ChatMessage {
+            sender_id,
+            hub_id,
+            channel_id,
+            message_id: ID::new_v4(),
+            message: message.to_string(),
+        }
+    }
+
+    fn saved(path: &std::path::Path, hub_id: ID, channel_id: ID) -> ChannelReadState {
+        ReadState::open(ID::new_v4(), path.to_path_buf())
+            .unwrap()
+            .channel(hub_id, channel_id)
+    }
+
+    #[test]
+    fn saves_counted_events() {
+        let path = std::env::temp_dir().join(format!("wicrs-read-state-{}.json", ID::new_v4()));
+        let (user_id, hub_id, channel_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        let state = ReadState::open(user_id, path.clone()).unwrap();
+        let mention = format!("hi <@{}>", user_id);
+
+        // Within the save interval of opening, the count is only kept in memory.
+        state
+            .handle_event(&chat_message(ID::new_v4(), hub_id, channel_id, "hi"))
+            .unwrap();
+        assert!(!path.exists());
+
+        *state.last_saved.lock().unwrap() = Instant::now() - SAVE_INTERVAL;
+        state
+            .handle_event(&chat_message(ID::new_v4(), hub_id, channel_id, &mention))
+            .unwrap();
+        let channel = saved(&path, hub_id, channel_id);
+        assert_eq!((channel.unread, channel.mentions), (2, 1));
+
+        state
+            .handle_event(&chat_message(ID::new_v4(), hub_id, channel_id, "hi"))
+            .unwrap();
+        assert_eq!(saved(&path, hub_id, channel_id).unread, 2);
+        drop(state);
+        assert_eq!(saved(&path, hub_id, channel_id).unread, 3);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_saves_are_retried() {
+        let dir = std::env::temp_dir().join(format!("wicrs-read-state-{}", ID::new_v4()));
+        let path = dir.join("state.json");
+        let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+        let state = ReadState::open(ID::new_v4(), path.clone()).unwrap();
+        *state.last_saved.lock().unwrap() = Instant::now() - SAVE_INTERVAL;
+        let event = chat_message(ID::new_v4(), hub_id, channel_id, "hi");
+        assert!(state.handle_event(&event).is_err());
+        assert!(state.unsaved.load(Ordering::SeqCst));
+
+        fs::create_dir(&dir).unwrap();
+        drop(state);
+        assert_eq!(saved(&path, hub_id, channel_id).unread, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn own_messages_move_the_marker() {
+        let (user_id, hub_id, channel_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        let state = ReadState::new(user_id);
+        state
+            .handle_event(&chat_message(ID::new_v4(), hub_id, channel_id, "hi"))
+            .unwrap();
+        let own = chat_message(user_id, hub_id, channel_id, "hello");
+        state.handle_event(&own).unwrap();
+        let channel = state.channel(hub_id, channel_id);
+        assert_eq!(channel.unread, 0);
+        match own {
+            WsServerMessage::ChatMessage { message_id, .. } => {
+                assert_eq!(channel.last_read, Some(message_id))
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,656 @@
+//! Keeping an existing hub in line with a configuration file.
+//!
+//! A [`HubConfig`] is a [`HubSpec`] for a hub that already exists, loaded from JSON, TOML
+//! (`config-toml` feature) or YAML (`config-yaml` feature). [`HubConfig::plan`] compares it with
+//! the hub on the server and lists the differences, [`HubConfig::reconcile`] applies them.
+//! Channels are matched by name, so names have to be unique in the file and among the hub's
+//! channels that are in the file. Channels on the server that are not in the file are reported
+//! but never deleted, and only the permissions listed in the file are checked.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::WicrsApi,
+    error::{Error, Result},
+    protocol::{ChannelPermission, HttpChannelUpdate, HubPermission, PermissionSetting, ID},
+    spec::HubSpec,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HubConfig {
+    pub hub_id: ID,
+    #[serde(flatten)]
+    pub spec: HubSpec,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Json,
+    #[cfg(feature = "config-toml")]
+    Toml,
+    #[cfg(feature = "config-yaml")]
+    Yaml,
+}
+
+impl ConfigFormat {
+    /// Format of a file going by its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path.as_ref().extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            #[cfg(feature = "config-toml")]
+            "toml" => Some(Self::Toml),
+            #[cfg(feature = "config-yaml")]
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+}
+
+impl HubConfig {
+    pub fn from_str(config: &str, format: ConfigFormat) -> Result<Self> {
+        let config: Self = match format {
+            ConfigFormat::Json => serde_json::from_str(config)?,
+            #[cfg(feature = "config-toml")]
+            ConfigFormat::Toml => toml::from_str(config)?,
+            #[cfg(feature = "config-yaml")]
+            ConfigFormat::Yaml => serde_yaml::from_str(config)?,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Fails with [`Error::DuplicateChannel`] if a channel name is used more than once.
+    pub fn validate(&self) -> Result<()> {
+        let mut names = HashSet::new();
+        match self
+            .spec
+            .channels
+            .iter()
+            .find(|channel| !names.insert(&channel.name))
+        {
+            Some(channel) => Err(Error::DuplicateChannel(channel.name.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = ConfigFormat::from_path(path)
+            .ok_or_else(|| Error::UnknownConfigFormat(path.display().to_string()))?;
+        Self::from_str(&fs::read_to_string(path)?, format)
+    }
+}
+
+/// A difference between the configuration and the hub on the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    HubName {
+        from: String,
+        to: String,
+    },
+    HubDescription {
+        from: String,
+        to: String,
+    },
+    HubDefaultGroup {
+        from: ID,
+        to: ID,
+    },
+    CreateChannel {
+        name: String,
+    },
+    ChannelDescription {
+        channel: String,
+        from: String,
+        to: String,
+    },
+    HubPermission {
+        member: ID,
+        permission: HubPermission,
+        from: PermissionSetting,
+        to: PermissionSetting,
+    },
+    ChannelPermission {
+        channel: String,
+        member: ID,
+        permission: ChannelPermission,
+        from: PermissionSetting,
+        to: PermissionSetting,
+    },
+    /// A channel that is not in the configuration, only reported.
+    UnmanagedChannel {
+        name: String,
+        id: ID,
+    },
+}
+
+impl Change {
+    /// Whether [`HubConfig::reconcile`] makes a request for this change.
+    pub fn is_applied(&self) -> bool {
+        !matches!(self, Change::UnmanagedChannel { .. })
+    }
+}
+
+fn setting(setting: &PermissionSetting) -> &'static str {
+    match setting {
+        Some(true) => "allowed",
+        Some(false) => "denied",
+        None => "unset",
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::HubName { from, to } => write!(f, "~ hub name: {:?} -> {:?}", from, to),
+            Change::HubDescription { from, to } => {
+                write!(f, "~ hub description: {:?} -> {:?}", from, to)
+            }
+            Change::HubDefaultGroup { from, to } => {
+                write!(f, "~ hub default group: {} -> {}", from, to)
+            }
+            Change::CreateChannel { name } => write!(f, "+ channel #{}", name),
+            Change::ChannelDescription { channel, from, to } => {
+                write!(f, "~ #{} description: {:?} -> {:?}", channel, from, to)
+            }
+            Change::HubPermission {
+                member,
+                permission,
+                from,
+                to,
+            } => write!(
+                f,
+                "~ {} hub permission {}: {} -> {}",
+                member,
+                permission,
+                setting(from),
+                setting(to)
+            ),
+            Change::ChannelPermission {
+                channel,
+                member,
+                permission,
+                from,
+                to,
+            } => write!(
+                f,
+                "~ {} #{} permission {}: {} -> {}",
+                member,
+                channel,
+                permission,
+                setting(from),
+                setting(to)
+            ),
+            Change::UnmanagedChannel { name, id } => {
+                write!(
+                    f,
+                    "? channel #{} ({}) is not in the configuration",
+                    name, id
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub hub_id: ID,
+    pub changes: Vec<Change>,
+    /// IDs of the channels that already exist, by name.
+    channels: HashMap<String, ID>,
+}
+
+impl Plan {
+    /// Whether the hub has drifted from the configuration, unmanaged channels are not counted.
+    pub fn has_changes(&self) -> bool {
+        self.changes.iter().any(Change::is_applied)
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.changes.is_empty() {
+            return writeln!(f, "hub {} is up to date", self.hub_id);
+        }
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        Ok(())
+    }
+}
+
+impl HubConfig {
+    /// Compares the configuration with the hub on the server. Fails if a channel name is in the
+    /// configuration more than once or if the hub has more than one channel with a name that is.
+    pub async fn plan<A>(&self, api: &A) -> Result<Plan>
+    where
+        A: WicrsApi + ?Sized,
+    {
+        self.validate()?;
+        let hub = api.hub_get(self.hub_id).await?;
+        let spec = &self.spec;
+        let mut changes = Vec::new();
+        if hub.name != spec.name {
+            changes.push(Change::HubName {
+                from: hub.name.clone(),
+                to: spec.name.clone(),
+            });
+        }
+        if let Some(description) = spec.description.as_ref().filter(|d| **d != hub.description) {
+            changes.push(Change::HubDescription {
+                from: hub.description.clone(),
+                to: description.clone(),
+            });
+        }
+        if let Some(group) = spec.default_group.filter(|g| *g != hub.default_group) {
+            changes.push(Change::HubDefaultGroup {
+                from: hub.default_group,
+                to: group,
+            });
+        }
+        for permission in &spec.permissions {
+            let current = api
+                .member_get_hub_permission(self.hub_id, permission.member, permission.permission)
+                .await?;
+            if current != permission.setting {
+                changes.push(Change::HubPermission {
+                    member: permission.member,
+                    permission: permission.permission,
+                    from: current,
+                    to: permission.setting,
+                });
+            }
+        }
+
+        let mut channels = HashMap::new();
+        for channel in hub.channels.values() {
+            let managed = spec.channels.iter().any(|c| c.name == channel.name);
+            if channels.insert(channel.name.clone(), channel.id).is_some() && managed {
+                return Err(Error::AmbiguousChannel(channel.name.clone()));
+            }
+        }
+        for channel in &spec.channels {
+            let existing = match channels.get(&channel.name) {
+                Some(id) => Some(api.channel_get(self.hub_id, *id).await?),
+                None => {
+                    changes.push(Change::CreateChannel {
+                        name: channel.name.clone(),
+                    });
+                    None
+                }
+            };
+            let description = existing
+                .as_ref()
+                .map(|existing| existing.description.clone())
+                .unwrap_or_default();
+            if let Some(wanted) = channel.description.as_ref().filter(|d| **d != description) {
+                changes.push(Change::ChannelDescription {
+                    channel: channel.name.clone(),
+                    from: description,
+                    to: wanted.clone(),
+                });
+            }
+            for permission in &channel.permissions {
+                let current = match &existing {
+                    Some(existing) => {
+                        api.member_get_channel_permission(
+                            self.hub_id,
+                            permission.member,
+                            existing.id,
+                            permission.permission,
+                        )
+                        .await?
+                    }
+                    None => None,
+                };
+                if existing.is_none() || current != permission.setting {
+                    changes.push(Change::ChannelPermission {
+                        channel: channel.name.clone(),
+                        member: permission.member,
+                        permission: permission.permission,
+                        from: current,
+                        to: permission.setting,
+                    });
+                }
+            }
+        }
+        let mut unmanaged = hub
+            .channels
+            .values()
+            .filter(|channel| !spec.channels.iter().any(|c| c.name == channel.name))
+            .collect::<Vec<_>>();
+        unmanaged.sort_by(|a, b| a.name.cmp(&b.name));
+        changes.extend(
+            unmanaged
+                .into_iter()
+                .map(|channel| Change::UnmanagedChannel {
+                    name: channel.name.clone(),
+                    id: channel.id,
+                }),
+        );
+
+        Ok(Plan {
+            hub_id: self.hub_id,
+            changes,
+            channels,
+        })
+    }
+
+    /// Applies a plan made by [`HubConfig::plan`], stopping at the first failed request.
+    pub async fn apply<A>(&self, api: &A, plan: &Plan) -> Result<()>
+    where
+        A: WicrsApi + ?Sized,
+    {
+        let hub = plan.hub_id;
+        let mut name = None;
+        let mut description = None;
+        let mut default_group = None;
+        for change in &plan.changes {
+            match change {
+                Change::HubName { to, .. } => name = Some(to.clone()),
+                Change::HubDescription { to, .. } => description = Some(to.clone()),
+                Change::HubDefaultGroup { to, .. } => default_group = Some(*to),
+                _ => {}
+            }
+        }
+        if name.is_some() || description.is_some() || default_group.is_some() {
+            api.hub_update(hub, name, description, default_group)
+                .await?;
+        }
+
+        let mut channels = plan.channels.clone();
+        let channel_id = |channels: &HashMap<String, ID>, name: &String| {
+            channels
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::UnknownChannel(name.clone()))
+        };
+        for change in &plan.changes {
+            match change {
+                Change::CreateChannel { name } => {
+                    let id = api.channel_create(hub, name.clone()).await?;
+                    channels.insert(name.clone(), id);
+                }
+                Change::ChannelDescription { channel, to, .. } => {
+                    let update = HttpChannelUpdate {
+                        name: None,
+                        description: Some(to.clone()),
+                    };
+                    api.channel_update(hub, channel_id(&channels, channel)?, update)
+                        .await?;
+                }
+                Change::HubPermission {
+                    member,
+                    permission,
+                    to,
+                    ..
+                } => {
+                    api.member_set_hub_permission(hub, *member, *permission, *to)
+                        .await?;
+                }
+                Change::ChannelPermission {
+                    channel,
+                    member,
+                    permission,
+                    to,
+                    ..
+                } => {
+                    api.member_set_channel_permission(
+                        hub,
+                        *member,
+                        channel_id(&channels, channel)?,
+                        *permission,
+                        *to,
+                    )
+                    .await?;
+                }
+                Change::HubName { .. }
+                | Change::HubDescription { .. }
+                | Change::HubDefaultGroup { .. }
+                | Change::UnmanagedChannel { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Plans and applies the changes needed to match the configuration, returning the plan.
+    pub async fn reconcile<A>(&self, api: &A) -> Result<Plan>
+    where
+        A: WicrsApi + ?Sized,
+    {
+        let plan = self.plan(api).await?;
+        self.apply(api, &plan).await?;
+        Ok(plan)
+    }
+}
+
+#[cfg(all(test, feature = "use-tokio"))]
+mod tests {
+    use chrono::Utc;
+
+    use super::*;
+    use crate::{
+        fake::FakeApi,
+        protocol::{Channel, Hub},
+        spec::ChannelSpec,
+    };
+
+    fn hub(hub_id: ID, channels: &[(&str, ID)]) -> Hub {
+        Hub {
+            channels: channels
+                .iter()
+                .map(|(name, id)| {
+                    let channel = Channel {
+                        id: *id,
+                        hub_id,
+                        description: String::new(),
+                        name: name.to_string(),
+                        created: Utc::now(),
+                    };
+                    (*id, channel)
+                })
+                .collect(),
+            members: HashMap::new(),
+            bans: Default::default(),
+            mutes: Default::default(),
+            description: String::new(),
+            owner: ID::new_v4(),
+            groups: HashMap::new(),
+            default_group: ID::nil(),
+            name: "hub".to_string(),
+            id: hub_id,
+            created: Utc::now(),
+        }
+    }
+
+    /// A fake serving `hub` from `hub_get` and its channels from `channel_get`, permissions are
+    /// unset unless scripted.
+    fn api(hub: Hub) -> FakeApi {
+        let api = FakeApi::new(ID::new_v4());
+        let channels = hub.channels.clone();
+        api.respond("hub_get", hub).unwrap();
+        api.respond("member_get_hub_permission", None::<bool>)
+            .unwrap();
+        api.respond("member_get_channel_permission", None::<bool>)
+            .unwrap();
+        api.on("channel_get", move |call| {
+            let id: ID = serde_json::from_value(call.args[1].clone())?;
+            Ok(serde_json::to_value(&channels[&id])?)
+        });
+        api
+    }
+
+    fn config(hub_id: ID, spec: HubSpec) -> HubConfig {
+        HubConfig { hub_id, spec }
+    }
+
+    #[tokio::test]
+    async fn lists_the_differences() {
+        let (hub_id, general, old, member) =
+            (ID::new_v4(), ID::new_v4(), ID::new_v4(), ID::new_v4());
+        let api = api(hub(hub_id, &[("general", general), ("old", old)]));
+        api.respond_once("member_get_channel_permission", Some(true))
+            .unwrap();
+        let config = config(
+            hub_id,
+            HubSpec::new("renamed")
+                .channel(
+                    ChannelSpec::new("general")
+                        .description("talk")
+                        .permission(member, ChannelPermission::Write, Some(true))
+                        .permission(member, ChannelPermission::Read, Some(true)),
+                )
+                .channel(ChannelSpec::new("news").permission(
+                    member,
+                    ChannelPermission::Write,
+                    Some(false),
+                ))
+                .permission(member, HubPermission::Mute, None),
+        );
+
+        let plan = config.plan(&api).await.unwrap();
+        assert_eq!(
+            plan.changes,
+            [
+                Change::HubName {
+                    from: "hub".to_string(),
+                    to: "renamed".to_string(),
+                },
+                Change::ChannelDescription {
+                    channel: "general".to_string(),
+                    from: String::new(),
+                    to: "talk".to_string(),
+                },
+                Change::ChannelPermission {
+                    channel: "general".to_string(),
+                    member,
+                    permission: ChannelPermission::Read,
+                    from: None,
+                    to: Some(true),
+                },
+                Change::CreateChannel {
+                    name: "news".to_string(),
+                },
+                Change::ChannelPermission {
+                    channel: "news".to_string(),
+                    member,
+                    permission: ChannelPermission::Write,
+                    from: None,
+                    to: Some(false),
+                },
+                Change::UnmanagedChannel {
+                    name: "old".to_string(),
+                    id: old,
+                },
+            ]
+        );
+        assert!(plan.has_changes());
+    }
+
+    #[tokio::test]
+    async fn matching_hubs_have_no_changes() {
+        let (hub_id, general) = (ID::new_v4(), ID::new_v4());
+        let api = api(hub(hub_id, &[("general", general)]));
+        let config = config(
+            hub_id,
+            HubSpec::new("hub").channel(ChannelSpec::new("general")),
+        );
+        let plan = config.plan(&api).await.unwrap();
+        assert!(plan.changes.is_empty());
+        assert_eq!(plan.to_string(), format!("hub {} is up to date\n", hub_id));
+    }
+
+    #[tokio::test]
+    async fn rejects_duplicate_channel_names() {
+        let hub_id = ID::new_v4();
+        let json = format!(
+            r#"{{"hub_id": "{}", "name": "hub", "channels": [{{"name": "a"}}, {{"name": "a"}}]}}"#,
+            hub_id
+        );
+        assert!(matches!(
+            HubConfig::from_str(&json, ConfigFormat::Json),
+            Err(Error::DuplicateChannel(name)) if name == "a"
+        ));
+
+        let api = api(hub(hub_id, &[]));
+        let config = config(
+            hub_id,
+            HubSpec::new("hub")
+                .channel(ChannelSpec::new("a"))
+                .channel(ChannelSpec::new("a")),
+        );
+        assert!(matches!(
+            config.plan(&api).await,
+            Err(Error::DuplicateChannel(name)) if name == "a"
+        ));
+        assert!(api.calls().is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_hubs_with_duplicate_managed_channels() {
+        let hub_id = ID::new_v4();
+        let api = api(hub(
+            hub_id,
+            &[
+                ("a", ID::new_v4()),
+                ("a", ID::new_v4()),
+                ("b", ID::new_v4()),
+                ("b", ID::new_v4()),
+            ],
+        ));
+        let managed = config(hub_id, HubSpec::new("hub").channel(ChannelSpec::new("a")));
+        assert!(matches!(
+            managed.plan(&api).await,
+            Err(Error::AmbiguousChannel(name)) if name == "a"
+        ));
+
+        let unmanaged = config(hub_id, HubSpec::new("hub"));
+        let plan = unmanaged.plan(&api).await.unwrap();
+        assert_eq!(plan.changes.len(), 4);
+        assert!(!plan.has_changes());
+    }
+
+    #[tokio::test]
+    async fn applies_permissions_to_created_channels() {
+        let (hub_id, created, member) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        let api = api(hub(hub_id, &[]));
+        api.respond("channel_create", created).unwrap();
+        let config = config(
+            hub_id,
+            HubSpec::new("hub").channel(ChannelSpec::new("news").permission(
+                member,
+                ChannelPermission::Write,
+                Some(false),
+            )),
+        );
+        config.reconcile(&api).await.unwrap();
+        let calls = api.calls_to("member_set_channel_permission");
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].args[2], serde_json::to_value(created).unwrap());
+    }
+
+    #[tokio::test]
+    async fn plans_for_unknown_channels_fail_without_panicking() {
+        let api = FakeApi::new(ID::new_v4());
+        let config = config(ID::new_v4(), HubSpec::new("hub"));
+        let plan = Plan {
+            hub_id: config.hub_id,
+            changes: vec![Change::ChannelDescription {
+                channel: "missing".to_string(),
+                from: String::new(),
+                to: "text".to_string(),
+            }],
+            channels: HashMap::new(),
+        };
+        assert!(matches!(
+            config.apply(&api, &plan).await,
+            Err(Error::UnknownChannel(name)) if name == "missing"
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+//! Relays websocket events to HTTP webhooks.
+//!
+//! Every delivery is a JSON `POST` signed with [`sign_timestamped`] over the time of the attempt
+//! and the raw body. The signature is sent in the [`SIGNATURE_HEADER`] header and the time in
+//! [`TIMESTAMP_HEADER`], receivers check both with [`verify_timestamped`] and reject old
+//! timestamps so captured deliveries can not be replayed.
+
+use std::{
+    collections::HashSet,
+    fs::OpenOptions,
+    io::Write,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use reqwest::{header::HeaderValue, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::unbounded_channel;
+
+pub use crate::signature::{
+    sign, sign_timestamped, verify, verify_timestamped, SIGNATURE_HEADER, TIMESTAMP_HEADER,
+};
+use crate::{
+    error::Result,
+    protocol::{WsServerMessage, ID},
+    websocket::asyncws::WebsocketClient,
+};
+
+pub const EVENT_HEADER: &str = "x-wicrs-event";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    ChatMessage,
+    HubUpdated,
+    Other,
+}
+
+impl EventKind {
+    pub fn of(message: &WsServerMessage) -> Self {
+        match message {
+            WsServerMessage::ChatMessage { .. } => EventKind::ChatMessage,
+            WsServerMessage::HubUpdated { .. } => EventKind::HubUpdated,
+            _ => EventKind::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::ChatMessage => "chat_message",
+            EventKind::HubUpdated => "hub_updated",
+            EventKind::Other => "other",
+        }
+    }
+}
+
+/// Decides which events are sent to a target, `None` fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct EventFilter {
+    pub events: Option<HashSet<EventKind>>,
+    pub hubs: Option<HashSet<ID>>,
+    pub channels: Option<HashSet<(ID, ID)>>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, message: &WsServerMessage) -> bool {
+        if let Some(events) = &self.events {
+            if !events.contains(&EventKind::of(message)) {
+                return false;
+            }
+        }
+        let (hub_id, channel_id) = match message {
+            WsServerMessage::ChatMessage {
+                hub_id, channel_id, ..
+            } => (Some(*hub_id), Some(*channel_id)),
+            WsServerMessage::HubUpdated { hub_id, .. } => (Some(*hub_id), None),
+            _ => (None, None),
+        };
+        if let (Some(hubs), Some(hub_id)) = (&self.hubs, hub_id) {
+            if !hubs.contains(&hub_id) {
+                return false;
+            }
+        }
+        if let (Some(channels), Some(hub_id), Some(channel_id)) =
+            (&self.channels, hub_id, channel_id)
+        {
+            if !channels.contains(&(hub_id, channel_id)) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookTarget {
+    pub url: String,
+    pub secret: String,
+    pub filter: EventFilter,
+}
+
+impl WebhookTarget {
+    pub fn new(url: String, secret: String) -> Self {
+        Self {
+            url,
+            secret,
+            filter: EventFilter::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: EventFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayConfig {
+    pub hubs: Vec<ID>,
+    pub channels: Vec<(ID, ID)>,
+    pub targets: Vec<WebhookTarget>,
+    /// Number of delivery attempts before a payload is dead-lettered.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after every failed attempt.
+    pub retry_delay: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            hubs: Vec::new(),
+            channels: Vec::new(),
+            targets: Vec::new(),
+            max_attempts: 5,
+            retry_delay: Duration::from_millis(500),
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The body POSTed to webhook targets.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub event: EventKind,
+    pub timestamp: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+/// A payload that could not be delivered after all attempts.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub url: String,
+    pub payload: WebhookPayload,
+    pub attempts: u32,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+pub trait DeadLetterStore: Send + Sync {
+    fn store(&self, letter: DeadLetter) -> Result<()>;
+}
+
+/// Appends dead letters to a file, one JSON object per line.
+#[derive(Debug, Clone)]
+pub struct FileDeadLetterStore {
+    pub path: PathBuf,
+}
+
+impl DeadLetterStore for FileDeadLetterStore {
+    fn store(&self, letter: DeadLetter) -> Result<()> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut line = serde_json::to_vec(&letter)?;
+        line.push(b'\n');
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryDeadLetterStore {
+    letters: Mutex<Vec<DeadLetter>>,
+}
+
+impl MemoryDeadLetterStore {
+    pub fn letters(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().clone()
+    }
+}
+
+impl DeadLetterStore for MemoryDeadLetterStore {
+    fn store(&self, letter: DeadLetter) -> Result<()> {
+        self.letters.lock().unwrap().push(letter);
+        Ok(())
+    }
+}
+
+pub struct WebhookRelay {
+    config: RelayConfig,
+    client: Client,
+    dead_letters: Arc<dyn DeadLetterStore>,
+}
+
+impl WebhookRelay {
+    pub fn new(config: RelayConfig, dead_letters: Arc<dyn DeadLetterStore>) -> Result<Self> {
+        let client = Client::builder()
+            .user_agent("WICRS Webhook Relay")
+            .timeout(config.timeout)
+            .build()?;
+        Ok(Self {
+            config,
+            client,
+            dead_letters,
+        })
+    }
+
+    /// Subscribes to the configured hubs and channels and relays events until the websocket
+    /// connection closes. Every target has its own queue, so a slow target only delays its own
+    /// deliveries. Deliveries already queued are finished before returning.
+    pub async fn run(self: Arc<Self>, websocket: Arc<WebsocketClient>) -> Result<()> {
+        let (send, mut recv) = unbounded_channel();
+        let ws_loop = tokio::spawn(
+            Arc::clone(&websocket)
+                .start_loop(move |_, message| send.send(message).err().map(|_| ())),
+        );
+        let (queues, workers): (Vec<_>, Vec<_>) = (0..self.config.targets.len())
+            .map(|index| {
+                let (queue, mut pending) = unbounded_channel::<(WebhookPayload, Arc<[u8]>)>();
+                let relay = Arc::clone(&self);
+                let worker = tokio::spawn(async move {
+                    while let Some((payload, body)) = pending.recv().await {
+                        relay
+                            .deliver(&relay.config.targets[index], &payload, &body)
+                            .await;
+                    }
+                });
+                (queue, worker)
+            })
+            .unzip();
+        let relayed = async {
+            websocket.wait_for_loop().await;
+            for hub_id in &self.config.hubs {
+                websocket.subscribe_hub(*hub_id).await?;
+            }
+            for (hub_id, channel_id) in &self.config.channels {
+                websocket.subscribe_channel(*hub_id, *channel_id).await?;
+            }
+            while let Some(message) = recv.recv().await {
+                let (payload, body) = payload(&message)?;
+                let body = Arc::<[u8]>::from(body);
+                for (target, queue) in self.config.targets.iter().zip(&queues) {
+                    if target.filter.matches(&message) {
+                        let _ = queue.send((payload.clone(), Arc::clone(&body)));
+                    }
+                }
+            }
+            Ok(())
+        }
+        .await;
+        drop(queues);
+        join_all(workers).await;
+        if let Err(error) = relayed {
+            ws_loop.abort();
+            return Err(error);
+        }
+        match ws_loop.await {
+            Ok(result) => result,
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Delivers a single event to every target whose filter matches it.
+    pub async fn dispatch(&self, message: &WsServerMessage) -> Result<()> {
+        let (payload, body) = payload(message)?;
+        let deliveries = self
+            .config
+            .targets
+            .iter()
+            .filter(|target| target.filter.matches(message))
+            .map(|target| self.deliver(target, &payload, &body));
+        join_all(deliveries).await;
+        Ok(())
+    }
+
+    /// Delivers a payload to a target, retrying with backoff and dead-lettering it after the
+    /// last attempt or a response that will not change on retry. Dead letters that can not be
+    /// stored are logged with `tracing` when the feature is enabled.
+    async fn deliver(&self, target: &WebhookTarget, payload: &WebhookPayload, body: &[u8]) {
+        let mut delay = self.config.retry_delay;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let timestamp = Utc::now().timestamp();
+            let signature = sign_timestamped(target.secret.as_bytes(), timestamp, body);
+            let result = self
+                .client
+                .post(&target.url)
+                .header("content-type", HeaderValue::from_static("application/json"))
+                .header(SIGNATURE_HEADER, signature.as_str())
+                .header(TIMESTAMP_HEADER, timestamp)
+                .header(EVENT_HEADER, payload.event.as_str())
+                .body(body.to_vec())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            match result {
+                Ok(_) => return,
+                Err(error) if attempts >= self.config.max_attempts || !retryable(&error) => {
+                    let stored = self.dead_letters.store(DeadLetter {
+                        url: target.url.clone(),
+                        payload: payload.clone(),
+                        attempts,
+                        error: error.to_string(),
+                        failed_at: Utc::now(),
+                    });
+                    if let Err(error) = stored {
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(
+                            error = %error,
+                            url = %target.url,
+                            "storing dead letter failed"
+                        );
+                        #[cfg(not(feature = "tracing"))]
+                        let _ = error;
+                    }
+                    return;
+                }
+                Err(_) => {
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                }
+            }
+        }
+    }
+}
+
+/// Whether a failed delivery may succeed if retried, client errors other than timeouts and rate
+/// limiting are not.
+fn retryable(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) if status.is_client_error() => matches!(
+            status,
+            StatusCode::REQUEST_TIMEOUT | StatusCode::TOO_MANY_REQUESTS
+        ),
+        _ => true,
+    }
+}
+
+fn payload(message: &WsServerMessage) -> Result<(WebhookPayload, Vec<u8>)> {
+    let payload = WebhookPayload {
+        event: EventKind::of(message),
+        timestamp: Utc::now(),
+        data: serde_json::to_value(message)?,
+    };
+    let body = serde_json::to_vec(&payload)?;
+    Ok((payload, body))
+}
@@ -0,0 +1,319 @@
+//! Display names for mentions and channel references.
+//!
+//! Names are looked up per hub with a single `hub_get`, which returns every member and channel
+//! of the hub, and cached for a configurable time so that rendering a page of history costs at
+//! most one request.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    error::Result,
+    format::{self, Names, Node},
+    http::HttpClient,
+    protocol::{Message, ID},
+};
+
+/// Snapshot of the names of one hub's members and channels.
+#[derive(Debug, Clone, Default)]
+pub struct HubNames {
+    /// Nicknames of the members, empty for members without one.
+    pub users: HashMap<ID, String>,
+    pub channels: HashMap<ID, String>,
+}
+
+impl Names for HubNames {
+    fn user_name(&self, user_id: ID) -> Option<String> {
+        self.users
+            .get(&user_id)
+            .filter(|name| !name.is_empty())
+            .cloned()
+    }
+
+    fn channel_name(&self, channel_id: ID) -> Option<String> {
+        self.channels.get(&channel_id).cloned()
+    }
+}
+
+impl HubNames {
+    /// Replaces mentions and channel references in a message with `@name` and `#name`, the
+    /// rest of the markup is kept. References without a known name are left as they are.
+    pub fn decode(&self, text: &str) -> String {
+        format::to_markup(&self.name_references(format::parse(text)))
+    }
+
+    fn name_references(&self, nodes: Vec<Node>) -> Vec<Node> {
+        nodes
+            .into_iter()
+            .map(|node| match node {
+                Node::Mention(id) => match self.user_name(id) {
+                    Some(name) => Node::Text(format!("@{}", name)),
+                    None => node,
+                },
+                Node::Channel(id) => match self.channel_name(id) {
+                    Some(name) => Node::Text(format!("#{}", name)),
+                    None => node,
+                },
+                Node::Bold(children) => Node::Bold(self.name_references(children)),
+                Node::Italic(children) => Node::Italic(self.name_references(children)),
+                Node::Link { text, url } => Node::Link {
+                    text: self.name_references(text),
+                    url,
+                },
+                Node::Text(_) | Node::Code(_) => node,
+            })
+            .collect()
+    }
+
+    /// Turns `@name` and `#name` into mentions and channel references, the longest matching
+    /// name wins. `text` is markup, so names are matched with their markup characters escaped
+    /// the way [`HubNames::decode`] writes them. Text in code spans is left alone.
+    pub fn encode(&self, text: &str) -> String {
+        let escaped_names = |names: &HashMap<ID, String>| {
+            let mut names = names
+                .iter()
+                .filter(|(_, name)| !name.is_empty())
+                .map(|(id, name)| (*id, format::escape(name)))
+                .collect::<Vec<_>>();
+            names.sort_by_key(|(_, name)| std::cmp::Reverse(name.len()));
+            names
+        };
+        let users = escaped_names(&self.users);
+        let channels = escaped_names(&self.channels);
+
+        let mut output = String::with_capacity(text.len());
+        let mut in_code = false;
+        let mut escaped = false;
+        let mut previous: Option<char> = None;
+        let mut pos = 0;
+        while let Some(c) = text[pos..].chars().next() {
+            let rest = &text[pos + c.len_utf8()..];
+            let candidates = match c {
+                '@' => &users,
+                '#' => &channels,
+                _ => &Vec::new(),
+            };
+            let starts_word = !matches!(previous, Some(p) if p.is_alphanumeric());
+            let found = if in_code || escaped || !starts_word {
+                None
+            } else {
+                candidates.iter().find(|(_, name)| {
+                    rest.starts_with(name.as_str())
+                        && !matches!(rest[name.len()..].chars().next(), Some(next) if next.is_alphanumeric())
+                })
+            };
+            match found {
+                Some((id, name)) => {
+                    output.push_str(&format!("<{}{}>", c, id));
+                    pos += c.len_utf8() + name.len();
+                    previous = name.chars().next_back();
+                    continue;
+                }
+                None => output.push(c),
+            }
+            if c == '`' && !escaped {
+                in_code = !in_code;
+            }
+            escaped = c == '\\' && !escaped;
+            previous = Some(c);
+            pos += c.len_utf8();
+        }
+        output
+    }
+}
+
+struct CachedHub {
+    names: HubNames,
+    fetched: Instant,
+}
+
+pub struct Resolver {
+    client: Arc<HttpClient>,
+    ttl: Duration,
+    hubs: Mutex<HashMap<ID, CachedHub>>,
+    /// One lock per hub being fetched so concurrent lookups of its names share one request,
+    /// lookups of other hubs do not wait for it.
+    fetching: Mutex<HashMap<ID, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl Resolver {
+    pub fn new(client: Arc<HttpClient>, ttl: Duration) -> Self {
+        Self {
+            client,
+            ttl,
+            hubs: Mutex::new(HashMap::new()),
+            fetching: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Names of a hub, fetched if they are not cached or older than the TTL.
+    pub async fn names(&self, hub_id: ID) -> Result<HubNames> {
+        self.lookup(hub_id, &HashSet::new(), &HashSet::new()).await
+    }
+
+    /// Names for a page of messages, including every sender, mention and channel reference,
+    /// with at most one request.
+    pub async fn names_for(&self, hub_id: ID, messages: &[Message]) -> Result<HubNames> {
+        let mut users = HashSet::new();
+        let mut channels = HashSet::new();
+        for message in messages {
+            users.insert(message.sender);
+            collect_references(&format::parse(&message.content), &mut users, &mut channels);
+        }
+        self.lookup(hub_id, &users, &channels).await
+    }
+
+    pub async fn decode(&self, hub_id: ID, text: &str) -> Result<String> {
+        let mut users = HashSet::new();
+        let mut channels = HashSet::new();
+        collect_references(&format::parse(text), &mut users, &mut channels);
+        Ok(self.lookup(hub_id, &users, &channels).await?.decode(text))
+    }
+
+    pub async fn encode(&self, hub_id: ID, text: &str) -> Result<String> {
+        Ok(self.names(hub_id).await?.encode(text))
+    }
+
+    /// Drops the cached names of a hub, for example after a member changed their nickname.
+    pub fn invalidate(&self, hub_id: ID) {
+        self.hubs.lock().unwrap().remove(&hub_id);
+    }
+
+    /// Returns the cached names if they are fresh and know every given ID, otherwise fetches
+    /// the hub. IDs the hub does not know (members that left) do not cause another fetch.
+    async fn lookup(
+        &self,
+        hub_id: ID,
+        users: &HashSet<ID>,
+        channels: &HashSet<ID>,
+    ) -> Result<HubNames> {
+        if let Some(names) = self.cached(hub_id, users, channels) {
+            return Ok(names);
+        }
+        let lock = Arc::clone(self.fetching.lock().unwrap().entry(hub_id).or_default());
+        let result = {
+            let _fetching = lock.lock().await;
+            match self.cached(hub_id, users, channels) {
+                Some(names) => Ok(names),
+                None => self.fetch(hub_id).await,
+            }
+        };
+        let mut fetching = self.fetching.lock().unwrap();
+        // Only this lookup and the map still hold the lock.
+        if Arc::strong_count(&lock) == 2 {
+            fetching.remove(&hub_id);
+        }
+        result
+    }
+
+    async fn fetch(&self, hub_id: ID) -> Result<HubNames> {
+        let hub = self.client.hub_get(hub_id).await?;
+        let names = HubNames {
+            users: hub
+                .members
+                .into_iter()
+                .map(|(id, member)| (id, member.nickname))
+                .collect(),
+            channels: hub
+                .channels
+                .into_iter()
+                .map(|(id, channel)| (id, channel.name))
+                .collect(),
+        };
+        self.hubs.lock().unwrap().insert(
+            hub_id,
+            CachedHub {
+                names: names.clone(),
+                fetched: Instant::now(),
+            },
+        );
+        Ok(names)
+    }
+
+    fn cached(&self, hub_id: ID, users: &HashSet<ID>, channels: &HashSet<ID>) -> Option<HubNames> {
+        let hubs = self.hubs.lock().unwrap();
+        let cached = hubs.get(&hub_id)?;
+        let complete = users.iter().all(|id| cached.names.users.contains_key(id))
+            && channels
+                .iter()
+                .all(|id| cached.names.channels.contains_key(id));
+        // IDs missing right after a fetch are not in the hub, fetching again would not help.
+        let fresh = cached.fetched.elapsed() < self.ttl;
+        let recent = cached.fetched.elapsed() < self.ttl / 10;
+        if fresh && (complete || recent) {
+            Some(cached.names.clone())
+        } else {
+            None
+        }
+    }
+}
+
+fn collect_references(nodes: &[Node], users: &mut HashSet<ID>, channels: &mut HashSet<ID>) {
+    for node in nodes {
+        match node {
+            Node::Mention(id) => {
+                users.insert(*id);
+            }
+            Node::Channel(id) => {
+                channels.insert(*id);
+            }
+            Node::Bold(children) | Node::Italic(children) | Node::Link { text: children, .. } => {
+                collect_references(children, users, channels)
+            }
+            Node::Text(_) | Node::Code(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names() -> (HubNames, ID, ID, ID) {
+        let (alice, bob, general) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        let mut names = HubNames::default();
+        names.users.insert(alice, "alice_b*".to_string());
+        names.users.insert(bob, String::new());
+        names.channels.insert(general, "general".to_string());
+        (names, alice, bob, general)
+    }
+
+    #[test]
+    fn decode_keeps_formatting() {
+        let (names, alice, bob, general) = names();
+        let text = format!(
+            "**hi <@{}>**, see [<#{}>](https://example.com) and `<@{}>` <@{}>",
+            alice, general, alice, bob
+        );
+        assert_eq!(
+            names.decode(&text),
+            format!(
+                "**hi @alice\\_b\\***, see [#general](https://example.com) and `<@{}>` <@{}>",
+                alice, bob
+            )
+        );
+    }
+
+    #[test]
+    fn encode_reverses_decode() {
+        let (names, alice, bob, general) = names();
+        let text = format!(
+            "**hi <@{}>**, see *<#{}>* and `<@{}>` <@{}>",
+            alice, general, alice, bob
+        );
+        assert_eq!(names.encode(&names.decode(&text)), text);
+    }
+
+    #[test]
+    fn encode_matches_whole_names() {
+        let (names, _, _, general) = names();
+        assert_eq!(
+            names.encode("#general #generally a#general \\#general `#general`"),
+            format!("<#{}> #generally a#general \\#general `#general`", general)
+        );
+        assert_eq!(names.encode("@alice_b*"), "@alice_b*");
+    }
+}
@@ -0,0 +1,117 @@
+//! HMAC-SHA256 signatures used to authenticate webhook payloads.
+
+use std::time::Duration;
+
+use chrono::Utc;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+pub const SIGNATURE_HEADER: &str = "x-wicrs-signature";
+/// Unix time in seconds at which a timestamped signature was made.
+pub const TIMESTAMP_HEADER: &str = "x-wicrs-timestamp";
+
+/// Signs `body` with `secret`, the result is in the format sent in [`SIGNATURE_HEADER`]:
+/// `sha256=<hex encoded signature>`.
+pub fn sign(secret: &[u8], body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Checks a signature produced by [`sign`] in constant time.
+pub fn verify(secret: &[u8], body: &[u8], signature: &str) -> bool {
+    let signature = match signature
+        .strip_prefix("sha256=")
+        .and_then(|hex| hex::decode(hex).ok())
+    {
+        Some(signature) => signature,
+        None => return false,
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    mac.verify(&signature).is_ok()
+}
+
+fn timestamped(timestamp: i64, body: &[u8]) -> Vec<u8> {
+    let mut signed = format!("{}.", timestamp).into_bytes();
+    signed.extend_from_slice(body);
+    signed
+}
+
+/// Signs `<timestamp>.<body>`, so a captured request can not be replayed once the timestamp is
+/// too old. The timestamp is sent in [`TIMESTAMP_HEADER`].
+pub fn sign_timestamped(secret: &[u8], timestamp: i64, body: &[u8]) -> String {
+    sign(secret, &timestamped(timestamp, body))
+}
+
+/// Checks a signature produced by [`sign_timestamped`], the timestamp must be at most
+/// `tolerance` away from the current time.
+pub fn verify_timestamped(
+    secret: &[u8],
+    timestamp: &str,
+    body: &[u8],
+    signature: &str,
+    tolerance: Duration,
+) -> bool {
+    let timestamp = match timestamp.parse::<i64>() {
+        Ok(timestamp) => timestamp,
+        Err(_) => return false,
+    };
+    let age = match Utc::now().timestamp().checked_sub(timestamp) {
+        Some(age) => age.unsigned_abs(),
+        None => return false,
+    };
+    age <= tolerance.as_secs() && verify(secret, &timestamped(timestamp, body), signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_own_signatures() {
+        let signature = sign(b"secret", b"body");
+        assert!(verify(b"secret", b"body", &signature));
+        assert!(!verify(b"other", b"body", &signature));
+        assert!(!verify(b"secret", b"changed", &signature));
+        assert!(!verify(b"secret", b"body", "md5=00"));
+    }
+
+    #[test]
+    fn timestamped_signatures_expire() {
+        let tolerance = Duration::from_secs(300);
+        let now = Utc::now().timestamp();
+        let signature = sign_timestamped(b"secret", now, b"body");
+        let timestamp = now.to_string();
+        assert!(verify_timestamped(
+            b"secret", &timestamp, b"body", &signature, tolerance
+        ));
+        assert!(!verify(b"secret", b"body", &signature));
+        let later = (now + 1).to_string();
+        assert!(!verify_timestamped(
+            b"secret", &later, b"body", &signature, tolerance
+        ));
+
+        let old = now - 600;
+        let signature = sign_timestamped(b"secret", old, b"body");
+        let timestamp = old.to_string();
+        assert!(!verify_timestamped(
+            b"secret", &timestamp, b"body", &signature, tolerance
+        ));
+    }
+
+    #[test]
+    fn rejects_extreme_timestamps() {
+        let tolerance = Duration::from_secs(300);
+        for timestamp in [i64::MIN, i64::MIN + 1, i64::MAX] {
+            let signature = sign_timestamped(b"secret", timestamp, b"body");
+            assert!(!verify_timestamped(
+                b"secret",
+                &timestamp.to_string(),
+                b"body",
+                &signature,
+                tolerance
+            ));
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! Creating a fully configured hub in one call.
+//!
+//! A [`HubSpec`] describes a hub with its channels and member permission overrides,
+//! [`HubSpec::apply`] creates it one request at a time and deletes the hub again if any request
+//! fails, so a failed setup does not leave a half configured hub behind. The rollback only
+//! deletes the hub, it relies on the server deleting the hub's channels along with it.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    api::WicrsApi,
+    error::Error,
+    protocol::{ChannelPermission, HttpChannelUpdate, HubPermission, PermissionSetting, ID},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HubPermissionOverride {
+    pub member: ID,
+    pub permission: HubPermission,
+    pub setting: PermissionSetting,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelPermissionOverride {
+    pub member: ID,
+    pub permission: ChannelPermission,
+    pub setting: PermissionSetting,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChannelSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub permissions: Vec<ChannelPermissionOverride>,
+}
+
+impl ChannelSpec {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            permissions: Vec::new(),
+        }
+    }
+
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn permission(
+        mut self,
+        member: ID,
+        permission: ChannelPermission,
+        setting: PermissionSetting,
+    ) -> Self {
+        self.permissions.push(ChannelPermissionOverride {
+            member,
+            permission,
+            setting,
+        });
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HubSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub default_group: Option<ID>,
+    #[serde(default)]
+    pub channels: Vec<ChannelSpec>,
+    #[serde(default)]
+    pub permissions: Vec<HubPermissionOverride>,
+}
+
+/// A request made while applying a [`HubSpec`], channels and overrides are given by their index
+/// in the spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    CreateHub,
+    UpdateHub,
+    CreateChannel(usize),
+    UpdateChannel(usize),
+    SetHubPermission(usize),
+    SetChannelPermission { channel: usize, permission: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// The step that was just completed.
+    pub step: Step,
+    pub completed: usize,
+    pub total: usize,
+}
+
+/// IDs of the hub and channels created from a [`HubSpec`], the channels are in spec order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedHub {
+    pub hub_id: ID,
+    pub channels: Vec<ID>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("applying hub spec failed at {step:?}: {error}")]
+pub struct ApplyError {
+    pub step: Step,
+    pub error: Error,
+    /// The hub that was created before the failure, if any. It has been deleted, with the
+    /// channels the server deletes along with it, unless `rollback_error` is set.
+    pub hub_id: Option<ID>,
+    pub rollback_error: Option<Error>,
+}
+
+impl HubSpec {
+    pub fn new<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            description: None,
+            default_group: None,
+            channels: Vec::new(),
+            permissions: Vec::new(),
+        }
+    }
+
+    pub fn description<S: Into<String>>(mut self, description: S) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn default_group(mut self, group: ID) -> Self {
+        self.default_group = Some(group);
+        self
+    }
+
+    pub fn channel(mut self, channel: ChannelSpec) -> Self {
+        self.channels.push(channel);
+        self
+    }
+
+    pub fn permission(
+        mut self,
+        member: ID,
+        permission: HubPermission,
+        setting: PermissionSetting,
+    ) -> Self {
+        self.permissions.push(HubPermissionOverride {
+            member,
+            permission,
+            setting,
+        });
+        self
+    }
+
+    /// The requests [`HubSpec::apply`] makes, in order.
+    pub fn steps(&self) -> Vec<Step> {
+        let mut steps = vec![Step::CreateHub];
+        if self.description.is_some() || self.default_group.is_some() {
+            steps.push(Step::UpdateHub);
+        }
+        for (index, channel) in self.channels.iter().enumerate() {
+            steps.push(Step::CreateChannel(index));
+            if channel.description.is_some() {
+                steps.push(Step::UpdateChannel(index));
+            }
+        }
+        steps.extend((0..self.permissions.len()).map(Step::SetHubPermission));
+        for (index, channel) in self.channels.iter().enumerate() {
+            steps.extend((0..channel.permissions.len()).map(|permission| {
+                Step::SetChannelPermission {
+                    channel: index,
+                    permission,
+                }
+            }));
+        }
+        steps
+    }
+
+    pub async fn apply<A>(&self, api: &A) -> Result<AppliedHub, ApplyError>
+    where
+        A: WicrsApi + ?Sized,
+    {
+        self.apply_with_progress(api, |_| {}).await
+    }
+
+    /// Creates the hub, calling `progress` after every request.
+    pub async fn apply_with_progress<A, F>(
+        &self,
+        api: &A,
+        mut progress: F,
+    ) -> Result<AppliedHub, ApplyError>
+    where
+        A: WicrsApi + ?Sized,
+        F: FnMut(Progress) + Send,
+    {
+        let steps = self.steps();
+        let mut applied = AppliedHub {
+            hub_id: ID::nil(),
+            channels: Vec::with_capacity(self.channels.len()),
+        };
+        for (completed, step) in steps.iter().enumerate() {
+            if let Err(error) = self.apply_step(api, *step, &mut applied).await {
+                let hub_id = if *step == Step::CreateHub {
+                    None
+                } else {
+                    Some(applied.hub_id)
+                };
+                let rollback_error = match hub_id {
+                    Some(hub_id) => api.hub_delete(hub_id).await.err(),
+                    None => None,
+                };
+                return Err(ApplyError {
+                    step: *step,
+                    error,
+                    hub_id,
+                    rollback_error,
+                });
+            }
+            progress(Progress {
+                step: *step,
+                completed: completed + 1,
+                total: steps.len(),
+            });
+        }
+        Ok(applied)
+    }
+
+    async fn apply_step<A>(
+        &self,
+        api: &A,
+        step: Step,
+        applied: &mut AppliedHub,
+    ) -> crate::Result<()>
+    where
+        A: WicrsApi + ?Sized,
+    {
+        let hub = applied.hub_id;
+        match step {
+            Step::CreateHub => applied.hub_id = api.hub_create(self.name.clone()).await?,
+            Step::UpdateHub => {
+                api.hub_update(hub, None, self.description.clone(), self.default_group)
+                    .await?;
+            }
+            Step::CreateChannel(index) => {
+                let channel = api
+                    .channel_create(hub, self.channels[index].name.clone())
+                    .await?;
+                applied.channels.push(channel);
+            }
+            Step::UpdateChannel(index) => {
+                let update = HttpChannelUpdate {
+                    name: None,
+                    description: self.channels[index].description.clone(),
+                };
+                api.channel_update(hub, applied.channels[index], update)
+                    .await?;
+            }
+            Step::SetHubPermission(index) => {
+                let permission = self.permissions[index];
+                api.member_set_hub_permission(
+                    hub,
+                    permission.member,
+                    permission.permission,
+                    permission.setting,
+                )
+                .await?;
+            }
+            Step::SetChannelPermission {
+                channel,
+                permission,
+            } => {
+                let permission = self.channels[channel].permissions[permission];
+                api.member_set_channel_permission(
+                    hub,
+                    permission.member,
+                    applied.channels[channel],
+                    permission.permission,
+                    permission.setting,
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_bare_hub_is_one_step() {
+        assert_eq!(HubSpec::new("hub").steps(), [Step::CreateHub]);
+    }
+
+    #[test]
+    fn steps_follow_the_spec() {
+        let member = ID::new_v4();
+        let spec = HubSpec::new("hub")
+            .description("a hub")
+            .permission(member, HubPermission::Mute, Some(true))
+            .permission(member, HubPermission::Kick, Some(false))
+            .channel(ChannelSpec::new("general").permission(
+                member,
+                ChannelPermission::Read,
+                Some(true),
+            ))
+            .channel(
+                ChannelSpec::new("news")
+                    .description("announcements")
+                    .permission(member, ChannelPermission::Write, Some(false))
+                    .permission(member, ChannelPermission::Read, Some(true)),
+            );
+        assert_eq!(
+            spec.steps(),
+            [
+                Step::CreateHub,
+                Step::UpdateHub,
+                Step::CreateChannel(0),
+                Step::CreateChannel(1),
+                Step::UpdateChannel(1),
+                Step::SetHubPermission(0),
+                Step::SetHubPermission(1),
+                Step::SetChannelPermission {
+                    channel: 0,
+                    permission: 0
+                },
+                Step::SetChannelPermission {
+                    channel: 1,
+                    permission: 0
+                },
+                Step::SetChannelPermission {
+                    channel: 1,
+                    permission: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn a_default_group_updates_the_hub() {
+        let spec = HubSpec::new("hub").default_group(ID::new_v4());
+        assert_eq!(spec.steps(), [Step::CreateHub, Step::UpdateHub]);
+    }
+
+    #[cfg(feature = "use-tokio")]
+    #[tokio::test]
+    async fn deletes_the_hub_when_a_step_fails() {
+        use crate::{fake::FakeApi, protocol::ApiError};
+
+        let api = FakeApi::new(ID::new_v4());
+        let hub_id = ID::new_v4();
+        api.respond("hub_create", hub_id).unwrap();
+        api.respond("channel_create", ID::new_v4()).unwrap();
+        api.fail_once("channel_update", ApiError::NotFound);
+        let spec = HubSpec::new("hub")
+            .channel(ChannelSpec::new("general"))
+            .channel(ChannelSpec::new("news").description("announcements"));
+
+        let error = spec.apply(&api).await.unwrap_err();
+        assert_eq!(error.step, Step::UpdateChannel(1));
+        assert_eq!(error.hub_id, Some(hub_id));
+        assert!(error.rollback_error.is_none());
+        let deleted = api.calls_to("hub_delete");
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].args, [serde_json::to_value(hub_id).unwrap()]);
+    }
+
+    #[cfg(feature = "use-tokio")]
+    #[tokio::test]
+    async fn nothing_is_rolled_back_when_the_hub_is_not_created() {
+        use crate::{fake::FakeApi, protocol::ApiError};
+
+        let api = FakeApi::new(ID::new_v4());
+        api.fail_once("hub_create", ApiError::Muted);
+        let error = HubSpec::new("hub").apply(&api).await.unwrap_err();
+        assert_eq!(error.step, Step::CreateHub);
+        assert_eq!(error.hub_id, None);
+        assert!(api.calls_to("hub_delete").is_empty());
+    }
+
+    #[cfg(feature = "use-tokio")]
+    #[tokio::test]
+    async fn channel_permissions_are_set_on_the_created_channel() {
+        use crate::fake::FakeApi;
+
+        let api = FakeApi::new(ID::new_v4());
+        let (hub_id, channel_id, member) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+        api.respond("hub_create", hub_id).unwrap();
+        api.respond("channel_create", channel_id).unwrap();
+        let spec = HubSpec::new("hub").channel(ChannelSpec::new("general").permission(
+            member,
+            ChannelPermission::Write,
+            Some(false),
+        ));
+
+        let applied = spec.apply(&api).await.unwrap();
+        assert_eq!(applied.channels, [channel_id]);
+        let calls = api.calls_to("member_set_channel_permission");
+        assert_eq!(calls.len(), 1);
+        let expected = serde_json::json!([hub_id, member, channel_id, "Write", false]);
+        assert_eq!(serde_json::Value::Array(calls[0].args.clone()), expected);
+    }
+}
@@ -0,0 +1,363 @@
+//! Streaming of large list responses.
+//!
+//! [`JsonArrayParser`] splits a JSON array into its elements as the bytes arrive, only the
+//! element being read is kept in memory. The `*_stream` methods of [`HttpClient`] use it to
+//! return messages one by one instead of buffering whole responses.
+
+use std::{collections::VecDeque, fmt::Display};
+
+use chrono::{DateTime, Utc};
+use futures_util::{stream, Stream};
+use reqwest::Method;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::{
+        HttpLastMessagesQuery, HttpMessagesAfterQuery, HttpMessagesBeforeQuery,
+        HttpMessagesBetweenQuery, Message, Response, ID,
+    },
+};
+
+/// What the body looks like before the array, `{"Success":` or nothing.
+const SUCCESS_PREFIX: &[u8] = b"{\"Success\":";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// Before the array, the envelope is buffered to check that it is a success.
+    Prefix,
+    Array,
+    /// The array has ended, the rest of the envelope is ignored.
+    Done,
+    /// The body is not a successful list, it is buffered and parsed at the end.
+    Buffering,
+}
+
+#[derive(Debug)]
+pub struct JsonArrayParser {
+    mode: Mode,
+    buffer: Vec<u8>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl Default for JsonArrayParser {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Prefix,
+            buffer: Vec::new(),
+            depth: 0,
+            in_string: false,
+            escaped: false,
+        }
+    }
+}
+
+impl JsonArrayParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds the next bytes of the body, returning the elements completed by them.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let mut elements = Vec::new();
+        for &byte in chunk {
+            match self.mode {
+                Mode::Done => {}
+                Mode::Buffering => self.buffer.push(byte),
+                Mode::Prefix => {
+                    if !self.in_string && byte == b'[' {
+                        let prefix = self
+                            .buffer
+                            .iter()
+                            .copied()
+                            .filter(|byte| !byte.is_ascii_whitespace())
+                            .collect::<Vec<_>>();
+                        if prefix.is_empty() || prefix == SUCCESS_PREFIX {
+                            self.mode = Mode::Array;
+                            self.buffer.clear();
+                            continue;
+                        }
+                        self.mode = Mode::Buffering;
+                    }
+                    self.string_state(byte);
+                    self.buffer.push(byte);
+                }
+                Mode::Array => {
+                    if self.in_string {
+                        self.string_state(byte);
+                        self.buffer.push(byte);
+                        continue;
+                    }
+                    match byte {
+                        b',' | b']' if self.depth == 0 => {
+                            if !self.buffer.is_empty() {
+                                elements.push(std::mem::take(&mut self.buffer));
+                            }
+                            if byte == b']' {
+                                self.mode = Mode::Done;
+                            }
+                        }
+                        byte if byte.is_ascii_whitespace() && self.buffer.is_empty() => {}
+                        byte => {
+                            match byte {
+                                b'"' => self.in_string = true,
+                                b'{' | b'[' => self.depth += 1,
+                                b'}' | b']' => self.depth = self.depth.saturating_sub(1),
+                                _ => {}
+                            }
+                            self.buffer.push(byte);
+                        }
+                    }
+                }
+            }
+        }
+        elements
+    }
+
+    fn string_state(&mut self, byte: u8) {
+        if self.in_string {
+            if self.escaped {
+                self.escaped = false;
+            } else if byte == b'\\' {
+                self.escaped = true;
+            } else if byte == b'"' {
+                self.in_string = false;
+            }
+        } else if byte == b'"' {
+            self.in_string = true;
+        }
+    }
+
+    /// Ends the body, returning the elements of a body that was not a streamed list (for example
+    /// an empty success) or the error it contained.
+    pub fn finish(&mut self) -> Result<Vec<Value>> {
+        match self.mode {
+            Mode::Done => Ok(Vec::new()),
+            Mode::Array => Err(serde_json::from_slice::<Value>(b"[").unwrap_err().into()),
+            Mode::Prefix | Mode::Buffering => {
+                let buffer = std::mem::take(&mut self.buffer);
+                match serde_json::from_slice::<Response<Value>>(&buffer)? {
+                    Response::Success(Value::Array(values)) => Ok(values),
+                    Response::Success(Value::Null) => Ok(Vec::new()),
+                    Response::Success(value) => Ok(vec![value]),
+                    Response::Error(error) => Err(error.into()),
+                }
+            }
+        }
+    }
+}
+
+struct StreamState<T> {
+    response: Option<reqwest::Response>,
+    parser: JsonArrayParser,
+    ready: VecDeque<Result<T>>,
+}
+
+/// Parses a response whose body is a list into a stream of its elements.
+pub fn json_array_stream<T>(response: reqwest::Response) -> impl Stream<Item = Result<T>>
+where
+    T: DeserializeOwned,
+{
+    let state = StreamState {
+        response: Some(response),
+        parser: JsonArrayParser::new(),
+        ready: VecDeque::new(),
+    };
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.ready.pop_front() {
+                return Some((item, state));
+            }
+            let response = state.response.as_mut()?;
+            match response.chunk().await {
+                Ok(Some(chunk)) => {
+                    for element in state.parser.push(&chunk) {
+                        state
+                            .ready
+                            .push_back(serde_json::from_slice(&element).map_err(Into::into));
+                    }
+                }
+                Ok(None) => {
+                    state.response = None;
+                    match state.parser.finish() {
+                        Ok(values) => state.ready.extend(
+                            values
+                                .into_iter()
+                                .map(|value| serde_json::from_value(value).map_err(Into::into)),
+                        ),
+                        Err(error) => state.ready.push_back(Err(error)),
+                    }
+                }
+                Err(error) => {
+                    state.response = None;
+                    state.ready.push_back(Err(error.into()));
+                }
+            }
+        }
+    })
+}
+
+impl HttpClient {
+    /// Like [`HttpClient::send_json`] for requests returning a list, the elements are parsed as
+    /// they arrive.
+    pub async fn stream_json<S, D, T>(
+        &self,
+        method: Method,
+        url: S,
+        data: D,
+    ) -> Result<impl Stream<Item = Result<T>>>
+    where
+        S: Display,
+        D: Serialize,
+        T: DeserializeOwned,
+    {
+        let request = self
+            .request_builder(method.clone(), url)?
+            .body(serde_json::to_string(&data)?)
+            .header("content-type", "application/json")
+            .build()?;
+        Ok(json_array_stream(self.execute_raw(request).await?))
+    }
+
+    pub async fn messages_stream_after(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: ID,
+        max: usize,
+    ) -> Result<impl Stream<Item = Result<Message>>> {
+        self.stream_json(
+            Method::GET,
+            format!("/message/{}/{}/after", hub, channel),
+            HttpMessagesAfterQuery { from, max },
+        )
+        .await
+    }
+
+    pub async fn messages_stream_before(
+        &self,
+        hub: ID,
+        channel: ID,
+        to: ID,
+        max: usize,
+    ) -> Result<impl Stream<Item = Result<Message>>> {
+        self.stream_json(
+            Method::GET,
+            format!("/message/{}/{}/before", hub, channel),
+            HttpMessagesBeforeQuery { to, max },
+        )
+        .await
+    }
+
+    pub async fn messages_stream_last(
+        &self,
+        hub: ID,
+        channel: ID,
+        max: usize,
+    ) -> Result<impl Stream<Item = Result<Message>>> {
+        self.stream_json(
+            Method::GET,
+            format!("/message/{}/{}/last", hub, channel),
+            HttpLastMessagesQuery { max },
+        )
+        .await
+    }
+
+    pub async fn messages_stream_between(
+        &self,
+        hub: ID,
+        channel: ID,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        max: usize,
+        new_to_old: bool,
+    ) -> Result<impl Stream<Item = Result<Message>>> {
+        self.stream_json(
+            Method::GET,
+            format!("/message/{}/{}/between", hub, channel),
+            HttpMessagesBetweenQuery {
+                from,
+                to,
+                max,
+                new_to_old,
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::ApiError, Error};
+
+    /// Feeds `body` in chunks of `size` bytes, returning the elements as text.
+    fn parse(body: &str, size: usize) -> (Vec<String>, Result<Vec<Value>>) {
+        let mut parser = JsonArrayParser::new();
+        let mut elements = Vec::new();
+        for chunk in body.as_bytes().chunks(size) {
+            elements.extend(parser.push(chunk));
+        }
+        let elements = elements
+            .into_iter()
+            .map(|element| String::from_utf8(element).unwrap())
+            .collect();
+        (elements, parser.finish())
+    }
+
+    #[test]
+    fn splits_elements_across_chunks() {
+        let body = r#" {"Success": [ {"a": [1, {"b": "]"}]}, "x,\"]\\", [[]] , 2 ] } "#;
+        for size in 1..body.len() {
+            let (elements, rest) = parse(body, size);
+            assert_eq!(
+                elements,
+                [r#"{"a": [1, {"b": "]"}]}"#, r#""x,\"]\\""#, "[[]] ", "2 "],
+                "{}",
+                size
+            );
+            assert!(rest.unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn parses_bare_and_empty_arrays() {
+        assert_eq!(parse("[1,2]", 1).0, ["1", "2"]);
+        let (elements, rest) = parse(r#"{"Success":[]}"#, 3);
+        assert!(elements.is_empty());
+        assert!(rest.unwrap().is_empty());
+        let (elements, rest) = parse(r#"{"Success":null}"#, 3);
+        assert!(elements.is_empty());
+        assert!(rest.unwrap().is_empty());
+    }
+
+    #[test]
+    fn returns_errors_at_the_end() {
+        let body = serde_json::to_string(&Response::<()>::Error(ApiError::Other(
+            "[not a list]".to_string(),
+        )))
+        .unwrap();
+        for size in 1..body.len() {
+            let (elements, rest) = parse(&body, size);
+            assert!(elements.is_empty());
+            match rest {
+                Err(Error::WICRSError(ApiError::Other(message))) => {
+                    assert_eq!(message, "[not a list]")
+                }
+                other => panic!("{:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn fails_on_truncated_bodies() {
+        let (elements, rest) = parse(r#"{"Success":[{"a":1},{"b""#, 4);
+        assert_eq!(elements, [r#"{"a":1}"#]);
+        assert!(matches!(rest, Err(Error::Json(_))));
+        assert!(matches!(parse(r#"{"Succ"#, 4).1, Err(Error::Json(_))));
+    }
+}
@@ -0,0 +1,157 @@
+//! Reference counted hub and channel subscriptions shared by several parts of an application.
+//!
+//! [`SubscriptionManager::subscribe`] returns a [`SubscriptionGuard`], the server is only told to
+//! unsubscribe once the last guard for a hub or channel is dropped or released.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::runtime::Handle;
+
+use crate::{
+    error::Result,
+    protocol::ID,
+    websocket::{asyncws::WebsocketClient, Subscription},
+};
+
+pub struct SubscriptionManager {
+    client: Arc<WebsocketClient>,
+    counts: Mutex<HashMap<Subscription, usize>>,
+    /// Serializes subscribe and unsubscribe commands so they reach the server in the same order
+    /// as the counts change.
+    commands: tokio::sync::Mutex<()>,
+}
+
+impl SubscriptionManager {
+    pub fn new(client: Arc<WebsocketClient>) -> Arc<Self> {
+        Arc::new(Self {
+            client,
+            counts: Mutex::new(HashMap::new()),
+            commands: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    pub fn client(&self) -> &Arc<WebsocketClient> {
+        &self.client
+    }
+
+    pub async fn subscribe(
+        self: &Arc<Self>,
+        subscription: Subscription,
+    ) -> Result<SubscriptionGuard> {
+        let _commands = self.commands.lock().await;
+        let subscribed = self.counts.lock().unwrap().contains_key(&subscription);
+        if !subscribed {
+            self.client.subscribe(subscription).await?;
+        }
+        *self.counts.lock().unwrap().entry(subscription).or_insert(0) += 1;
+        Ok(SubscriptionGuard {
+            manager: Some(Arc::clone(self)),
+            subscription,
+        })
+    }
+
+    pub async fn subscribe_hub(self: &Arc<Self>, hub_id: ID) -> Result<SubscriptionGuard> {
+        self.subscribe(Subscription::Hub(hub_id)).await
+    }
+
+    pub async fn subscribe_channel(
+        self: &Arc<Self>,
+        hub_id: ID,
+        channel_id: ID,
+    ) -> Result<SubscriptionGuard> {
+        self.subscribe(Subscription::Channel(hub_id, channel_id))
+            .await
+    }
+
+    /// Current subscriptions with the number of guards held for each.
+    pub fn subscriptions(&self) -> Vec<(Subscription, usize)> {
+        let mut subscriptions = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(subscription, count)| (*subscription, *count))
+            .collect::<Vec<_>>();
+        subscriptions.sort();
+        subscriptions
+    }
+
+    /// Sends the subscribe commands for every held subscription again, for use after the
+    /// connection was replaced.
+    pub async fn resubscribe(&self) -> Result<()> {
+        let _commands = self.commands.lock().await;
+        for (subscription, _) in self.subscriptions() {
+            self.client.subscribe(subscription).await?;
+        }
+        Ok(())
+    }
+
+    async fn release(&self, subscription: Subscription) -> Result<()> {
+        let _commands = self.commands.lock().await;
+        if self.decrement(subscription) {
+            self.client.unsubscribe(subscription).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the last guard for `subscription` was released.
+    fn decrement(&self, subscription: Subscription) -> bool {
+        let mut counts = self.counts.lock().unwrap();
+        match counts.get_mut(&subscription) {
+            Some(count) if *count > 1 => {
+                *count -= 1;
+                false
+            }
+            Some(_) => {
+                counts.remove(&subscription);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Keeps a hub or channel subscribed while it is held.
+///
+/// Dropping the guard unsubscribes in a background task, use [`SubscriptionGuard::release`] to
+/// wait for the unsubscribe and see its result.
+#[must_use = "the subscription is released when the guard is dropped"]
+pub struct SubscriptionGuard {
+    manager: Option<Arc<SubscriptionManager>>,
+    subscription: Subscription,
+}
+
+impl SubscriptionGuard {
+    pub fn subscription(&self) -> Subscription {
+        self.subscription
+    }
+
+    pub async fn release(mut self) -> Result<()> {
+        match self.manager.take() {
+            Some(manager) => manager.release(self.subscription).await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for SubscriptionGuard {
+    fn drop(&mut self) {
+        if let Some(manager) = self.manager.take() {
+            let subscription = self.subscription;
+            match Handle::try_current() {
+                Ok(handle) => {
+                    handle.spawn(async move {
+                        let _ = manager.release(subscription).await;
+                    });
+                }
+                // Without a runtime the command can not be sent, the count is still kept right.
+                Err(_) => {
+                    manager.decrement(subscription);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,335 @@
+//! `tracing` instrumentation for the HTTP and websocket clients.
+//!
+//! HTTP calls are recorded in `http_request` spans, websocket traffic in `ws_connect`, `ws_send`
+//! and `ws_receive` spans. Message contents are only recorded once redaction is turned off with
+//! [`set_redact_contents`].
+
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Instant,
+};
+
+use serde_json::Value;
+use tracing::{field::Empty, Instrument, Span};
+
+use crate::{
+    error::Result,
+    protocol::{WsClientMessage, WsServerMessage, ID},
+    websocket::variant_name,
+};
+
+static REDACT_CONTENTS: AtomicBool = AtomicBool::new(true);
+
+/// Sets whether message contents are left out of spans, they are redacted by default.
+pub fn set_redact_contents(redact: bool) {
+    REDACT_CONTENTS.store(redact, Ordering::Relaxed);
+}
+
+pub fn redact_contents() -> bool {
+    REDACT_CONTENTS.load(Ordering::Relaxed)
+}
+
+pub(crate) fn http_span(method: &reqwest::Method, route: &str, ids: &[ID]) -> Span {
+    let span = tracing::debug_span!(
+        "http_request",
+        method = %method,
+        route = %route,
+        hub = Empty,
+        channel = Empty,
+        member = Empty,
+        status = Empty,
+        latency_ms = Empty,
+    );
+    // Every route starts with the hub, member routes follow it with the member and may end with
+    // a channel while message and channel routes follow it with the channel.
+    if let Some(hub) = ids.first() {
+        span.record("hub", hub.to_string().as_str());
+    }
+    if route.starts_with("/member/") {
+        if let Some(member) = ids.get(1) {
+            span.record("member", member.to_string().as_str());
+        }
+        if let Some(channel) = ids.get(2) {
+            span.record("channel", channel.to_string().as_str());
+        }
+    } else if let Some(channel) = ids.get(1) {
+        span.record("channel", channel.to_string().as_str());
+    }
+    span
+}
+
+/// Records the latency of `future` and an error event if it fails in the current span.
+pub(crate) async fn timed<F, T>(future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let start = Instant::now();
+    let result = future.await;
+    Span::current().record("latency_ms", start.elapsed().as_secs_f64() * 1000.0);
+    if let Err(error) = &result {
+        tracing::error!(error = %error, "request failed");
+    }
+    result
+}
+
+pub(crate) fn record_status(status: u16) {
+    Span::current().record("status", status as u64);
+}
+
+pub(crate) fn ws_connect_span(server_api_url: &str, user_id: ID) -> Span {
+    tracing::debug_span!("ws_connect", url = %server_api_url, user = %user_id)
+}
+
+pub(crate) fn ws_send_span(message: &WsClientMessage) -> Span {
+    let span = tracing::debug_span!(
+        "ws_send",
+        kind = Empty,
+        hub = Empty,
+        channel = Empty,
+        content = Empty,
+    );
+    if let Ok(value) = serde_json::to_value(message) {
+        record_message_fields(&span, &value);
+    }
+    span
+}
+
+pub(crate) fn ws_receive_span() -> Span {
+    tracing::debug_span!(
+        "ws_receive",
+        kind = Empty,
+        hub = Empty,
+        channel = Empty,
+        sender = Empty,
+        content = Empty,
+    )
+}
+
+pub(crate) fn record_received(span: &Span, message: &WsServerMessage) {
+    if let Ok(value) = serde_json::to_value(message) {
+        record_message_fields(span, &value);
+        if let Some(sender) = fields(&value).and_then(|fields| fields.get("sender_id")) {
+            span.record("sender", display_value(sender).as_str());
+        }
+    }
+}
+
+/// Runs `f` in `span`, recording an error event if it fails.
+pub(crate) fn traced<T, F>(span: &Span, f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T>,
+{
+    span.in_scope(|| {
+        let result = f();
+        if let Err(error) = &result {
+            tracing::error!(error = %error, "websocket error");
+        }
+        result
+    })
+}
+
+/// Runs `future` in `span`, recording an error event if it fails.
+pub(crate) async fn traced_async<T, F>(span: &Span, future: F) -> Result<T>
+where
+    F: Future<Output = Result<T>>,
+{
+    let result = future.instrument(span.clone()).await;
+    if let Err(error) = &result {
+        span.in_scope(|| tracing::error!(error = %error, "websocket error"));
+    }
+    result
+}
+
+fn fields(value: &Value) -> Option<&serde_json::Map<String, Value>> {
+    match value {
+        Value::Object(map) => map.values().next().and_then(Value::as_object),
+        _ => None,
+    }
+}
+
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(string) => string.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn record_message_fields(span: &Span, value: &Value) {
+    if let Some(kind) = variant_name(value) {
+        span.record("kind", kind);
+    }
+    if let Some(fields) = fields(value) {
+        if let Some(hub) = fields.get("hub_id") {
+            span.record("hub", display_value(hub).as_str());
+        }
+        if let Some(channel) = fields.get("channel_id") {
+            span.record("channel", display_value(channel).as_str());
+        }
+        if !redact_contents() {
+            if let Some(content) = fields.get("message") {
+                span.record("content", display_value(content).as_str());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        fmt,
+        sync::{Arc, Mutex},
+    };
+
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+    use tracing_core::span::Current;
+
+    use super::*;
+
+    type Fields = HashMap<String, String>;
+
+    #[derive(Default)]
+    struct Recorded {
+        spans: Vec<(&'static Metadata<'static>, Fields)>,
+        /// Events with the name of the span they were in.
+        events: Vec<(Option<&'static str>, Fields)>,
+        entered: Vec<u64>,
+    }
+
+    /// Keeps every span and event in memory.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Recorded>>);
+
+    impl Recorder {
+        fn span(&self, name: &str) -> Fields {
+            let recorded = self.0.lock().unwrap();
+            let spans = recorded
+                .spans
+                .iter()
+                .filter(|(span, _)| span.name() == name)
+                .collect::<Vec<_>>();
+            assert_eq!(spans.len(), 1, "one {} span", name);
+            spans[0].1.clone()
+        }
+    }
+
+    struct Visitor<'a>(&'a mut Fields);
+
+    impl Visit for Visitor<'_> {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Fields::new();
+            span.record(&mut Visitor(&mut fields));
+            let mut recorded = self.0.lock().unwrap();
+            recorded.spans.push((span.metadata(), fields));
+            Id::from_u64(recorded.spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut recorded = self.0.lock().unwrap();
+            let fields = &mut recorded.spans[span.into_u64() as usize - 1].1;
+            values.record(&mut Visitor(fields));
+        }
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::new();
+            event.record(&mut Visitor(&mut fields));
+            let mut recorded = self.0.lock().unwrap();
+            let span = recorded
+                .entered
+                .last()
+                .map(|id| recorded.spans[*id as usize - 1].0.name());
+            recorded.events.push((span, fields));
+        }
+
+        fn enter(&self, span: &Id) {
+            self.0.lock().unwrap().entered.push(span.into_u64());
+        }
+
+        fn exit(&self, _: &Id) {
+            self.0.lock().unwrap().entered.pop();
+        }
+
+        fn current_span(&self) -> Current {
+            let recorded = self.0.lock().unwrap();
+            match recorded.entered.last() {
+                Some(id) => Current::new(Id::from_u64(*id), recorded.spans[*id as usize - 1].0),
+                None => Current::none(),
+            }
+        }
+    }
+
+    #[cfg(feature = "use-tokio")]
+    #[tokio::test]
+    async fn failed_requests_are_traced() {
+        let recorder = Recorder::default();
+        let _default = tracing::subscriber::set_default(recorder.clone());
+        // Nothing listens on the discard port, so the request fails without a response.
+        let client =
+            crate::http::HttpClient::new(ID::new_v4(), "http://127.0.0.1:9/api".to_string())
+                .unwrap();
+        let (hub, member) = (ID::new_v4(), ID::new_v4());
+        assert!(client.member_get(hub, member).await.is_err());
+
+        let span = recorder.span("http_request");
+        assert_eq!(span["method"], "GET");
+        assert_eq!(span["route"], "/member/{id}/{id}");
+        assert_eq!(span["hub"], hub.to_string());
+        assert_eq!(span["member"], member.to_string());
+        assert!(!span.contains_key("channel"));
+        assert!(!span.contains_key("status"));
+        assert!(span.contains_key("latency_ms"));
+
+        let recorded = recorder.0.lock().unwrap();
+        let failures = recorded
+            .events
+            .iter()
+            .filter(|(span, fields)| {
+                *span == Some("http_request")
+                    && fields.get("message").map(String::as_str) == Some("request failed")
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].1.contains_key("error"));
+    }
+
+    #[test]
+    fn sent_messages_are_redacted() {
+        let recorder = Recorder::default();
+        let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+        tracing::subscriber::with_default(recorder.clone(), || {
+            ws_send_span(&WsClientMessage::SendMessage {
+                hub_id,
+                channel_id,
+                message: "secret".to_string(),
+            });
+        });
+
+        let span = recorder.span("ws_send");
+        assert_eq!(span["kind"], "SendMessage");
+        assert_eq!(span["hub"], hub_id.to_string());
+        assert_eq!(span["channel"], channel_id.to_string());
+        assert!(!span.contains_key("content"));
+    }
+}
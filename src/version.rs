@@ -0,0 +1,137 @@
+//! Protocol versions and the servers this client works with.
+//!
+//! Servers send their protocol version in the [`VERSION_HEADER`] header of every HTTP response
+//! and of the websocket handshake, the client sends its own version in the same header. Servers
+//! that do not send it predate versioning and are treated as [`ProtocolVersion::LEGACY`].
+//!
+//! A server is compatible if it has the same major version as the client and is not older than
+//! [`ProtocolVersion::MINIMUM`]. Methods that need a newer server than that check [`Feature`]s
+//! first and fail with [`Error::Unsupported`] instead of sending requests the server does not
+//! understand.
+
+use std::{fmt, str::FromStr};
+
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+pub const VERSION_HEADER: &str = "wicrs-protocol";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl ProtocolVersion {
+    /// Version of servers that do not send a version.
+    pub const LEGACY: Self = Self::new(1, 0);
+    /// Oldest server version this client works with.
+    pub const MINIMUM: Self = Self::LEGACY;
+    /// Version this client implements.
+    pub const CURRENT: Self = Self::new(1, 2);
+
+    pub const fn new(major: u16, minor: u16) -> Self {
+        Self { major, minor }
+    }
+
+    /// Version sent in the [`VERSION_HEADER`] of a response.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self> {
+        match headers.get(VERSION_HEADER) {
+            Some(value) => value
+                .to_str()
+                .map_err(|_| Error::InvalidProtocolVersion(format!("{:?}", value)))?
+                .parse(),
+            None => Ok(Self::LEGACY),
+        }
+    }
+
+    /// Whether this client can talk to a server of this version.
+    pub fn is_compatible(self) -> bool {
+        self.major == Self::CURRENT.major && self >= Self::MINIMUM
+    }
+
+    pub fn check_compatible(self) -> Result<Self> {
+        if self.is_compatible() {
+            Ok(self)
+        } else {
+            Err(Error::IncompatibleServer {
+                server: self,
+                client: Self::CURRENT,
+            })
+        }
+    }
+
+    pub fn supports(self, feature: Feature) -> bool {
+        self >= feature.since()
+    }
+
+    pub fn require(self, feature: Feature) -> Result<()> {
+        if self.supports(feature) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported {
+                feature,
+                server: self,
+            })
+        }
+    }
+}
+
+impl fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl FromStr for ProtocolVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidProtocolVersion(s.to_string());
+        let (major, minor) = s.trim().split_once('.').ok_or_else(invalid)?;
+        Ok(Self::new(
+            major.parse().map_err(|_| invalid())?,
+            minor.parse().map_err(|_| invalid())?,
+        ))
+    }
+}
+
+/// Parts of the protocol that not every compatible server has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Setting a member's channel permission for one channel, older servers have no channel in
+    /// the route.
+    ChannelInPermissionRoute,
+    /// Uploading and downloading attachments.
+    Attachments,
+}
+
+/// The first protocol version with each feature.
+pub const FEATURES: &[(Feature, ProtocolVersion)] = &[
+    (
+        Feature::ChannelInPermissionRoute,
+        ProtocolVersion::new(1, 2),
+    ),
+    (Feature::Attachments, ProtocolVersion::new(1, 2)),
+];
+
+impl Feature {
+    pub fn since(self) -> ProtocolVersion {
+        FEATURES
+            .iter()
+            .find(|(feature, _)| *feature == self)
+            .map(|(_, version)| *version)
+            .unwrap_or(ProtocolVersion::MINIMUM)
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Feature::ChannelInPermissionRoute => "channel permissions for one channel",
+            Feature::Attachments => "attachments",
+        })
+    }
+}
@@ -0,0 +1,480 @@
+//! Embeddable HTTP server that posts incoming webhook payloads into channels.
+//!
+//! Every hook has a secret URL (`/hooks/<token>`) bound to one channel. The request body is either
+//! sent as is (plain text) or, for JSON bodies, rendered through the hook's template where
+//! `{{path.to.field}}` placeholders are replaced by the matching values of the payload.
+
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex, RwLock},
+    time::Duration,
+};
+
+use hyper::{
+    body::HttpBody,
+    header::{CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::{
+    error::Result,
+    http::HttpClient,
+    protocol::ID,
+    rate_limit::{check_burst, check_rate, TokenBucket},
+    signature::{verify_timestamped, SIGNATURE_HEADER, TIMESTAMP_HEADER},
+};
+
+pub const HOOK_PATH_PREFIX: &str = "/hooks/";
+
+#[derive(Debug, Clone)]
+pub struct WebhookServerConfig {
+    /// Largest accepted request body in bytes.
+    pub max_payload: usize,
+    /// Number of requests a hook can receive in a burst.
+    pub burst: u32,
+    /// Sustained number of requests per second allowed for each hook.
+    pub rate: f64,
+    /// How far the timestamp of a signed request may be from the current time.
+    pub timestamp_tolerance: Duration,
+}
+
+impl Default for WebhookServerConfig {
+    fn default() -> Self {
+        Self {
+            max_payload: 16 * 1024,
+            burst: 10,
+            rate: 1.0,
+            timestamp_tolerance: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+impl WebhookServerConfig {
+    /// Fails with [`crate::error::Error::InvalidRate`] unless the rate is positive and finite,
+    /// and with [`crate::error::Error::InvalidBurst`] if the burst is 0.
+    pub fn validate(&self) -> Result<()> {
+        check_rate(self.rate)?;
+        check_burst(self.burst)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Hook {
+    pub token: String,
+    pub hub_id: ID,
+    pub channel_id: ID,
+    /// If set, requests must carry a signature made with
+    /// [`crate::signature::sign_timestamped`] in [`SIGNATURE_HEADER`] and its time in
+    /// [`TIMESTAMP_HEADER`], within [`WebhookServerConfig::timestamp_tolerance`] of now.
+    pub secret: Option<String>,
+    /// Template used to turn JSON payloads into a message.
+    pub template: Option<String>,
+}
+
+impl Hook {
+    pub fn path(&self) -> String {
+        format!("{}{}", HOOK_PATH_PREFIX, self.token)
+    }
+}
+
+struct HookState {
+    hook: Hook,
+    bucket: Mutex<TokenBucket>,
+}
+
+pub struct WebhookServer {
+    client: Arc<HttpClient>,
+    config: WebhookServerConfig,
+    hooks: RwLock<HashMap<String, HookState>>,
+}
+
+impl WebhookServer {
+    /// Fails if the configuration is not valid, see [`WebhookServerConfig::validate`].
+    pub fn new(client: Arc<HttpClient>, config: WebhookServerConfig) -> Result<Self> {
+        config.validate()?;
+        Ok(Self {
+            client,
+            config,
+            hooks: RwLock::new(HashMap::new()),
+        })
+    }
+
+    /// Creates a hook with a new random token for the given channel.
+    pub fn add_hook(
+        &self,
+        hub_id: ID,
+        channel_id: ID,
+        secret: Option<String>,
+        template: Option<String>,
+    ) -> Hook {
+        let hook = Hook {
+            token: format!(
+                "{}{}",
+                Uuid::new_v4().to_simple(),
+                Uuid::new_v4().to_simple()
+            ),
+            hub_id,
+            channel_id,
+            secret,
+            template,
+        };
+        self.insert_hook(hook.clone());
+        hook
+    }
+
+    /// Registers a hook with a known token, replacing any hook with the same token.
+    pub fn insert_hook(&self, hook: Hook) {
+        let state = HookState {
+            bucket: Mutex::new(TokenBucket::new(self.config.burst)),
+            hook,
+        };
+        self.hooks
+            .write()
+            .unwrap()
+            .insert(state.hook.token.clone(), state);
+    }
+
+    pub fn remove_hook(&self, token: &str) -> Option<Hook> {
+        self.hooks
+            .write()
+            .unwrap()
+            .remove(token)
+            .map(|state| state.hook)
+    }
+
+    pub fn hooks(&self) -> Vec<Hook> {
+        self.hooks
+            .read()
+            .unwrap()
+            .values()
+            .map(|state| state.hook.clone())
+            .collect()
+    }
+
+    /// Serves hooks on `addr` until the server fails.
+    pub async fn serve(self: Arc<Self>, addr: SocketAddr) -> Result<()> {
+        let make_service = make_service_fn(move |_| {
+            let server = Arc::clone(&self);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let server = Arc::clone(&server);
+                    async move { Ok::<_, Infallible>(server.handle(request).await) }
+                }))
+            }
+        });
+        Server::bind(&addr).serve(make_service).await?;
+        Ok(())
+    }
+
+    /// Handles a single request, can be called from an existing hyper service to embed the hooks
+    /// in another server.
+    pub async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let token = match request.uri().path().strip_prefix(HOOK_PATH_PREFIX) {
+            Some(token) => token.to_string(),
+            None => return respond(StatusCode::NOT_FOUND, "unknown hook"),
+        };
+        if request.method() != Method::POST {
+            return respond(StatusCode::METHOD_NOT_ALLOWED, "hooks only accept POST");
+        }
+        let hook = match self.hooks.read().unwrap().get(&token) {
+            Some(state) => state.hook.clone(),
+            None => return respond(StatusCode::NOT_FOUND, "unknown hook"),
+        };
+
+        let declared_length = request
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if matches!(declared_length, Some(length) if length > self.config.max_payload) {
+            return respond(StatusCode::PAYLOAD_TOO_LARGE, "payload too large");
+        }
+        let content_type = request
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        let is_json = matches!(content_type, Some(value) if value.starts_with("application/json"));
+        let header = |name| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let signature = header(SIGNATURE_HEADER);
+        let timestamp = header(TIMESTAMP_HEADER);
+
+        let mut body = request.into_body();
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(_) => return respond(StatusCode::BAD_REQUEST, "failed to read body"),
+            };
+            if bytes.len() + chunk.len() > self.config.max_payload {
+                return respond(StatusCode::PAYLOAD_TOO_LARGE, "payload too large");
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        // Only signed requests are charged, so forged requests can not use up the hook's limit.
+        if let Some(secret) = &hook.secret {
+            let valid = matches!(
+                (&timestamp, &signature),
+                (Some(timestamp), Some(signature)) if verify_timestamped(
+                    secret.as_bytes(),
+                    timestamp,
+                    &bytes,
+                    signature,
+                    self.config.timestamp_tolerance,
+                )
+            );
+            if !valid {
+                return respond(StatusCode::UNAUTHORIZED, "invalid signature");
+            }
+        }
+
+        let limited = match self.hooks.read().unwrap().get(&token) {
+            Some(state) => state
+                .bucket
+                .lock()
+                .unwrap()
+                .take(self.config.burst, self.config.rate),
+            None => return respond(StatusCode::NOT_FOUND, "unknown hook"),
+        };
+        if let Err(retry_after) = limited {
+            let mut response = respond(StatusCode::TOO_MANY_REQUESTS, "rate limited");
+            response.headers_mut().insert(
+                RETRY_AFTER,
+                (retry_after.as_secs() + 1).to_string().parse().unwrap(),
+            );
+            return response;
+        }
+
+        let message = match message_from_payload(&bytes, is_json, hook.template.as_deref()) {
+            Ok(message) if !message.trim().is_empty() => message,
+            Ok(_) => return respond(StatusCode::BAD_REQUEST, "empty message"),
+            Err(error) => return respond(StatusCode::BAD_REQUEST, error),
+        };
+        match self
+            .client
+            .message_send(hook.hub_id, hook.channel_id, message)
+            .await
+        {
+            Ok(message_id) => respond(StatusCode::OK, message_id.to_string()),
+            Err(error) => respond(StatusCode::BAD_GATEWAY, error.to_string()),
+        }
+    }
+}
+
+fn respond<B: Into<Body>>(status: StatusCode, body: B) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+/// Builds the message text for a payload, JSON payloads without a template use their `text`
+/// field.
+pub fn message_from_payload(
+    bytes: &[u8],
+    is_json: bool,
+    template: Option<&str>,
+) -> std::result::Result<String, &'static str> {
+    let text = std::str::from_utf8(bytes).map_err(|_| "payload is not valid UTF-8")?;
+    if !is_json {
+        return Ok(text.to_string());
+    }
+    let value: Value = serde_json::from_str(text).map_err(|_| "payload is not valid JSON")?;
+    match template {
+        Some(template) => Ok(render_template(template, &value)),
+        None => match value.get("text") {
+            Some(Value::String(text)) => Ok(text.clone()),
+            _ => Err("JSON payloads need a text field or a template"),
+        },
+    }
+}
+
+/// Replaces `{{path.to.field}}` placeholders with values from `value`, missing fields are
+/// replaced with nothing.
+pub fn render_template(template: &str, value: &Value) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        output.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}") {
+            Some(end) => {
+                let path = after[..end].trim();
+                let field =
+                    path.split('.')
+                        .filter(|key| !key.is_empty())
+                        .try_fold(value, |value, key| match value {
+                            Value::Array(array) => {
+                                key.parse::<usize>().ok().and_then(|i| array.get(i))
+                            }
+                            _ => value.get(key),
+                        });
+                match field {
+                    Some(Value::String(string)) => output.push_str(string),
+                    Some(Value::Null) | None => {}
+                    Some(other) => output.push_str(&other.to_string()),
+                }
+                rest = &after[end + 2..];
+            }
+            None => {
+                output.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use chrono::Utc;
+
+    use crate::signature::{sign, sign_timestamped};
+
+    fn server(burst: u32) -> WebhookServer {
+        let client = HttpClient::new(ID::new_v4(), "http://127.0.0.1:9/api".to_string()).unwrap();
+        let config = WebhookServerConfig {
+            burst,
+            rate: 0.001,
+            ..WebhookServerConfig::default()
+        };
+        WebhookServer::new(Arc::new(client), config).unwrap()
+    }
+
+    fn request(hook: &Hook, body: &str, signature: Option<(i64, String)>) -> Request<Body> {
+        let mut request = Request::post(hook.path()).header(CONTENT_TYPE, "text/plain");
+        if let Some((timestamp, signature)) = signature {
+            request = request
+                .header(TIMESTAMP_HEADER, timestamp)
+                .header(SIGNATURE_HEADER, signature);
+        }
+        request.body(Body::from(body.to_string())).unwrap()
+    }
+
+    /// A signature of `body` made `age` seconds ago.
+    fn signed(body: &str, age: i64) -> Option<(i64, String)> {
+        let timestamp = Utc::now().timestamp() - age;
+        Some((
+            timestamp,
+            sign_timestamped(b"secret", timestamp, body.as_bytes()),
+        ))
+    }
+
+    #[test]
+    fn renders_templates() {
+        let value = json!({
+            "user": { "name": "alice", "age": 30 },
+            "items": ["first", "second"],
+            "empty": null,
+        });
+        assert_eq!(
+            render_template("{{ user.name }} ({{user.age}}) {{items.1}}", &value),
+            "alice (30) second"
+        );
+        assert_eq!(render_template("[{{missing}}{{empty}}]", &value), "[]");
+        assert_eq!(
+            render_template("{{user}}", &value),
+            r#"{"age":30,"name":"alice"}"#
+        );
+        assert_eq!(
+            render_template("open {{user.name", &value),
+            "open {{user.name"
+        );
+    }
+
+    #[test]
+    fn builds_messages_from_payloads() {
+        assert_eq!(
+            message_from_payload(b"plain", false, None).unwrap(),
+            "plain"
+        );
+        assert_eq!(
+            message_from_payload(br#"{"text":"hi"}"#, true, None).unwrap(),
+            "hi"
+        );
+        assert_eq!(
+            message_from_payload(br#"{"a":"b"}"#, true, Some("a={{a}}")).unwrap(),
+            "a=b"
+        );
+        assert!(message_from_payload(br#"{"a":"b"}"#, true, None).is_err());
+        assert!(message_from_payload(b"{", true, None).is_err());
+        assert!(message_from_payload(&[0xff], false, None).is_err());
+    }
+
+    #[test]
+    fn rejects_unusable_rates() {
+        let client = HttpClient::new(ID::new_v4(), "http://127.0.0.1:9/api".to_string()).unwrap();
+        let config = WebhookServerConfig {
+            rate: 0.0,
+            ..WebhookServerConfig::default()
+        };
+        assert!(WebhookServer::new(Arc::new(client), config).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_bursts() {
+        let config = WebhookServerConfig {
+            burst: 0,
+            ..WebhookServerConfig::default()
+        };
+        assert!(matches!(
+            config.validate(),
+            Err(crate::error::Error::InvalidBurst)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_replayed_and_untimestamped_requests() {
+        let server = server(10);
+        let hook = server.add_hook(ID::new_v4(), ID::new_v4(), Some("secret".to_string()), None);
+        let replayed = request(&hook, "old", signed("old", 60 * 60));
+        assert_eq!(
+            server.handle(replayed).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let untimestamped = Request::post(hook.path())
+            .header(SIGNATURE_HEADER, sign(b"secret", b"plain"))
+            .body(Body::from("plain"))
+            .unwrap();
+        assert_eq!(
+            server.handle(untimestamped).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let fresh = request(&hook, "fresh", signed("fresh", 10));
+        assert_eq!(server.handle(fresh).await.status(), StatusCode::BAD_GATEWAY);
+    }
+
+    #[tokio::test]
+    async fn forged_requests_do_not_use_up_the_rate_limit() {
+        let server = server(1);
+        let hook = server.add_hook(ID::new_v4(), ID::new_v4(), Some("secret".to_string()), None);
+        for _ in 0..3 {
+            let response = server
+                .handle(request(&hook, "forged", Some((0, "00".to_string()))))
+                .await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let valid = request(&hook, "signed", signed("signed", 0));
+        // The API is unreachable, but the request got past the rate limit.
+        assert_eq!(server.handle(valid).await.status(), StatusCode::BAD_GATEWAY);
+        let valid = request(&hook, "signed", signed("signed", 0));
+        assert_eq!(
+            server.handle(valid).await.status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+    }
+}
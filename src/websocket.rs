@@ -1,45 +1,353 @@
-use std::sync::Arc;
+use std::{
+    collections::HashSet,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use crate::{error::Result, Error};
-use wicrs_server::prelude::{WsClientMessage, WsServerMessage, ID};
+use crate::{
+    error::Result,
+    protocol::{WsClientMessage, WsServerMessage, ID},
+    version::{ProtocolVersion, VERSION_HEADER},
+    Error,
+};
+use serde_json::Value;
+
+/// How long `close` waits for the server to answer the close handshake.
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait after the first failed reconnect, doubled after every further failure.
+pub const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+/// Longest wait between reconnects.
+pub const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+/// A hub or channel the client receives events for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Subscription {
+    Hub(ID),
+    Channel(ID, ID),
+}
+
+impl Subscription {
+    pub(crate) fn subscribe_message(self) -> WsClientMessage {
+        match self {
+            Subscription::Hub(hub_id) => WsClientMessage::SubscribeHub { hub_id },
+            Subscription::Channel(hub_id, channel_id) => {
+                WsClientMessage::SubscribeChannel { hub_id, channel_id }
+            }
+        }
+    }
+
+    pub(crate) fn unsubscribe_message(self) -> WsClientMessage {
+        match self {
+            Subscription::Hub(hub_id) => WsClientMessage::UnsubscribeHub { hub_id },
+            Subscription::Channel(hub_id, channel_id) => {
+                WsClientMessage::UnsubscribeChannel { hub_id, channel_id }
+            }
+        }
+    }
+}
+
+/// Handshake request for the websocket endpoint of a server.
+fn handshake_request(user_id: ID, server_api_url: &str) -> http::Request<()> {
+    http::Request::builder()
+        .uri(&format!("{}/websocket", server_api_url))
+        .header("authorization", &user_id.to_string())
+        .header(VERSION_HEADER, ProtocolVersion::CURRENT.to_string())
+        .body(())
+        .unwrap()
+}
+
+/// Protocol version the server sent in its handshake response.
+fn handshake_version<B>(response: &http::Response<B>) -> Result<ProtocolVersion> {
+    ProtocolVersion::from_headers(response.headers())?.check_compatible()
+}
+
+/// Code and reason of a websocket close frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseReason {
+    pub code: u16,
+    pub reason: String,
+}
+
+/// Keepalive settings for websocket connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Send a ping once nothing has been received for this long, `None` disables pings.
+    pub ping_interval: Option<Duration>,
+    /// Fail with [`Error::HeartbeatTimeout`] if nothing is received this long after a ping.
+    pub pong_timeout: Duration,
+    /// Fail with [`Error::HeartbeatTimeout`] if nothing at all is received for this long.
+    pub idle_timeout: Option<Duration>,
+    /// Reconnect instead of failing when the heartbeat fails or the connection is lost while a
+    /// message loop is running. Failed reconnects are retried with backoff until the client is
+    /// closed.
+    pub reconnect: bool,
+}
+
+impl HeartbeatConfig {
+    pub fn disabled() -> Self {
+        Self {
+            ping_interval: None,
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: None,
+            reconnect: false,
+        }
+    }
+}
+
+/// Heartbeats are disabled by default, set [`HeartbeatConfig::ping_interval`] to send pings.
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self::disabled()
+    }
+}
+
+#[derive(Debug)]
+struct HeartbeatState {
+    last_received: Instant,
+    ping_sent: Option<Instant>,
+}
+
+impl HeartbeatState {
+    fn new() -> Self {
+        Self {
+            last_received: Instant::now(),
+            ping_sent: None,
+        }
+    }
+
+    fn received(&mut self) {
+        self.last_received = Instant::now();
+        self.ping_sent = None;
+    }
+
+    /// The next time [`HeartbeatState::check`] needs to run.
+    fn deadline(&self, config: &HeartbeatConfig) -> Option<Instant> {
+        let idle = config
+            .idle_timeout
+            .map(|timeout| self.last_received + timeout);
+        let ping = match self.ping_sent {
+            Some(sent) => Some(sent + config.pong_timeout),
+            None => config
+                .ping_interval
+                .map(|interval| self.last_received + interval),
+        };
+        match (idle, ping) {
+            (Some(idle), Some(ping)) => Some(idle.min(ping)),
+            (idle, ping) => idle.or(ping),
+        }
+    }
+
+    /// Returns whether a ping has to be sent now, or an error if the server stopped responding.
+    fn check(&mut self, config: &HeartbeatConfig) -> Result<bool> {
+        let now = Instant::now();
+        if let Some(timeout) = config.idle_timeout {
+            if now >= self.last_received + timeout {
+                return Err(Error::HeartbeatTimeout(timeout));
+            }
+        }
+        match (self.ping_sent, config.ping_interval) {
+            (Some(sent), _) if now >= sent + config.pong_timeout => {
+                Err(Error::HeartbeatTimeout(config.pong_timeout))
+            }
+            (None, Some(interval)) if now >= self.last_received + interval => {
+                self.ping_sent = Some(now);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+}
+
+/// Name of the variant of a websocket message, used to label metrics.
+pub trait MessageKind {
+    fn kind(&self) -> &'static str;
+}
+
+impl MessageKind for WsClientMessage {
+    fn kind(&self) -> &'static str {
+        match self {
+            WsClientMessage::SubscribeHub { .. } => "SubscribeHub",
+            WsClientMessage::UnsubscribeHub { .. } => "UnsubscribeHub",
+            WsClientMessage::SubscribeChannel { .. } => "SubscribeChannel",
+            WsClientMessage::UnsubscribeChannel { .. } => "UnsubscribeChannel",
+            WsClientMessage::StartTyping { .. } => "StartTyping",
+            WsClientMessage::StopTyping { .. } => "StopTyping",
+            WsClientMessage::SendMessage { .. } => "SendMessage",
+        }
+    }
+}
+
+impl MessageKind for WsServerMessage {
+    fn kind(&self) -> &'static str {
+        match self {
+            WsServerMessage::Error(_) => "Error",
+            WsServerMessage::InvalidCommand => "InvalidCommand",
+            WsServerMessage::NotSigned => "NotSigned",
+            WsServerMessage::CommandFailed => "CommandFailed",
+            WsServerMessage::ChatMessage { .. } => "ChatMessage",
+            WsServerMessage::HubUpdated { .. } => "HubUpdated",
+            WsServerMessage::Success => "Success",
+            WsServerMessage::UserStartedTyping { .. } => "UserStartedTyping",
+            WsServerMessage::UserStoppedTyping { .. } => "UserStoppedTyping",
+        }
+    }
+}
+
+/// Websocket messages are externally tagged enums, unit variants serialize to their name and
+/// the others to an object with the variant name as the only key.
+#[cfg_attr(not(feature = "tracing"), allow(dead_code))]
+pub(crate) fn variant_name(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(kind) => Some(kind),
+        Value::Object(map) => map.keys().next().map(String::as_str),
+        _ => None,
+    }
+}
 
 pub mod syncws {
     use super::*;
     use std::net::TcpStream;
     use tungstenite::{connect, stream::MaybeTlsStream, WebSocket};
-    use tungstenite::{handshake::client::Request, Message};
+    use tungstenite::{protocol::CloseFrame, Message};
 
     pub struct WebsocketClient {
         pub user_id: ID,
+        server_version: ProtocolVersion,
         websocket: WebSocket<MaybeTlsStream<TcpStream>>,
+        heartbeat: HeartbeatConfig,
+        heartbeat_state: HeartbeatState,
+        subscriptions: HashSet<Subscription>,
+        close_reason: Option<CloseReason>,
     }
 
     impl WebsocketClient {
         pub fn new(user_id: ID, server_api_url: &str) -> Result<Arc<Self>> {
-            let request = Request::builder()
-                .uri(&format!("{}/websocket", server_api_url))
-                .header("authorization", &user_id.to_string())
-                .body(())
-                .unwrap();
-            let (mut websocket, _) = connect(request)?;
+            Self::new_with_heartbeat(user_id, server_api_url, HeartbeatConfig::default())
+        }
+
+        pub fn new_with_heartbeat(
+            user_id: ID,
+            server_api_url: &str,
+            heartbeat: HeartbeatConfig,
+        ) -> Result<Arc<Self>> {
+            #[cfg(feature = "tracing")]
+            let result = crate::trace::traced(
+                &crate::trace::ws_connect_span(server_api_url, user_id),
+                || Self::connect(user_id, server_api_url, heartbeat),
+            );
+            #[cfg(not(feature = "tracing"))]
+            let result = Self::connect(user_id, server_api_url, heartbeat);
+            crate::metrics::ws_connect(&result);
+            result
+        }
+
+        fn connect(
+            user_id: ID,
+            server_api_url: &str,
+            heartbeat: HeartbeatConfig,
+        ) -> Result<Arc<Self>> {
+            let (mut websocket, response) = connect(handshake_request(user_id, server_api_url))?;
+            let server_version = handshake_version(&response)?;
             websocket.write_message(Message::Text(user_id.to_string()))?;
-            Ok(Arc::new(Self { user_id, websocket }))
+            Ok(Arc::new(Self {
+                user_id,
+                server_version,
+                websocket,
+                heartbeat,
+                heartbeat_state: HeartbeatState::new(),
+                subscriptions: HashSet::new(),
+                close_reason: None,
+            }))
+        }
+
+        /// Sets the timeout on the TCP stream, under the TLS session for `wss` connections.
+        fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+            let stream = match self.websocket.get_ref() {
+                MaybeTlsStream::Plain(stream) => stream,
+                #[cfg(feature = "native-tls")]
+                MaybeTlsStream::NativeTls(stream) => stream.get_ref(),
+                #[cfg(feature = "rustls-tls")]
+                MaybeTlsStream::Rustls(stream) => &stream.sock,
+                // TLS backends enabled directly on `tungstenite` instead of through this crate.
+                #[allow(unreachable_patterns)]
+                _ if timeout.is_none() => return Ok(()),
+                #[allow(unreachable_patterns)]
+                _ => return Err(Error::ReadTimeoutUnsupported),
+            };
+            stream.set_read_timeout(timeout)?;
+            Ok(())
         }
 
         pub fn next_ws_message(&mut self) -> Result<WsServerMessage> {
-            if let Ok(message) = self.websocket.read_message() {
-                let text = message.to_text()?;
-                Ok(serde_json::from_str(text)?)
-            } else {
-                Err(Error::WsClosed)
+            #[cfg(feature = "tracing")]
+            let result = {
+                let span = crate::trace::ws_receive_span();
+                let result = crate::trace::traced(&span, || self.read_ws_message());
+                if let Ok(message) = &result {
+                    crate::trace::record_received(&span, message);
+                }
+                result
+            };
+            #[cfg(not(feature = "tracing"))]
+            let result = self.read_ws_message();
+            crate::metrics::ws_received(&result);
+            result
+        }
+
+        fn read_ws_message(&mut self) -> Result<WsServerMessage> {
+            loop {
+                // Blocking reads time out at the next heartbeat deadline so pings can be sent.
+                let timeout = self
+                    .heartbeat_state
+                    .deadline(&self.heartbeat)
+                    .map(|deadline| {
+                        deadline
+                            .saturating_duration_since(Instant::now())
+                            .max(Duration::from_millis(1))
+                    });
+                self.set_read_timeout(timeout)?;
+                match self.websocket.read_message() {
+                    Ok(Message::Ping(_)) | Ok(Message::Pong(_)) => self.heartbeat_state.received(),
+                    Ok(Message::Close(frame)) => {
+                        self.close_reason = close_reason(frame);
+                        return Err(Error::WsClosed);
+                    }
+                    Ok(message) => {
+                        self.heartbeat_state.received();
+                        let text = message.to_text()?;
+                        return Ok(serde_json::from_str(text)?);
+                    }
+                    Err(tungstenite::Error::Io(error))
+                        if matches!(
+                            error.kind(),
+                            std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        if self.heartbeat_state.check(&self.heartbeat)? {
+                            self.websocket.write_message(Message::Ping(Vec::new()))?;
+                        }
+                    }
+                    Err(_) => return Err(Error::WsClosed),
+                }
             }
         }
 
         /// Sends a message to the server over websocket, if self.sender is not locked, do not wait for response...
         fn send_ws_message(&mut self, message: WsClientMessage) -> Result<()> {
+            #[cfg(feature = "tracing")]
+            let result = crate::trace::traced(&crate::trace::ws_send_span(&message), || {
+                self.write_ws_message(&message)
+            });
+            #[cfg(not(feature = "tracing"))]
+            let result = self.write_ws_message(&message);
+            crate::metrics::ws_sent(&message, &result);
+            result
+        }
+
+        fn write_ws_message(&mut self, message: &WsClientMessage) -> Result<()> {
             Ok(self
                 .websocket
-                .write_message(Message::Text(serde_json::to_string(&message)?))?)
+                .write_message(Message::Text(serde_json::to_string(message)?))?)
         }
 
         pub fn send_message(&mut self, hub_id: ID, channel_id: ID, message: String) -> Result<()> {
@@ -51,19 +359,74 @@ pub mod syncws {
         }
 
         pub fn subscribe_hub(&mut self, hub_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::SubscribeHub { hub_id })
+            self.subscribe(Subscription::Hub(hub_id))
         }
 
         pub fn subscribe_channel(&mut self, hub_id: ID, channel_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::SubscribeChannel { hub_id, channel_id })
+            self.subscribe(Subscription::Channel(hub_id, channel_id))
         }
 
         pub fn unsubscribe_hub(&mut self, hub_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::UnsubscribeHub { hub_id })
+            self.unsubscribe(Subscription::Hub(hub_id))
         }
 
         pub fn unsubscribe_channel(&mut self, hub_id: ID, channel_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::UnsubscribeChannel { hub_id, channel_id })
+            self.unsubscribe(Subscription::Channel(hub_id, channel_id))
+        }
+
+        pub fn subscribe(&mut self, subscription: Subscription) -> Result<()> {
+            self.send_ws_message(subscription.subscribe_message())?;
+            self.subscriptions.insert(subscription);
+            Ok(())
+        }
+
+        pub fn unsubscribe(&mut self, subscription: Subscription) -> Result<()> {
+            self.send_ws_message(subscription.unsubscribe_message())?;
+            self.subscriptions.remove(&subscription);
+            Ok(())
+        }
+
+        pub fn subscriptions(&self) -> Vec<Subscription> {
+            self.subscriptions.iter().copied().collect()
+        }
+
+        /// Close code and reason sent by the server when it closed the connection.
+        pub fn close_reason(&self) -> Option<CloseReason> {
+            self.close_reason.clone()
+        }
+
+        /// Protocol version the server sent when connecting.
+        pub fn server_version(&self) -> ProtocolVersion {
+            self.server_version
+        }
+
+        /// Unsubscribes from everything and closes the connection with the given code and
+        /// reason, waits up to [`CLOSE_TIMEOUT`] for the server to answer and returns its close
+        /// reason.
+        pub fn close(&mut self, code: u16, reason: &str) -> Result<Option<CloseReason>> {
+            for subscription in self.subscriptions() {
+                self.unsubscribe(subscription)?;
+            }
+            self.websocket.close(Some(CloseFrame {
+                code: code.into(),
+                reason: reason.to_string().into(),
+            }))?;
+            let deadline = Instant::now() + CLOSE_TIMEOUT;
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining == Duration::from_secs(0) {
+                    break;
+                }
+                self.set_read_timeout(Some(remaining))?;
+                match self.websocket.read_message() {
+                    Ok(Message::Close(frame)) => {
+                        self.close_reason = close_reason(frame);
+                    }
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+            Ok(self.close_reason.clone())
         }
 
         pub fn start_typing(&mut self, hub_id: ID, channel_id: ID) -> Result<()> {
@@ -74,6 +437,13 @@ pub mod syncws {
             self.send_ws_message(WsClientMessage::StopTyping { hub_id, channel_id })
         }
     }
+
+    fn close_reason(frame: Option<CloseFrame>) -> Option<CloseReason> {
+        frame.map(|frame| CloseReason {
+            code: frame.code.into(),
+            reason: frame.reason.into_owned(),
+        })
+    }
 }
 
 #[cfg(feature = "use-tokio")]
@@ -84,62 +454,208 @@ pub mod asyncws {
         stream::{SplitSink, SplitStream},
         SinkExt, StreamExt,
     };
-    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::{
+        collections::VecDeque,
+        sync::atomic::{AtomicBool, Ordering},
+        time::Instant,
+    };
     use tokio::{
         net::TcpStream,
         sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
-        sync::Mutex,
+        sync::{watch, Mutex},
     };
     use tokio_tungstenite::{
         connect_async,
-        tungstenite::{handshake::client::Request, Message},
+        tungstenite::{protocol::CloseFrame, Message},
         MaybeTlsStream, WebSocketStream,
     };
+    use tokio_util::sync::CancellationToken;
+
+    type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
 
     pub struct WebsocketClient {
         pub user_id: ID,
-        websocket_send: Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>,
-        websocket_recv: Mutex<SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>>,
+        server_api_url: String,
+        server_version: std::sync::Mutex<ProtocolVersion>,
+        websocket_send: Mutex<SplitSink<WsStream, Message>>,
+        websocket_recv: Mutex<SplitStream<WsStream>>,
         sender: Mutex<UnboundedSender<Result<()>>>,
         receiver: Mutex<UnboundedReceiver<Result<()>>>,
-        loop_running: AtomicBool,
+        /// Fails the pending ack when its connection is replaced, `sender` is held by the loop.
+        ack_failures: UnboundedSender<Result<()>>,
+        /// Whether a command sent on the current connection is waiting for its ack.
+        awaiting_ack: AtomicBool,
+        /// Events received while restoring subscriptions, read before the new connection.
+        replayed: std::sync::Mutex<VecDeque<WsServerMessage>>,
+        loop_running: watch::Sender<bool>,
+        loop_state: watch::Receiver<bool>,
+        heartbeat: HeartbeatConfig,
+        heartbeat_state: std::sync::Mutex<HeartbeatState>,
+        subscriptions: std::sync::Mutex<HashSet<Subscription>>,
+        close_reason: std::sync::Mutex<Option<CloseReason>>,
+        cancel: CancellationToken,
     }
 
     impl WebsocketClient {
         pub async fn new(user_id: ID, server_api_url: &str) -> Result<Arc<Self>> {
-            let request = Request::builder()
-                .uri(&format!("{}/websocket", server_api_url))
-                .header("authorization", &user_id.to_string())
-                .body(())
-                .unwrap();
-            let (websocket, _) = connect_async(request).await?;
+            Self::new_with_heartbeat(user_id, server_api_url, HeartbeatConfig::default()).await
+        }
+
+        pub async fn new_with_heartbeat(
+            user_id: ID,
+            server_api_url: &str,
+            heartbeat: HeartbeatConfig,
+        ) -> Result<Arc<Self>> {
+            #[cfg(feature = "tracing")]
+            let result = crate::trace::traced_async(
+                &crate::trace::ws_connect_span(server_api_url, user_id),
+                Self::connect(user_id, server_api_url, heartbeat),
+            )
+            .await;
+            #[cfg(not(feature = "tracing"))]
+            let result = Self::connect(user_id, server_api_url, heartbeat).await;
+            crate::metrics::ws_connect(&result);
+            result
+        }
+
+        async fn open(
+            user_id: ID,
+            server_api_url: &str,
+        ) -> Result<(
+            SplitSink<WsStream, Message>,
+            SplitStream<WsStream>,
+            ProtocolVersion,
+        )> {
+            let (websocket, response) =
+                connect_async(handshake_request(user_id, server_api_url)).await?;
+            let server_version = handshake_version(&response)?;
             let (mut s, r) = websocket.split();
             s.send(Message::Text(user_id.to_string())).await?;
+            Ok((s, r, server_version))
+        }
+
+        async fn connect(
+            user_id: ID,
+            server_api_url: &str,
+            heartbeat: HeartbeatConfig,
+        ) -> Result<Arc<Self>> {
+            let (s, r, server_version) = Self::open(user_id, server_api_url).await?;
             let (send, recv) = unbounded_channel();
+            let (loop_running, loop_state) = watch::channel(false);
             Ok(Arc::new(Self {
                 user_id,
+                server_api_url: server_api_url.to_string(),
+                server_version: std::sync::Mutex::new(server_version),
                 websocket_send: Mutex::new(s),
                 websocket_recv: Mutex::new(r),
+                ack_failures: send.clone(),
                 sender: Mutex::new(send),
                 receiver: Mutex::new(recv),
-                loop_running: AtomicBool::new(false),
+                awaiting_ack: AtomicBool::new(false),
+                replayed: std::sync::Mutex::new(VecDeque::new()),
+                loop_running,
+                loop_state,
+                heartbeat,
+                heartbeat_state: std::sync::Mutex::new(HeartbeatState::new()),
+                subscriptions: std::sync::Mutex::new(HashSet::new()),
+                close_reason: std::sync::Mutex::new(None),
+                cancel: CancellationToken::new(),
             }))
         }
 
+        /// Replaces the connection with a new one and restores the subscriptions of the old one,
+        /// this is done by `start_loop` when the connection is lost and
+        /// [`HeartbeatConfig::reconnect`] is set. Events received while restoring the
+        /// subscriptions are kept for the next reads and a command still waiting for its ack
+        /// fails with [`Error::WsClosed`].
+        pub async fn reconnect(&self) -> Result<()> {
+            let (mut s, mut r, server_version) =
+                Self::open(self.user_id, &self.server_api_url).await?;
+            let mut replayed = VecDeque::new();
+            for subscription in self.subscriptions() {
+                let message = subscription.subscribe_message();
+                s.send(Message::Text(serde_json::to_string(&message)?))
+                    .await?;
+                loop {
+                    let message = r.next().await.ok_or(Error::WsClosed)??;
+                    if let Message::Text(text) = message {
+                        match serde_json::from_str(&text)? {
+                            WsServerMessage::Success => break,
+                            WsServerMessage::Error(e) => return Err(e.into()),
+                            message => replayed.push_back(message),
+                        }
+                    }
+                }
+            }
+            {
+                let mut websocket_send = self.websocket_send.lock().await;
+                *websocket_send = s;
+                // Commands are sent with this lock held, so none can be waiting for an ack from
+                // the old connection after this.
+                if self.awaiting_ack.swap(false, Ordering::SeqCst) {
+                    let _ = self.ack_failures.send(Err(Error::WsClosed));
+                }
+            }
+            *self.websocket_recv.lock().await = r;
+            self.replayed.lock().unwrap().extend(replayed);
+            *self.heartbeat_state.lock().unwrap() = HeartbeatState::new();
+            *self.server_version.lock().unwrap() = server_version;
+            crate::metrics::record_ws_reconnect();
+            Ok(())
+        }
+
+        /// Calls [`WebsocketClient::reconnect`] until it succeeds, waiting longer after every
+        /// failure. Only cancelling the loop with [`WebsocketClient::close`] stops it.
+        async fn reconnect_with_backoff(&self) {
+            let mut delay = RECONNECT_DELAY;
+            while let Err(error) = self.reconnect().await {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(error = %error, retry_in = ?delay, "websocket reconnect failed");
+                #[cfg(not(feature = "tracing"))]
+                let _ = error;
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+
+        pub fn is_loop_running(&self) -> bool {
+            *self.loop_state.borrow()
+        }
+
+        /// Waits until `start_loop` is running. Commands sent before that are not acknowledged
+        /// by the loop, so their `Success` would be taken as the acknowledgement of a later
+        /// command.
+        pub async fn wait_for_loop(&self) {
+            let mut state = self.loop_state.clone();
+            while !*state.borrow() {
+                if state.changed().await.is_err() {
+                    return;
+                }
+            }
+        }
+
         pub async fn start_loop<F, R>(self: Arc<Self>, action: F) -> Result<R>
         where
             F: Fn(Arc<Self>, WsServerMessage) -> Option<R>,
         {
             let sender = self.sender.lock().await;
-            self.loop_running.store(true, Ordering::Release);
+            let _ = self.loop_running.send(true);
             let message_loop = || async {
                 loop {
-                    let message = self.next_ws_message().await?;
+                    let message = match self.next_ws_message().await {
+                        Err(error) if self.heartbeat.reconnect && ends_connection(&error) => {
+                            self.reconnect_with_backoff().await;
+                            continue;
+                        }
+                        result => result?,
+                    };
                     match message {
                         WsServerMessage::Success => {
+                            self.awaiting_ack.store(false, Ordering::SeqCst);
                             sender.send(Ok(())).map_err(|_| Error::TokioMpscSend)?;
                         }
                         WsServerMessage::Error(e) => {
+                            self.awaiting_ack.store(false, Ordering::SeqCst);
                             sender
                                 .send(Err(e.into()))
                                 .map_err(|_| Error::TokioMpscSend)?;
@@ -152,36 +668,112 @@ pub mod asyncws {
                     }
                 }
             };
-            let result = message_loop().await;
-            self.loop_running.store(false, Ordering::Release);
+            let result = tokio::select! {
+                result = message_loop() => result,
+                _ = self.cancel.cancelled() => Err(Error::WsShutdown),
+            };
+            let _ = self.loop_running.send(false);
+            if self.awaiting_ack.swap(false, Ordering::SeqCst) {
+                let _ = sender.send(Err(Error::WsClosed));
+            }
             result
         }
 
         pub async fn next_ws_message(&self) -> Result<WsServerMessage> {
-            if let Some(message) = {
-                let mut lock = self.websocket_recv.lock().await;
-                lock.next().await
-            } {
-                let message = message?;
-                let text = message.to_text()?;
-                Ok(serde_json::from_str(text)?)
-            } else {
-                Err(Error::WsClosed)
+            #[cfg(feature = "tracing")]
+            let result = {
+                let span = crate::trace::ws_receive_span();
+                let result = crate::trace::traced_async(&span, self.read_ws_message()).await;
+                if let Ok(message) = &result {
+                    crate::trace::record_received(&span, message);
+                }
+                result
+            };
+            #[cfg(not(feature = "tracing"))]
+            let result = self.read_ws_message().await;
+            crate::metrics::ws_received(&result);
+            result
+        }
+
+        async fn read_ws_message(&self) -> Result<WsServerMessage> {
+            let mut websocket = self.websocket_recv.lock().await;
+            if let Some(message) = self.replayed.lock().unwrap().pop_front() {
+                return Ok(message);
+            }
+            loop {
+                let deadline = self
+                    .heartbeat_state
+                    .lock()
+                    .unwrap()
+                    .deadline(&self.heartbeat);
+                let next = match deadline {
+                    Some(deadline) => {
+                        match tokio::time::timeout_at(deadline.into(), websocket.next()).await {
+                            Ok(next) => next,
+                            Err(_) => {
+                                let ping = self
+                                    .heartbeat_state
+                                    .lock()
+                                    .unwrap()
+                                    .check(&self.heartbeat)?;
+                                if ping {
+                                    let mut lock = self.websocket_send.lock().await;
+                                    lock.send(Message::Ping(Vec::new())).await?;
+                                }
+                                continue;
+                            }
+                        }
+                    }
+                    None => websocket.next().await,
+                };
+                let message = match next {
+                    Some(message) => message?,
+                    None => return Err(Error::WsClosed),
+                };
+                self.heartbeat_state.lock().unwrap().received();
+                match message {
+                    Message::Ping(_) | Message::Pong(_) => continue,
+                    Message::Close(frame) => {
+                        *self.close_reason.lock().unwrap() = close_reason(frame);
+                        return Err(Error::WsClosed);
+                    }
+                    message => {
+                        let text = message.to_text()?;
+                        return Ok(serde_json::from_str(text)?);
+                    }
+                }
             }
         }
 
         /// Sends a message to the server over websocket, if self.sender is not locked, do not wait for response...
         async fn send_ws_message(&self, message: WsClientMessage) -> Result<()> {
+            #[cfg(feature = "tracing")]
+            let result = crate::trace::traced_async(
+                &crate::trace::ws_send_span(&message),
+                self.write_ws_message(&message),
+            )
+            .await;
+            #[cfg(not(feature = "tracing"))]
+            let result = self.write_ws_message(&message).await;
+            crate::metrics::ws_sent(&message, &result);
+            result
+        }
+
+        async fn write_ws_message(&self, message: &WsClientMessage) -> Result<()> {
             let mut receiver = self.receiver.lock().await;
+            let loop_running = self.is_loop_running();
             {
                 let mut lock = self.websocket_send.lock().await;
-                lock.send(Message::Text(serde_json::to_string(&message)?))
+                lock.send(Message::Text(serde_json::to_string(message)?))
                     .await?;
                 lock.flush().await?;
+                self.awaiting_ack.store(loop_running, Ordering::SeqCst);
             }
 
-            if self.loop_running.load(Ordering::Acquire) {
+            if loop_running {
+                let sent = Instant::now();
                 if let Some(result) = receiver.recv().await {
+                    crate::metrics::ws_ack(sent.elapsed());
                     result
                 } else {
                     Err(Error::TokioMpscSend)
@@ -208,25 +800,92 @@ pub mod asyncws {
         }
 
         pub async fn subscribe_hub(&self, hub_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::SubscribeHub { hub_id })
-                .await
+            self.subscribe(Subscription::Hub(hub_id)).await
         }
 
         pub async fn subscribe_channel(&self, hub_id: ID, channel_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::SubscribeChannel { hub_id, channel_id })
+            self.subscribe(Subscription::Channel(hub_id, channel_id))
                 .await
         }
 
         pub async fn unsubscribe_hub(&self, hub_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::UnsubscribeHub { hub_id })
-                .await
+            self.unsubscribe(Subscription::Hub(hub_id)).await
         }
 
         pub async fn unsubscribe_channel(&self, hub_id: ID, channel_id: ID) -> Result<()> {
-            self.send_ws_message(WsClientMessage::UnsubscribeChannel { hub_id, channel_id })
+            self.unsubscribe(Subscription::Channel(hub_id, channel_id))
                 .await
         }
 
+        pub async fn subscribe(&self, subscription: Subscription) -> Result<()> {
+            self.send_ws_message(subscription.subscribe_message())
+                .await?;
+            self.subscriptions.lock().unwrap().insert(subscription);
+            Ok(())
+        }
+
+        pub async fn unsubscribe(&self, subscription: Subscription) -> Result<()> {
+            self.send_ws_message(subscription.unsubscribe_message())
+                .await?;
+            self.subscriptions.lock().unwrap().remove(&subscription);
+            Ok(())
+        }
+
+        pub fn subscriptions(&self) -> Vec<Subscription> {
+            self.subscriptions.lock().unwrap().iter().copied().collect()
+        }
+
+        /// Close code and reason sent by the server when it closed the connection.
+        pub fn close_reason(&self) -> Option<CloseReason> {
+            self.close_reason.lock().unwrap().clone()
+        }
+
+        /// Protocol version the server sent when connecting or reconnecting.
+        pub fn server_version(&self) -> ProtocolVersion {
+            *self.server_version.lock().unwrap()
+        }
+
+        /// Token that stops `start_loop` with [`Error::WsShutdown`] when cancelled.
+        pub fn cancellation_token(&self) -> CancellationToken {
+            self.cancel.clone()
+        }
+
+        /// Unsubscribes from everything, waits for pending acks, stops `start_loop` and closes
+        /// the connection with the given code and reason. Waits up to [`CLOSE_TIMEOUT`] for the
+        /// server to answer and returns its close reason.
+        pub async fn close(&self, code: u16, reason: &str) -> Result<Option<CloseReason>> {
+            for subscription in self.subscriptions() {
+                if let Err(error) = self.unsubscribe(subscription).await {
+                    // Still stops a loop that is waiting to reconnect.
+                    self.cancel.cancel();
+                    return Err(error);
+                }
+            }
+            let _receiver = self.receiver.lock().await;
+            self.cancel.cancel();
+            // `start_loop` holds the sender until it has stopped.
+            let _sender = self.sender.lock().await;
+            {
+                let mut lock = self.websocket_send.lock().await;
+                lock.send(Message::Close(Some(CloseFrame {
+                    code: code.into(),
+                    reason: reason.to_string().into(),
+                })))
+                .await?;
+            }
+            let mut websocket = self.websocket_recv.lock().await;
+            let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                while let Some(Ok(message)) = websocket.next().await {
+                    if let Message::Close(frame) = message {
+                        *self.close_reason.lock().unwrap() = close_reason(frame);
+                        break;
+                    }
+                }
+            })
+            .await;
+            Ok(self.close_reason())
+        }
+
         pub async fn start_typing(&self, hub_id: ID, channel_id: ID) -> Result<()> {
             self.send_ws_message(WsClientMessage::StartTyping { hub_id, channel_id })
                 .await
@@ -237,4 +896,104 @@ pub mod asyncws {
                 .await
         }
     }
+
+    /// Whether the connection can not be used after `error`.
+    fn ends_connection(error: &Error) -> bool {
+        match error {
+            Error::WsClosed | Error::HeartbeatTimeout(_) => true,
+            Error::TokioTungstenite(error) => {
+                !matches!(error, tokio_tungstenite::tungstenite::Error::Utf8)
+            }
+            _ => false,
+        }
+    }
+
+    fn close_reason(frame: Option<CloseFrame>) -> Option<CloseReason> {
+        frame.map(|frame| CloseReason {
+            code: frame.code.into(),
+            reason: frame.reason.into_owned(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(ping_interval: Option<u64>, idle_timeout: Option<u64>) -> HeartbeatConfig {
+        HeartbeatConfig {
+            ping_interval: ping_interval.map(Duration::from_secs),
+            pong_timeout: Duration::from_secs(10),
+            idle_timeout: idle_timeout.map(Duration::from_secs),
+            reconnect: false,
+        }
+    }
+
+    /// State of a connection that last received something `secs` ago.
+    fn received_ago(secs: u64) -> HeartbeatState {
+        HeartbeatState {
+            last_received: Instant::now() - Duration::from_secs(secs),
+            ping_sent: None,
+        }
+    }
+
+    #[test]
+    fn heartbeats_are_disabled_by_default() {
+        assert_eq!(HeartbeatConfig::default(), HeartbeatConfig::disabled());
+    }
+
+    #[test]
+    fn disabled_heartbeats_have_no_deadline() {
+        let mut state = received_ago(3600);
+        assert_eq!(state.deadline(&HeartbeatConfig::disabled()), None);
+        assert!(!state.check(&HeartbeatConfig::disabled()).unwrap());
+    }
+
+    #[test]
+    fn pings_once_idle_for_the_interval() {
+        let config = config(Some(30), None);
+        let mut state = received_ago(10);
+        assert_eq!(
+            state.deadline(&config),
+            Some(state.last_received + Duration::from_secs(30))
+        );
+        assert!(!state.check(&config).unwrap());
+
+        let mut state = received_ago(31);
+        assert!(state.check(&config).unwrap());
+        let sent = state.ping_sent.unwrap();
+        assert_eq!(state.deadline(&config), Some(sent + config.pong_timeout));
+        // The ping is only sent once while waiting for the pong.
+        assert!(!state.check(&config).unwrap());
+    }
+
+    #[test]
+    fn fails_without_a_pong() {
+        let config = config(Some(30), None);
+        let mut state = received_ago(60);
+        state.ping_sent = Some(Instant::now() - Duration::from_secs(11));
+        assert!(matches!(
+            state.check(&config),
+            Err(Error::HeartbeatTimeout(timeout)) if timeout == config.pong_timeout
+        ));
+
+        state.received();
+        assert_eq!(state.ping_sent, None);
+        assert!(!state.check(&config).unwrap());
+    }
+
+    #[test]
+    fn fails_when_idle_for_too_long() {
+        let config = config(Some(30), Some(20));
+        let state = received_ago(0);
+        assert_eq!(
+            state.deadline(&config),
+            Some(state.last_received + Duration::from_secs(20))
+        );
+        let mut state = received_ago(21);
+        assert!(matches!(
+            state.check(&config),
+            Err(Error::HeartbeatTimeout(timeout)) if timeout == Duration::from_secs(20)
+        ));
+    }
 }
@@ -0,0 +1,241 @@
+//! Attachment uploads and resumed downloads against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use common::{MockResponse, MockServer};
+use hyper::{header::RANGE, Method};
+use wicrs_api::{
+    attachment::{attachments_in, Attachment, Progress, ProgressCallback},
+    http::HttpClient,
+    protocol::ID,
+};
+
+fn client(server: &MockServer) -> HttpClient {
+    HttpClient::new(ID::new_v4(), server.api_url()).unwrap()
+}
+
+/// A progress callback and the progress it was called with.
+fn progress() -> (ProgressCallback, Arc<Mutex<Vec<Progress>>>) {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let callback = {
+        let seen = Arc::clone(&seen);
+        Arc::new(move |progress| seen.lock().unwrap().push(progress))
+    };
+    (callback, seen)
+}
+
+/// A path in the temporary directory that is removed when dropped.
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new() -> Self {
+        Self(std::env::temp_dir().join(format!("wicrs-api-test-{}", ID::new_v4())))
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[tokio::test]
+async fn uploads_bytes_as_multipart() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+    let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let attachment = Attachment {
+        id: ID::new_v4(),
+        hub_id,
+        channel_id,
+        name: "log.txt".to_string(),
+        size: data.len() as u64,
+        content_type: Some("text/plain".to_string()),
+    };
+    server.respond(
+        Method::POST,
+        &format!("/attachment/{}/{}", hub_id, channel_id),
+        MockResponse::success(attachment.clone()),
+    );
+    let (callback, seen) = progress();
+
+    let uploaded = client(&server)
+        .attachment_upload_bytes(
+            hub_id,
+            channel_id,
+            "log.txt".to_string(),
+            Some("text/plain".to_string()),
+            data.clone(),
+            Some(callback),
+        )
+        .await
+        .unwrap();
+    assert_eq!(uploaded, attachment);
+
+    let request = server
+        .requests()
+        .into_iter()
+        .find(|request| request.method == Method::POST)
+        .unwrap();
+    let content_type = request.headers["content-type"].to_str().unwrap();
+    assert!(content_type.starts_with("multipart/form-data; boundary="));
+    let body = String::from_utf8_lossy(&request.body).to_lowercase();
+    assert!(body.contains("name=\"file\"; filename=\"log.txt\""));
+    assert!(body.contains("content-type: text/plain"));
+    assert!(request
+        .body
+        .windows(data.len())
+        .any(|window| window == &data[..]));
+
+    let seen = seen.lock().unwrap().clone();
+    assert!(seen.len() > 1);
+    assert!(seen.windows(2).all(|w| w[0].transferred < w[1].transferred));
+    assert_eq!(
+        seen.last(),
+        Some(&Progress {
+            transferred: data.len() as u64,
+            total: Some(data.len() as u64),
+        })
+    );
+}
+
+#[tokio::test]
+async fn references_attachments_in_messages() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, message_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::POST,
+        &format!("/message/{}/{}", hub_id, channel_id),
+        MockResponse::success(message_id),
+    );
+    let attachment = Attachment {
+        id: ID::new_v4(),
+        hub_id,
+        channel_id,
+        name: "screen [1].png".to_string(),
+        size: 1,
+        content_type: None,
+    };
+
+    let sent = client(&server)
+        .message_send_with_attachments(
+            hub_id,
+            channel_id,
+            "look".to_string(),
+            std::slice::from_ref(&attachment),
+        )
+        .await
+        .unwrap();
+    assert_eq!(sent, message_id);
+
+    let request = server.requests().pop().unwrap();
+    let message = String::from_utf8(request.body).unwrap();
+    let found = attachments_in(&message);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].id, attachment.id);
+    assert_eq!(found[0].name, attachment.name);
+}
+
+/// Serves `data` at `path`, answering range requests like a real server.
+fn serve_ranges(server: &MockServer, path: &str, data: Vec<u8>) {
+    server.on(Method::GET, path, move |request| {
+        let start = request
+            .headers
+            .get(RANGE)
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| range.strip_prefix("bytes="))
+            .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok());
+        match start {
+            Some(start) if start >= data.len() => MockResponse::new(416, "")
+                .header("content-range", &format!("bytes */{}", data.len())),
+            Some(start) => MockResponse::new(206, data[start..].to_vec()).header(
+                "content-range",
+                &format!("bytes {}-{}/{}", start, data.len() - 1, data.len()),
+            ),
+            None => MockResponse::new(200, data.clone()),
+        }
+    });
+}
+
+#[tokio::test]
+async fn resumes_downloads_from_the_end_of_the_file() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, attachment_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let data = (0..100_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let path = format!("/attachment/{}/{}/{}", hub_id, channel_id, attachment_id);
+    serve_ranges(&server, &path, data.clone());
+    let file = TempPath::new();
+    std::fs::write(&file.0, &data[..30_000]).unwrap();
+    let client = client(&server);
+    let (callback, seen) = progress();
+
+    let size = client
+        .attachment_download_to_file(hub_id, channel_id, attachment_id, &file.0, Some(callback))
+        .await
+        .unwrap();
+    assert_eq!(size, data.len() as u64);
+    assert_eq!(std::fs::read(&file.0).unwrap(), data);
+    let ranges = server
+        .requests()
+        .iter()
+        .filter_map(|request| request.headers.get(RANGE).cloned())
+        .collect::<Vec<_>>();
+    assert_eq!(ranges, ["bytes=30000-"]);
+    let seen = seen.lock().unwrap().clone();
+    assert!(seen[0].transferred > 30_000);
+    assert!(seen
+        .iter()
+        .all(|progress| progress.total == Some(data.len() as u64)));
+
+    // A complete file is not downloaded again.
+    let size = client
+        .attachment_download_to_file(hub_id, channel_id, attachment_id, &file.0, None)
+        .await
+        .unwrap();
+    assert_eq!(size, data.len() as u64);
+    assert_eq!(std::fs::read(&file.0).unwrap(), data);
+}
+
+#[tokio::test]
+async fn restarts_downloads_when_the_range_is_ignored() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, attachment_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::GET,
+        &format!("/attachment/{}/{}/{}", hub_id, channel_id, attachment_id),
+        MockResponse::new(200, "whole file"),
+    );
+    let file = TempPath::new();
+    std::fs::write(&file.0, "stale partial file").unwrap();
+
+    let size = client(&server)
+        .attachment_download_to_file(hub_id, channel_id, attachment_id, &file.0, None)
+        .await
+        .unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(std::fs::read(&file.0).unwrap(), b"whole file");
+}
+
+#[tokio::test]
+async fn downloads_again_when_the_file_is_larger_than_the_attachment() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, attachment_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let path = format!("/attachment/{}/{}/{}", hub_id, channel_id, attachment_id);
+    serve_ranges(&server, &path, b"attachment".to_vec());
+    let file = TempPath::new();
+    std::fs::write(&file.0, "a different and longer file").unwrap();
+
+    let size = client(&server)
+        .attachment_download_to_file(hub_id, channel_id, attachment_id, &file.0, None)
+        .await
+        .unwrap();
+    assert_eq!(size, 10);
+    assert_eq!(std::fs::read(&file.0).unwrap(), b"attachment");
+}
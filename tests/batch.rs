@@ -0,0 +1,150 @@
+//! Batched requests, against the mock server for the helpers.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use chrono::Utc;
+use common::{MockResponse, MockServer};
+use hyper::Method;
+use wicrs_api::{
+    http::HttpClient,
+    protocol::{ApiError, ChannelPermission, HubMember, ID},
+    Error,
+};
+
+fn client(server: &MockServer) -> HttpClient {
+    HttpClient::new(ID::new_v4(), server.api_url()).unwrap()
+}
+
+fn member(hub: ID, user_id: ID) -> HubMember {
+    HubMember {
+        user_id,
+        joined: Utc::now(),
+        hub,
+        nickname: user_id.to_string(),
+        groups: Vec::new(),
+        hub_permissions: HashMap::new(),
+        channel_permissions: HashMap::new(),
+    }
+}
+
+#[tokio::test]
+async fn results_keep_the_order_requests_were_added_in() {
+    let server = MockServer::start().await;
+    let client = client(&server);
+    let mut batch = client.batch().concurrency(4);
+    for index in 0..4u64 {
+        batch.push(move |_| async move {
+            // Later requests finish first.
+            tokio::time::sleep(Duration::from_millis(20 * (4 - index))).await;
+            Ok(index)
+        });
+    }
+    assert_eq!(batch.len(), 4);
+    let results = batch.run().await;
+    let results = results
+        .into_iter()
+        .map(|result| result.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, vec![0, 1, 2, 3]);
+}
+
+#[tokio::test]
+async fn limits_the_requests_in_flight() {
+    let server = MockServer::start().await;
+    let client = client(&server);
+    let in_flight = Arc::new(AtomicUsize::new(0));
+    let most = Arc::new(AtomicUsize::new(0));
+    let mut batch = client.batch().concurrency(3);
+    for _ in 0..10 {
+        let in_flight = Arc::clone(&in_flight);
+        let most = Arc::clone(&most);
+        batch.push(move |_| async move {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        });
+    }
+    assert!(batch.run().await.iter().all(Result::is_ok));
+    assert_eq!(most.load(Ordering::SeqCst), 3);
+
+    // A concurrency of 0 still runs the requests, one at a time.
+    let most = Arc::new(AtomicUsize::new(0));
+    let mut batch = client.batch().concurrency(0);
+    for _ in 0..3 {
+        let in_flight = Arc::clone(&in_flight);
+        let most = Arc::clone(&most);
+        batch.push(move |_| async move {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            most.fetch_max(now, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        });
+    }
+    assert_eq!(batch.run().await.len(), 3);
+    assert_eq!(most.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn members_get_returns_failures_in_place() {
+    let server = MockServer::start().await;
+    let hub = ID::new_v4();
+    let members = [ID::new_v4(), ID::new_v4(), ID::new_v4()];
+    for (index, user_id) in members.iter().enumerate() {
+        let path = format!("/member/{}/{}", hub, user_id);
+        let response = if index == 1 {
+            MockResponse::error(ApiError::NotFound)
+        } else {
+            MockResponse::success(member(hub, *user_id))
+        };
+        server.respond(Method::GET, &path, response);
+    }
+
+    let results = client(&server).members_get(hub, &members, 2).await;
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().user_id, members[0]);
+    assert!(matches!(
+        results[1],
+        Err(Error::WICRSError(ApiError::NotFound))
+    ));
+    assert_eq!(results[2].as_ref().unwrap().user_id, members[2]);
+}
+
+#[tokio::test]
+async fn gets_a_permission_in_each_channel() {
+    let server = MockServer::start().await;
+    let (hub, member) = (ID::new_v4(), ID::new_v4());
+    let channels = [ID::new_v4(), ID::new_v4(), ID::new_v4()];
+    let settings = [Some(true), Some(false), None];
+    for (channel, setting) in channels.iter().zip(settings.iter()) {
+        let path = format!(
+            "/member/{}/{}/channel_permission/{}/{}",
+            hub,
+            member,
+            channel,
+            ChannelPermission::Write
+        );
+        server.respond(Method::GET, &path, MockResponse::success(*setting));
+    }
+
+    let results = client(&server)
+        .member_get_channels_permission(hub, member, &channels, ChannelPermission::Write, 8)
+        .await
+        .into_iter()
+        .map(|result| result.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(results, settings.to_vec());
+}
@@ -0,0 +1,112 @@
+//! Recording sessions against the mock server and replaying them without it.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use common::{MockResponse, MockServer};
+use futures_util::StreamExt;
+use hyper::Method;
+use wicrs_api::{
+    cassette::{Cassette, Interaction, Recorder, Replayer},
+    http::HttpClient,
+    protocol::ID,
+    version::VERSION_HEADER,
+};
+
+fn binary_file() -> Vec<u8> {
+    (0..=255u8).cycle().take(100_000).collect()
+}
+
+/// Records the download of a binary attachment, with the IDs it was downloaded with.
+async fn record(server: &MockServer, recorder: &Recorder) -> (ID, ID, ID) {
+    let (hub_id, channel_id, attachment_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let data = binary_file();
+    server.respond(
+        Method::GET,
+        &format!("/attachment/{}/{}/{}", hub_id, channel_id, attachment_id),
+        MockResponse::new(206, data[10..].to_vec())
+            .header("content-type", "application/octet-stream")
+            .header(
+                "content-range",
+                &format!("bytes 10-{}/{}", data.len() - 1, data.len()),
+            )
+            .header("x-trace", "abc"),
+    );
+    let client = HttpClient::new(ID::new_v4(), server.api_url())
+        .unwrap()
+        .with_middleware(recorder.clone());
+
+    let download = client
+        .attachment_download(hub_id, channel_id, attachment_id, 10)
+        .await
+        .unwrap();
+    let recorded = recorder.cassette().interactions.len();
+    let body = download
+        .into_stream()
+        .map(|chunk| chunk.unwrap().to_vec())
+        .concat()
+        .await;
+    assert_eq!(body, data[10..]);
+    // The body reached the client before it was recorded, it was not buffered.
+    assert_eq!(recorder.cassette().interactions.len(), recorded + 1);
+    (hub_id, channel_id, attachment_id)
+}
+
+#[tokio::test]
+async fn records_binary_bodies_and_every_header() {
+    let server = MockServer::start().await;
+    let recorder = Recorder::new();
+    record(&server, &recorder).await;
+
+    let path = std::env::temp_dir().join(format!("wicrs-api-cassette-{}.json", ID::new_v4()));
+    recorder.save(&path).unwrap();
+    let cassette = Cassette::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(cassette, recorder.cassette());
+
+    match cassette.interactions.last() {
+        Some(Interaction::Http {
+            status,
+            headers,
+            response_body,
+            ..
+        }) => {
+            assert_eq!(*status, 206);
+            assert_eq!(response_body[..], binary_file()[10..]);
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(header, _)| header == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            assert_eq!(header("x-trace"), Some("abc"));
+            assert_eq!(header("content-range"), Some("bytes 10-99999/100000"));
+            assert!(header(VERSION_HEADER).is_some());
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn replays_recorded_downloads() {
+    let server = MockServer::start().await;
+    let recorder = Recorder::new();
+    let (hub_id, channel_id, attachment_id) = record(&server, &recorder).await;
+    let replayer = Replayer::new(&recorder.cassette());
+    let client = HttpClient::new(ID::new_v4(), "http://127.0.0.1:9/api".to_string())
+        .unwrap()
+        .with_middleware(replayer);
+
+    let mut download = client
+        .attachment_download(hub_id, channel_id, attachment_id, 10)
+        .await
+        .unwrap();
+    assert_eq!(download.offset, 10);
+    assert_eq!(download.total, Some(100_000));
+    let mut body = Vec::new();
+    while let Some(chunk) = download.chunk().await.unwrap() {
+        body.extend_from_slice(&chunk);
+    }
+    assert_eq!(body, binary_file()[10..]);
+}
@@ -0,0 +1,375 @@
+//! A mock WICRS server for the integration tests.
+//!
+//! HTTP requests are answered by the handlers registered for their method and path (without the
+//! `/api` prefix), everything else gets a 404. The websocket endpoint acknowledges every command
+//! with `Success` and forwards the events pushed with [`MockServer::push_event`], connections can
+//! be dropped with [`MockServer::disconnect`].
+
+#![allow(dead_code)]
+
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use hyper::{
+    header::{HeaderName, HeaderValue},
+    service::{make_service_fn, service_fn},
+    upgrade::Upgraded,
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode,
+};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::broadcast;
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message},
+    WebSocketStream,
+};
+use wicrs_api::{
+    protocol::{ApiError, Response as ApiResponse, WsServerMessage},
+    version::{ProtocolVersion, VERSION_HEADER},
+};
+
+/// How long the helpers wait for something to happen before failing the test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone)]
+pub struct Recorded {
+    pub method: Method,
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// How long to wait before answering.
+    pub delay: Duration,
+}
+
+impl MockResponse {
+    pub fn new<B: Into<Vec<u8>>>(status: u16, body: B) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: body.into(),
+            delay: Duration::from_secs(0),
+        }
+    }
+
+    pub fn success<T: Serialize>(value: T) -> Self {
+        Self::new(
+            200,
+            serde_json::to_vec(&ApiResponse::Success(value)).unwrap(),
+        )
+        .header("content-type", "application/json")
+    }
+
+    pub fn error(error: ApiError) -> Self {
+        Self::new(
+            200,
+            serde_json::to_vec(&ApiResponse::<()>::Error(error)).unwrap(),
+        )
+        .header("content-type", "application/json")
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+}
+
+type Handler = Arc<dyn Fn(&Recorded) -> MockResponse + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    routes: Vec<(Method, String, Handler)>,
+    requests: Vec<Recorded>,
+    commands: Vec<Value>,
+    /// Commands are recorded but not acknowledged while set.
+    hold_acks: bool,
+    /// Events sent right before the next acknowledgements.
+    before_ack: VecDeque<String>,
+    /// HTTP responses have no version header while set, like servers that predate versioning.
+    legacy: bool,
+    /// Websocket handshakes are refused while set, like a server that is restarting.
+    reject_websocket: bool,
+    websocket_attempts: usize,
+}
+
+#[derive(Debug, Clone)]
+enum Outgoing {
+    Event(String),
+    Disconnect,
+}
+
+#[derive(Clone)]
+pub struct MockServer {
+    pub addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    events: broadcast::Sender<Outgoing>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let (events, _) = broadcast::channel(64);
+        let state = Arc::new(Mutex::new(State::default()));
+        let (server_state, server_events) = (Arc::clone(&state), events.clone());
+        let make_service = make_service_fn(move |_| {
+            let (state, events) = (Arc::clone(&server_state), server_events.clone());
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    handle(Arc::clone(&state), events.clone(), request)
+                }))
+            }
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        Self {
+            addr,
+            state,
+            events,
+        }
+    }
+
+    pub fn api_url(&self) -> String {
+        format!("http://{}/api", self.addr)
+    }
+
+    pub fn ws_url(&self) -> String {
+        format!("ws://{}/api", self.addr)
+    }
+
+    /// Answers requests to `path` with `handler`, later handlers for the same route win.
+    pub fn on<F>(&self, method: Method, path: &str, handler: F)
+    where
+        F: Fn(&Recorded) -> MockResponse + Send + Sync + 'static,
+    {
+        self.state
+            .lock()
+            .unwrap()
+            .routes
+            .insert(0, (method, path.to_string(), Arc::new(handler)));
+    }
+
+    pub fn respond(&self, method: Method, path: &str, response: MockResponse) {
+        self.on(method, path, move |_| response.clone());
+    }
+
+    pub fn requests(&self) -> Vec<Recorded> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Commands received over websocket, the user ID sent when connecting is not included.
+    pub fn commands(&self) -> Vec<Value> {
+        self.state.lock().unwrap().commands.clone()
+    }
+
+    /// Waits until at least `count` websocket commands have been received.
+    pub async fn wait_for_commands(&self, count: usize) -> Vec<Value> {
+        eventually(|| Some(self.commands()).filter(|commands| commands.len() >= count)).await
+    }
+
+    /// Sends an event to every connected websocket client.
+    pub fn push_event(&self, event: &WsServerMessage) {
+        let _ = self
+            .events
+            .send(Outgoing::Event(serde_json::to_string(event).unwrap()));
+    }
+
+    /// Drops every websocket connection without a close frame.
+    pub fn disconnect(&self) {
+        let _ = self.events.send(Outgoing::Disconnect);
+    }
+
+    pub fn hold_acks(&self, hold: bool) {
+        self.state.lock().unwrap().hold_acks = hold;
+    }
+
+    pub fn legacy(&self, legacy: bool) {
+        self.state.lock().unwrap().legacy = legacy;
+    }
+
+    pub fn reject_websocket(&self, reject: bool) {
+        self.state.lock().unwrap().reject_websocket = reject;
+    }
+
+    /// Number of websocket handshakes received, including refused ones.
+    pub fn websocket_attempts(&self) -> usize {
+        self.state.lock().unwrap().websocket_attempts
+    }
+
+    /// Sends `event` right before the next command is acknowledged.
+    pub fn event_before_ack(&self, event: &WsServerMessage) {
+        self.state
+            .lock()
+            .unwrap()
+            .before_ack
+            .push_back(serde_json::to_string(event).unwrap());
+    }
+}
+
+/// Polls `check` until it returns `Some`, panicking after [`TIMEOUT`].
+pub async fn eventually<T, F: FnMut() -> Option<T>>(mut check: F) -> T {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            if let Some(value) = check() {
+                return value;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .expect("timed out")
+}
+
+async fn handle(
+    state: Arc<Mutex<State>>,
+    events: broadcast::Sender<Outgoing>,
+    request: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() == "/api/websocket" {
+        let reject = {
+            let mut state = state.lock().unwrap();
+            state.websocket_attempts += 1;
+            state.reject_websocket
+        };
+        if reject {
+            let mut response = Response::new(Body::from("restarting"));
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            return Ok(response);
+        }
+        return Ok(upgrade(state, events, request));
+    }
+    let (parts, body) = request.into_parts();
+    let path = parts.uri.path();
+    let recorded = Recorded {
+        method: parts.method.clone(),
+        path: match parts.uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path.to_string(),
+        },
+        headers: parts.headers.clone(),
+        body: hyper::body::to_bytes(body).await.unwrap().to_vec(),
+    };
+    let (handler, legacy) = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(recorded.clone());
+        let handler = state
+            .routes
+            .iter()
+            .find(|(method, route, _)| {
+                *method == recorded.method && Some(route.as_str()) == path.strip_prefix("/api")
+            })
+            .map(|(_, _, handler)| Arc::clone(handler));
+        (handler, state.legacy)
+    };
+    let mock = match handler {
+        Some(handler) => handler(&recorded),
+        None => MockResponse::new(404, "not found"),
+    };
+    tokio::time::sleep(mock.delay).await;
+    let mut response = Response::new(Body::from(mock.body));
+    *response.status_mut() = StatusCode::from_u16(mock.status).unwrap();
+    let headers = response.headers_mut();
+    if !legacy {
+        headers.insert(
+            VERSION_HEADER,
+            HeaderValue::from_str(&ProtocolVersion::CURRENT.to_string()).unwrap(),
+        );
+    }
+    for (name, value) in mock.headers {
+        headers.insert(
+            HeaderName::from_bytes(name.as_bytes()).unwrap(),
+            HeaderValue::from_str(&value).unwrap(),
+        );
+    }
+    Ok(response)
+}
+
+fn upgrade(
+    state: Arc<Mutex<State>>,
+    events: broadcast::Sender<Outgoing>,
+    request: Request<Body>,
+) -> Response<Body> {
+    let key = request
+        .headers()
+        .get("sec-websocket-key")
+        .map(|key| derive_accept_key(key.as_bytes()))
+        .unwrap_or_default();
+    let events = events.subscribe();
+    tokio::spawn(async move {
+        if let Ok(upgraded) = hyper::upgrade::on(request).await {
+            let websocket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+            serve_websocket(state, events, websocket).await;
+        }
+    });
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("upgrade", "websocket")
+        .header("connection", "upgrade")
+        .header("sec-websocket-accept", key)
+        .header(VERSION_HEADER, ProtocolVersion::CURRENT.to_string())
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn serve_websocket(
+    state: Arc<Mutex<State>>,
+    mut events: broadcast::Receiver<Outgoing>,
+    websocket: WebSocketStream<Upgraded>,
+) {
+    let (mut sink, mut stream) = websocket.split();
+    let mut authenticated = false;
+    loop {
+        tokio::select! {
+            message = stream.next() => match message {
+                Some(Ok(Message::Text(text))) if !authenticated => authenticated = !text.is_empty(),
+                Some(Ok(Message::Text(text))) => {
+                    let command = serde_json::from_str(&text).unwrap_or(Value::String(text));
+                    let replies = {
+                        let mut state = state.lock().unwrap();
+                        state.commands.push(command);
+                        if state.hold_acks {
+                            Vec::new()
+                        } else {
+                            let mut replies = state.before_ack.drain(..).collect::<Vec<_>>();
+                            replies.push(serde_json::to_string(&WsServerMessage::Success).unwrap());
+                            replies
+                        }
+                    };
+                    for reply in replies {
+                        if sink.send(Message::Text(reply)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            event = events.recv() => match event {
+                Ok(Outgoing::Event(event)) => {
+                    if sink.send(Message::Text(event)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Outgoing::Disconnect) => break,
+                Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Runs the IRC gateway against the mock server with a simulated IRC client.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use common::{MockResponse, MockServer, TIMEOUT};
+use hyper::Method;
+use tokio::io::{
+    duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, Lines, ReadHalf, WriteHalf,
+};
+use wicrs_api::{
+    irc::{run, serve, GatewayConfig, IrcMessage},
+    protocol::{ApiError, HttpMemberStatus, Hub, HubMember, WsServerMessage, ID},
+};
+
+/// The client side of an IRC connection to the gateway.
+struct IrcClient {
+    lines: Lines<BufReader<ReadHalf<DuplexStream>>>,
+    write: WriteHalf<DuplexStream>,
+}
+
+impl IrcClient {
+    fn connect(server: &MockServer) -> Self {
+        let (client, gateway) = duplex(64 * 1024);
+        let config = GatewayConfig::new(
+            "127.0.0.1:0".parse().unwrap(),
+            server.api_url(),
+            server.ws_url(),
+        );
+        tokio::spawn(serve(gateway, Arc::new(config)));
+        let (read, write) = tokio::io::split(client);
+        Self {
+            lines: BufReader::new(read).lines(),
+            write,
+        }
+    }
+
+    async fn send(&mut self, line: &str) {
+        self.write.write_all(line.as_bytes()).await.unwrap();
+        self.write.write_all(b"\r\n").await.unwrap();
+    }
+
+    async fn next(&mut self) -> String {
+        tokio::time::timeout(TIMEOUT, self.lines.next_line())
+            .await
+            .expect("timed out waiting for the gateway")
+            .unwrap()
+            .expect("gateway closed the connection")
+    }
+
+    /// Reads lines until one with the given command, returning it.
+    async fn expect(&mut self, command: &str) -> IrcMessage {
+        loop {
+            let message = IrcMessage::parse(&self.next().await).unwrap();
+            if message.command == command {
+                return message;
+            }
+        }
+    }
+
+    async fn register(&mut self, user_id: ID) {
+        self.send(&format!("PASS {}", user_id)).await;
+        self.send("NICK tester").await;
+        self.send("USER tester 0 * :tester").await;
+        self.expect("001").await;
+        self.expect("422").await;
+    }
+}
+
+fn hub(id: ID, description: &str, members: &[ID]) -> Hub {
+    let member = |user_id: &ID| HubMember {
+        user_id: *user_id,
+        joined: Utc::now(),
+        hub: id,
+        nickname: String::new(),
+        groups: Vec::new(),
+        hub_permissions: HashMap::new(),
+        channel_permissions: HashMap::new(),
+    };
+    Hub {
+        channels: HashMap::new(),
+        members: members.iter().map(|id| (*id, member(id))).collect(),
+        bans: Default::default(),
+        mutes: Default::default(),
+        description: description.to_string(),
+        owner: members[0],
+        groups: HashMap::new(),
+        default_group: ID::nil(),
+        name: "hub".to_string(),
+        id,
+        created: Utc::now(),
+    }
+}
+
+#[tokio::test]
+async fn joins_hubs_without_passing_on_injected_lines() {
+    let server = MockServer::start().await;
+    let (user_id, hub_id) = (ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::POST,
+        &format!("/hub/{}/join", hub_id),
+        MockResponse::success(""),
+    );
+    server.respond(
+        Method::GET,
+        &format!("/hub/{}", hub_id),
+        MockResponse::success(hub(hub_id, "topic\r\nQUIT :bye", &[user_id])),
+    );
+    let mut client = IrcClient::connect(&server);
+    client.register(user_id).await;
+
+    client.send(&format!("JOIN #{}", hub_id)).await;
+    let join = client.expect("JOIN").await;
+    assert_eq!(join.params, vec![format!("#{}", hub_id)]);
+    let topic = client.next().await;
+    assert!(topic.ends_with(":topic  QUIT :bye"), "{}", topic);
+    let names = client.expect("353").await;
+    assert_eq!(names.params[3], user_id.to_string());
+    client.expect("366").await;
+    assert_eq!(
+        server.wait_for_commands(1).await[0],
+        serde_json::json!({ "SubscribeHub": { "hub_id": hub_id } })
+    );
+}
+
+#[tokio::test]
+async fn failed_joins_are_reported() {
+    let server = MockServer::start().await;
+    let (user_id, hub_id) = (ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::POST,
+        &format!("/hub/{}/join", hub_id),
+        MockResponse::error(ApiError::NotFound),
+    );
+    server.respond(
+        Method::GET,
+        &format!("/member/{}/{}/status", hub_id, user_id),
+        MockResponse::success(HttpMemberStatus {
+            muted: false,
+            banned: false,
+            member: false,
+        }),
+    );
+    let mut client = IrcClient::connect(&server);
+    client.register(user_id).await;
+
+    client.send(&format!("JOIN #{}", hub_id)).await;
+    let message = IrcMessage::parse(&client.next().await).unwrap();
+    assert_eq!(message.command, "403");
+    assert_eq!(message.params[1], format!("#{}", hub_id));
+    assert!(server.commands().is_empty());
+}
+
+#[tokio::test]
+async fn relays_chat_messages_both_ways() {
+    let server = MockServer::start().await;
+    let (user_id, sender_id, hub_id, channel_id) =
+        (ID::new_v4(), ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let mut client = IrcClient::connect(&server);
+    client.register(user_id).await;
+    let target = format!("#{}/{}", hub_id, channel_id);
+
+    client.send(&format!("PRIVMSG {} :hello", target)).await;
+    assert_eq!(
+        server.wait_for_commands(1).await[0],
+        serde_json::json!({ "SendMessage": {
+            "hub_id": hub_id,
+            "channel_id": channel_id,
+            "message": "hello",
+        } })
+    );
+
+    server.push_event(&WsServerMessage::ChatMessage {
+        sender_id,
+        hub_id,
+        channel_id,
+        message_id: ID::new_v4(),
+        message: "first\nsecond\rPART #x".to_string(),
+    });
+    let first = client.expect("PRIVMSG").await;
+    assert_eq!(first.params, vec![target.clone(), "first".to_string()]);
+    let second = client.expect("PRIVMSG").await;
+    assert_eq!(second.params, vec![target, "second PART #x".to_string()]);
+}
+
+#[tokio::test]
+async fn rejects_passwords_that_are_not_user_ids() {
+    let server = MockServer::start().await;
+    let mut client = IrcClient::connect(&server);
+    client.send("PASS hunter2").await;
+    client.send("NICK tester").await;
+    client.send("USER tester 0 * :tester").await;
+    assert_eq!(client.expect("464").await.params[0], "tester");
+}
+
+#[tokio::test]
+async fn run_keeps_accepting_after_a_client_fails() {
+    let listen = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let config = GatewayConfig::new(
+        listen,
+        "http://127.0.0.1:9/api".to_string(),
+        "ws://127.0.0.1:9/api".to_string(),
+    );
+    let mut gateway = tokio::spawn(run(config));
+    let mut failing = loop {
+        match tokio::net::TcpStream::connect(listen).await {
+            Ok(stream) => break stream,
+            Err(_) => tokio::task::yield_now().await,
+        }
+    };
+    // The websocket connection to the unreachable server fails and ends this client.
+    failing
+        .write_all(format!("NICK {}\r\nUSER a 0 * :a\r\n", ID::new_v4()).as_bytes())
+        .await
+        .unwrap();
+    let mut rest = Vec::new();
+    tokio::time::timeout(
+        TIMEOUT,
+        tokio::io::AsyncReadExt::read_to_end(&mut failing, &mut rest),
+    )
+    .await
+    .expect("the failing client was not disconnected")
+    .ok();
+
+    let (read, mut write) = tokio::net::TcpStream::connect(listen)
+        .await
+        .unwrap()
+        .into_split();
+    write
+        .write_all(b"PASS hunter2\r\nNICK tester\r\nUSER tester 0 * :tester\r\n")
+        .await
+        .unwrap();
+    let mut lines = BufReader::new(read).lines();
+    let line = tokio::time::timeout(TIMEOUT, lines.next_line())
+        .await
+        .expect("timed out waiting for the gateway")
+        .unwrap()
+        .expect("gateway closed the connection");
+    assert_eq!(IrcMessage::parse(&line).unwrap().command, "464");
+    let stopped = tokio::time::timeout(Duration::from_millis(50), &mut gateway).await;
+    assert!(stopped.is_err(), "gateway stopped: {:?}", stopped);
+    gateway.abort();
+}
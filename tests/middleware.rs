@@ -0,0 +1,189 @@
+//! Request middleware against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use common::{MockResponse, MockServer};
+use hyper::Method;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, AUTHORIZATION},
+    Request,
+};
+use wicrs_api::{
+    http::HttpClient,
+    middleware::{BoxFuture, Middleware, Next, SetHeaders},
+    protocol::{Response, ID},
+    version::{ProtocolVersion, VERSION_HEADER},
+};
+
+fn client(server: &MockServer) -> HttpClient {
+    HttpClient::new(ID::new_v4(), server.api_url()).unwrap()
+}
+
+/// Records when the request enters and the response leaves the middleware.
+struct Log {
+    name: &'static str,
+    log: Arc<Mutex<Vec<String>>>,
+}
+
+impl Middleware for Log {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, wicrs_api::Result<reqwest::Response>> {
+        Box::pin(async move {
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} request", self.name));
+            let response = next.run(request).await;
+            self.log
+                .lock()
+                .unwrap()
+                .push(format!("{} response", self.name));
+            response
+        })
+    }
+}
+
+/// Answers every request itself.
+struct Answer;
+
+impl Middleware for Answer {
+    fn handle<'a>(
+        &'a self,
+        _: Request,
+        _: Next<'a>,
+    ) -> BoxFuture<'a, wicrs_api::Result<reqwest::Response>> {
+        Box::pin(async move {
+            let body = serde_json::to_string(&Response::Success("answered")).unwrap();
+            Ok(http::Response::builder()
+                .status(200)
+                .header(VERSION_HEADER, ProtocolVersion::CURRENT.to_string())
+                .body(body)
+                .unwrap()
+                .into())
+        })
+    }
+}
+
+/// Sends the request again once if the server fails.
+struct RetryOnce;
+
+impl Middleware for RetryOnce {
+    fn handle<'a>(
+        &'a self,
+        request: Request,
+        next: Next<'a>,
+    ) -> BoxFuture<'a, wicrs_api::Result<reqwest::Response>> {
+        Box::pin(async move {
+            let retry = request.try_clone().unwrap();
+            let response = next.run(request).await?;
+            if response.status().is_server_error() {
+                next.run(retry).await
+            } else {
+                Ok(response)
+            }
+        })
+    }
+}
+
+#[tokio::test]
+async fn run_in_the_order_they_were_added() {
+    let server = MockServer::start().await;
+    server.respond(Method::GET, "/hub/list", MockResponse::success("ok"));
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let client = client(&server)
+        .with_middleware(Log {
+            name: "first",
+            log: Arc::clone(&log),
+        })
+        .with_middleware(Log {
+            name: "second",
+            log: Arc::clone(&log),
+        });
+
+    client
+        .request::<_, String>(Method::GET, "/hub/list")
+        .await
+        .unwrap();
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            "first request",
+            "second request",
+            "second response",
+            "first response"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn set_headers_replaces_existing_headers() {
+    let server = MockServer::start().await;
+    server.respond(Method::GET, "/hub/list", MockResponse::success("ok"));
+    let mut headers = HeaderMap::new();
+    headers.insert(AUTHORIZATION, HeaderValue::from_static("replaced"));
+    let client = client(&server).with_middleware(SetHeaders(headers));
+
+    client
+        .request::<_, String>(Method::GET, "/hub/list")
+        .await
+        .unwrap();
+    let requests = server.requests();
+    let authorization = requests[0]
+        .headers
+        .get_all(AUTHORIZATION)
+        .iter()
+        .collect::<Vec<_>>();
+    assert_eq!(authorization, vec![HeaderValue::from_static("replaced")]);
+}
+
+#[tokio::test]
+async fn can_answer_without_calling_the_server() {
+    let server = MockServer::start().await;
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let client = client(&server)
+        .with_middleware(Answer)
+        .with_middleware(Log {
+            name: "skipped",
+            log: Arc::clone(&log),
+        });
+
+    let answer = client
+        .request::<_, String>(Method::GET, "/hub/list")
+        .await
+        .unwrap();
+    assert_eq!(answer, "answered");
+    assert!(log.lock().unwrap().is_empty());
+    assert!(server.requests().is_empty());
+}
+
+#[tokio::test]
+async fn can_call_next_more_than_once() {
+    let server = MockServer::start().await;
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&calls);
+    server.on(Method::GET, "/hub/list", move |_| {
+        match counted.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::new(500, ""),
+            _ => MockResponse::success("ok"),
+        }
+    });
+    let client = client(&server).with_middleware(RetryOnce);
+
+    let result = client
+        .request::<_, String>(Method::GET, "/hub/list")
+        .await
+        .unwrap();
+    assert_eq!(result, "ok");
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    assert_eq!(server.requests().len(), 2);
+}
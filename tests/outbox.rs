@@ -0,0 +1,142 @@
+//! Sending through the outbox against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use common::{eventually, MockResponse, MockServer};
+use hyper::Method;
+use wicrs_api::{
+    http::HttpClient,
+    outbox::{DeliveryState, Outbox},
+    protocol::{ApiError, WsServerMessage, ID},
+};
+
+fn outbox(server: &MockServer) -> (Outbox, ID) {
+    let user_id = ID::new_v4();
+    let client = HttpClient::new(user_id, server.api_url()).unwrap();
+    (Outbox::new(Arc::new(client)), user_id)
+}
+
+fn echo(user_id: ID, hub_id: ID, channel_id: ID, message_id: ID, message: &str) -> WsServerMessage {
+    WsServerMessage::ChatMessage {
+        sender_id: user_id,
+        hub_id,
+        channel_id,
+        message_id,
+        message: message.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn reconciles_echoes_by_the_returned_id() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, message_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let path = format!("/message/{}/{}", hub_id, channel_id);
+    server.respond(Method::POST, &path, MockResponse::success(message_id));
+    let (outbox, user_id) = outbox(&server);
+
+    let sent = outbox.send(hub_id, channel_id, "hello".to_string()).await;
+    assert_eq!(sent.state, DeliveryState::Sent);
+    assert_eq!(sent.message_id, Some(message_id));
+    assert_eq!(outbox.messages().len(), 1);
+
+    // The server may change the content, the ID still matches.
+    let reconciled = outbox
+        .handle_event(&echo(user_id, hub_id, channel_id, message_id, "hello!"))
+        .unwrap();
+    assert_eq!(reconciled.local_id, sent.local_id);
+    assert!(outbox.messages().is_empty());
+}
+
+#[tokio::test]
+async fn reconciles_echoes_that_arrive_before_the_response() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, message_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let path = format!("/message/{}/{}", hub_id, channel_id);
+    server.respond(
+        Method::POST,
+        &path,
+        MockResponse::success(message_id).delay(Duration::from_millis(200)),
+    );
+    let (outbox, user_id) = outbox(&server);
+    let outbox = Arc::new(outbox);
+
+    let send = tokio::spawn({
+        let outbox = Arc::clone(&outbox);
+        async move { outbox.send(hub_id, channel_id, "hello".to_string()).await }
+    });
+    eventually(|| server.requests().pop()).await;
+    let reconciled = outbox
+        .handle_event(&echo(user_id, hub_id, channel_id, message_id, "hello"))
+        .unwrap();
+    assert_eq!(reconciled.message_id, Some(message_id));
+    assert_eq!(reconciled.state, DeliveryState::Sent);
+
+    let sent = send.await.unwrap();
+    assert_eq!(sent.local_id, reconciled.local_id);
+    assert_eq!(sent.message_id, Some(message_id));
+    assert!(outbox.messages().is_empty());
+}
+
+#[tokio::test]
+async fn retries_failed_sends() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id, message_id) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let path = format!("/message/{}/{}", hub_id, channel_id);
+    let calls = Arc::new(AtomicUsize::new(0));
+    server.on(Method::POST, &path, {
+        let calls = Arc::clone(&calls);
+        move |_| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => MockResponse::error(ApiError::Other("try again".to_string())),
+            _ => MockResponse::success(message_id),
+        }
+    });
+    let (outbox, _) = outbox(&server);
+
+    let failed = outbox.send(hub_id, channel_id, "hello".to_string()).await;
+    assert!(matches!(failed.state, DeliveryState::Failed(_)));
+    assert_eq!(outbox.failed().len(), 1);
+
+    let results = outbox.retry_failed().await;
+    assert_eq!(results.len(), 1);
+    assert_eq!(*results[0].as_ref().unwrap(), Some(message_id));
+    let sent = outbox.message(failed.local_id).unwrap();
+    assert_eq!(sent.state, DeliveryState::Sent);
+    assert!(outbox.failed().is_empty());
+    // Sent messages are not sent again.
+    assert_eq!(outbox.retry(failed.local_id).await.unwrap(), None);
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn matches_content_only_for_pending_messages() {
+    let server = MockServer::start().await;
+    let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+    let path = format!("/message/{}/{}", hub_id, channel_id);
+    server.respond(
+        Method::POST,
+        &path,
+        MockResponse::error(ApiError::Other("failed".to_string())),
+    );
+    let (outbox, user_id) = outbox(&server);
+
+    let failed = outbox.send(hub_id, channel_id, "hello".to_string()).await;
+    assert!(matches!(failed.state, DeliveryState::Failed(_)));
+    let echoed = echo(user_id, hub_id, channel_id, ID::new_v4(), "hello");
+    assert!(outbox.handle_event(&echoed).is_none());
+    assert_eq!(outbox.failed().len(), 1);
+
+    let pending = outbox.queue(hub_id, channel_id, "hello".to_string());
+    let reconciled = outbox.handle_event(&echoed).unwrap();
+    assert_eq!(reconciled.local_id, pending.local_id);
+    assert_eq!(outbox.failed().len(), 1);
+}
@@ -0,0 +1,100 @@
+//! Round trips of the protocol types through the JSON fixtures in `tests/fixtures/protocol`.
+//!
+//! With the `wicrs-server` feature the same fixtures are also checked against the server's types,
+//! so a change to the JSON representation on either side fails one of them.
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use wicrs_api::protocol::{
+    ApiError, HttpSetPermission, Hub, Message, PermissionSetting, Response, WsClientMessage,
+    WsServerMessage,
+};
+
+fn fixture(name: &str) -> Vec<Value> {
+    let path = format!(
+        "{}/tests/fixtures/protocol/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let json = std::fs::read_to_string(&path).unwrap();
+    serde_json::from_str(&json).unwrap()
+}
+
+/// Parses every value of a fixture and checks that it is written back the same way.
+fn round_trip<T: Serialize + DeserializeOwned>(name: &str) -> Vec<T> {
+    fixture(name)
+        .into_iter()
+        .map(|value| {
+            let parsed: T = serde_json::from_value(value.clone())
+                .unwrap_or_else(|error| panic!("{}: {} in {}", name, error, value));
+            assert_eq!(serde_json::to_value(&parsed).unwrap(), value, "{}", name);
+            parsed
+        })
+        .collect()
+}
+
+#[test]
+fn api_errors() {
+    // Fails to compile when a variant is added, so that it gets a fixture.
+    fn variant(error: &ApiError) -> &'static str {
+        match error {
+            ApiError::NotFound => "NotFound",
+            ApiError::Muted => "Muted",
+            ApiError::Other(_) => "Other",
+        }
+    }
+    let errors = round_trip::<ApiError>("api_error");
+    assert_eq!(
+        errors.iter().map(variant).collect::<Vec<_>>(),
+        ["NotFound", "Muted", "Other"]
+    );
+    assert_eq!(errors[2], ApiError::Other("hub is full".to_string()));
+}
+
+#[test]
+fn permission_settings() {
+    assert_eq!(
+        round_trip::<PermissionSetting>("permission_setting"),
+        [Some(true), Some(false), None]
+    );
+    let settings = round_trip::<HttpSetPermission>("set_permission");
+    assert_eq!(
+        settings
+            .iter()
+            .map(|permission| permission.setting)
+            .collect::<Vec<_>>(),
+        [Some(true), Some(false), None]
+    );
+}
+
+#[test]
+fn response_envelopes() {
+    let responses = round_trip::<Response<Value>>("response");
+    assert!(matches!(responses[0], Response::Success(Value::Null)));
+    assert!(matches!(
+        responses.last(),
+        Some(Response::Error(ApiError::Other(_)))
+    ));
+    let messages = round_trip::<Response<Message>>("response_message");
+    match &messages[0] {
+        Response::Success(message) => {
+            assert_eq!(message.created.timestamp_millis() % 1000, 250)
+        }
+        Response::Error(error) => panic!("{}", error),
+    }
+    assert!(matches!(messages[1], Response::Error(ApiError::NotFound)));
+}
+
+#[test]
+fn hubs() {
+    let hub = round_trip::<Hub>("hub").remove(0);
+    let member = &hub.members[&hub.owner];
+    assert_eq!(member.nickname, "alice");
+    assert_eq!(member.channel_permissions.len(), 1);
+}
+
+#[test]
+fn websocket_messages() {
+    round_trip::<WsClientMessage>("ws_client_message");
+    round_trip::<WsServerMessage>("ws_server_message");
+}
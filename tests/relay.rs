@@ -0,0 +1,213 @@
+//! Relays events from the mock server to webhook targets served by a local HTTP receiver.
+
+#![cfg(feature = "webhook-relay")]
+
+mod common;
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use common::{eventually, MockResponse, MockServer};
+use hyper::Method;
+use wicrs_api::{
+    protocol::{WsServerMessage, ID},
+    relay::{
+        verify_timestamped, EventFilter, EventKind, MemoryDeadLetterStore, RelayConfig,
+        WebhookPayload, WebhookRelay, WebhookTarget, EVENT_HEADER, SIGNATURE_HEADER,
+        TIMESTAMP_HEADER,
+    },
+    websocket::asyncws::WebsocketClient,
+};
+
+fn chat_message(hub_id: ID, channel_id: ID) -> WsServerMessage {
+    WsServerMessage::ChatMessage {
+        sender_id: ID::new_v4(),
+        hub_id,
+        channel_id,
+        message_id: ID::new_v4(),
+        message: "hello".to_string(),
+    }
+}
+
+/// A webhook receiver answering every delivery with `response`.
+async fn receiver(response: MockResponse) -> (MockServer, String) {
+    let receiver = MockServer::start().await;
+    receiver.respond(Method::POST, "/hook", response);
+    let url = format!("{}/hook", receiver.api_url());
+    (receiver, url)
+}
+
+/// Starts a relay and waits until it has sent its subscriptions.
+async fn start(
+    server: &MockServer,
+    config: RelayConfig,
+    dead_letters: Arc<MemoryDeadLetterStore>,
+) -> tokio::task::JoinHandle<()> {
+    let subscriptions = config.hubs.len() + config.channels.len();
+    let relay = Arc::new(WebhookRelay::new(config, dead_letters).unwrap());
+    let websocket = WebsocketClient::new(ID::new_v4(), &server.ws_url())
+        .await
+        .unwrap();
+    let running = tokio::spawn(async move {
+        let _ = relay.run(websocket).await;
+    });
+    server.wait_for_commands(subscriptions).await;
+    running
+}
+
+#[tokio::test]
+async fn delivers_signed_events() {
+    let server = MockServer::start().await;
+    let (receiver, url) = receiver(MockResponse::new(200, "")).await;
+    let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+    let config = RelayConfig {
+        hubs: vec![hub_id],
+        channels: vec![(hub_id, channel_id)],
+        targets: vec![WebhookTarget::new(url, "secret".to_string())],
+        ..RelayConfig::default()
+    };
+    let relay = start(&server, config, Default::default()).await;
+    assert_eq!(
+        server.commands(),
+        vec![
+            serde_json::json!({ "SubscribeHub": { "hub_id": hub_id } }),
+            serde_json::json!({ "SubscribeChannel": { "hub_id": hub_id, "channel_id": channel_id } }),
+        ]
+    );
+
+    server.push_event(&chat_message(hub_id, channel_id));
+    let delivery = eventually(|| receiver.requests().pop()).await;
+    let header = |name| delivery.headers[name].to_str().unwrap();
+    assert_eq!(header(EVENT_HEADER), "chat_message");
+    assert!(verify_timestamped(
+        b"secret",
+        header(TIMESTAMP_HEADER),
+        &delivery.body,
+        header(SIGNATURE_HEADER),
+        Duration::from_secs(60),
+    ));
+    let payload: WebhookPayload = serde_json::from_slice(&delivery.body).unwrap();
+    assert_eq!(payload.event, EventKind::ChatMessage);
+    assert_eq!(payload.data["ChatMessage"]["message"], "hello");
+    relay.abort();
+}
+
+#[tokio::test]
+async fn filters_events_per_target() {
+    let server = MockServer::start().await;
+    let (receiver, url) = receiver(MockResponse::new(200, "")).await;
+    let (hub_id, channel_id, other_channel) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let filter = EventFilter {
+        channels: Some(vec![(hub_id, channel_id)].into_iter().collect()),
+        ..EventFilter::default()
+    };
+    let config = RelayConfig {
+        hubs: vec![hub_id],
+        targets: vec![WebhookTarget::new(url, "secret".to_string()).with_filter(filter)],
+        ..RelayConfig::default()
+    };
+    let relay = start(&server, config, Default::default()).await;
+
+    server.push_event(&chat_message(hub_id, other_channel));
+    server.push_event(&chat_message(hub_id, channel_id));
+    let delivery = eventually(|| receiver.requests().pop()).await;
+    let payload: WebhookPayload = serde_json::from_slice(&delivery.body).unwrap();
+    assert_eq!(
+        payload.data["ChatMessage"]["channel_id"],
+        channel_id.to_string()
+    );
+    assert_eq!(receiver.requests().len(), 1);
+    relay.abort();
+}
+
+#[tokio::test]
+async fn slow_targets_do_not_delay_others() {
+    let server = MockServer::start().await;
+    let (_slow, slow_url) =
+        receiver(MockResponse::new(200, "").delay(Duration::from_secs(3))).await;
+    let (fast, fast_url) = receiver(MockResponse::new(200, "")).await;
+    let hub_id = ID::new_v4();
+    let config = RelayConfig {
+        hubs: vec![hub_id],
+        targets: vec![
+            WebhookTarget::new(slow_url, "secret".to_string()),
+            WebhookTarget::new(fast_url, "secret".to_string()),
+        ],
+        ..RelayConfig::default()
+    };
+    let relay = start(&server, config, Default::default()).await;
+
+    let start = Instant::now();
+    for _ in 0..3 {
+        server.push_event(&chat_message(hub_id, ID::new_v4()));
+    }
+    eventually(|| Some(()).filter(|_| fast.requests().len() == 3)).await;
+    assert!(start.elapsed() < Duration::from_secs(2));
+    relay.abort();
+}
+
+#[tokio::test]
+async fn dead_letters_failed_deliveries() {
+    let server = MockServer::start().await;
+    let receiver = MockServer::start().await;
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&calls);
+    receiver.on(Method::POST, "/hook", move |_| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        MockResponse::new(500, "")
+    });
+    let url = format!("{}/hook", receiver.api_url());
+    let hub_id = ID::new_v4();
+    let config = RelayConfig {
+        hubs: vec![hub_id],
+        targets: vec![WebhookTarget::new(url.clone(), "secret".to_string())],
+        max_attempts: 3,
+        retry_delay: Duration::from_millis(10),
+        ..RelayConfig::default()
+    };
+    let dead_letters = Arc::new(MemoryDeadLetterStore::default());
+    let relay = start(&server, config, Arc::clone(&dead_letters)).await;
+
+    server.push_event(&chat_message(hub_id, ID::new_v4()));
+    let letter = eventually(|| dead_letters.letters().pop()).await;
+    assert_eq!(letter.url, url);
+    assert_eq!(letter.attempts, 3);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+    relay.abort();
+}
+
+#[tokio::test]
+async fn does_not_retry_rejected_deliveries() {
+    let server = MockServer::start().await;
+    let receiver = MockServer::start().await;
+    let calls = Arc::new(AtomicUsize::new(0));
+    let counted = Arc::clone(&calls);
+    receiver.on(Method::POST, "/hook", move |_| {
+        counted.fetch_add(1, Ordering::SeqCst);
+        MockResponse::new(400, "")
+    });
+    let hub_id = ID::new_v4();
+    let config = RelayConfig {
+        hubs: vec![hub_id],
+        targets: vec![WebhookTarget::new(
+            format!("{}/hook", receiver.api_url()),
+            "secret".to_string(),
+        )],
+        max_attempts: 3,
+        retry_delay: Duration::from_millis(10),
+        ..RelayConfig::default()
+    };
+    let dead_letters = Arc::new(MemoryDeadLetterStore::default());
+    let relay = start(&server, config, Arc::clone(&dead_letters)).await;
+
+    server.push_event(&chat_message(hub_id, ID::new_v4()));
+    let letter = eventually(|| dead_letters.letters().pop()).await;
+    assert_eq!(letter.attempts, 1);
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    relay.abort();
+}
@@ -0,0 +1,127 @@
+//! Cached name lookups of the resolver against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use common::{eventually, MockResponse, MockServer};
+use hyper::Method;
+use wicrs_api::{
+    http::HttpClient,
+    protocol::{Hub, HubMember, Message, ID},
+    resolve::Resolver,
+};
+
+fn hub(id: ID, members: &[(ID, &str)]) -> Hub {
+    Hub {
+        channels: HashMap::new(),
+        members: members
+            .iter()
+            .map(|(user_id, nickname)| {
+                let member = HubMember {
+                    user_id: *user_id,
+                    joined: Utc::now(),
+                    hub: id,
+                    nickname: nickname.to_string(),
+                    groups: Vec::new(),
+                    hub_permissions: HashMap::new(),
+                    channel_permissions: HashMap::new(),
+                };
+                (*user_id, member)
+            })
+            .collect(),
+        bans: Default::default(),
+        mutes: Default::default(),
+        description: String::new(),
+        owner: ID::new_v4(),
+        groups: HashMap::new(),
+        default_group: ID::new_v4(),
+        name: "hub".to_string(),
+        id,
+        created: Utc::now(),
+    }
+}
+
+fn message(hub_id: ID, sender: ID) -> Message {
+    Message {
+        id: ID::new_v4(),
+        hub_id,
+        channel_id: ID::new_v4(),
+        sender,
+        created: Utc::now(),
+        content: "hi".to_string(),
+    }
+}
+
+fn resolver(server: &MockServer, ttl: Duration) -> Resolver {
+    let client = HttpClient::new(ID::new_v4(), server.api_url()).unwrap();
+    Resolver::new(Arc::new(client), ttl)
+}
+
+fn hub_requests(server: &MockServer, hub_id: ID) -> usize {
+    let path = format!("/api/hub/{}", hub_id);
+    server
+        .requests()
+        .iter()
+        .filter(|request| request.path == path)
+        .count()
+}
+
+#[tokio::test]
+async fn caches_members_without_a_nickname() {
+    let server = MockServer::start().await;
+    let (hub_id, user_id) = (ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::GET,
+        &format!("/hub/{}", hub_id),
+        MockResponse::success(hub(hub_id, &[(user_id, "")])),
+    );
+    let resolver = resolver(&server, Duration::from_secs(1));
+    let messages = [message(hub_id, user_id)];
+
+    let names = resolver.names_for(hub_id, &messages).await.unwrap();
+    assert_eq!(names.users.get(&user_id).map(String::as_str), Some(""));
+    let mention = format!("<@{}>", user_id);
+    assert_eq!(names.decode(&mention), mention);
+    // Past the time in which names missing from the cache are not fetched again.
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    resolver.names_for(hub_id, &messages).await.unwrap();
+    assert_eq!(hub_requests(&server, hub_id), 1);
+}
+
+#[tokio::test]
+async fn slow_hubs_do_not_hold_up_other_hubs() {
+    let server = MockServer::start().await;
+    let (slow, fast) = (ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::GET,
+        &format!("/hub/{}", slow),
+        MockResponse::success(hub(slow, &[])).delay(Duration::from_secs(2)),
+    );
+    server.respond(
+        Method::GET,
+        &format!("/hub/{}", fast),
+        MockResponse::success(hub(fast, &[])),
+    );
+    let resolver = Arc::new(resolver(&server, Duration::from_secs(60)));
+
+    let slow_lookups = (0..2)
+        .map(|_| {
+            let resolver = Arc::clone(&resolver);
+            tokio::spawn(async move { resolver.names(slow).await })
+        })
+        .collect::<Vec<_>>();
+    eventually(|| Some(()).filter(|_| hub_requests(&server, slow) == 1)).await;
+    tokio::time::timeout(Duration::from_secs(1), resolver.names(fast))
+        .await
+        .expect("lookup waited for another hub")
+        .unwrap();
+
+    for lookup in slow_lookups {
+        lookup.await.unwrap().unwrap();
+    }
+    assert_eq!(hub_requests(&server, slow), 1);
+}
@@ -0,0 +1,140 @@
+//! Reference counted subscriptions against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use common::{eventually, MockServer};
+use serde_json::Value;
+use wicrs_api::{
+    protocol::{WsClientMessage, ID},
+    subscriptions::SubscriptionManager,
+    websocket::{asyncws::WebsocketClient, HeartbeatConfig, Subscription},
+};
+
+async fn manager(server: &MockServer, reconnect: bool) -> Arc<SubscriptionManager> {
+    let heartbeat = HeartbeatConfig {
+        reconnect,
+        ..HeartbeatConfig::disabled()
+    };
+    let websocket = WebsocketClient::new_with_heartbeat(ID::new_v4(), &server.ws_url(), heartbeat)
+        .await
+        .unwrap();
+    tokio::spawn(Arc::clone(&websocket).start_loop(|_, _| None::<()>));
+    websocket.wait_for_loop().await;
+    SubscriptionManager::new(websocket)
+}
+
+fn command(message: WsClientMessage) -> Value {
+    serde_json::to_value(message).unwrap()
+}
+
+/// Gives commands that should not be sent a chance to arrive.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+#[tokio::test]
+async fn only_the_first_and_last_guards_send_commands() {
+    let server = MockServer::start().await;
+    let manager = manager(&server, false).await;
+    let hub_id = ID::new_v4();
+
+    let first = manager.subscribe_hub(hub_id).await.unwrap();
+    let second = manager.subscribe_hub(hub_id).await.unwrap();
+    assert_eq!(
+        manager.subscriptions(),
+        vec![(Subscription::Hub(hub_id), 2)]
+    );
+    assert_eq!(
+        server.commands(),
+        vec![command(WsClientMessage::SubscribeHub { hub_id })]
+    );
+
+    first.release().await.unwrap();
+    assert_eq!(
+        manager.subscriptions(),
+        vec![(Subscription::Hub(hub_id), 1)]
+    );
+    settle().await;
+    assert_eq!(server.commands().len(), 1);
+
+    second.release().await.unwrap();
+    assert!(manager.subscriptions().is_empty());
+    assert_eq!(
+        server.wait_for_commands(2).await[1],
+        command(WsClientMessage::UnsubscribeHub { hub_id })
+    );
+
+    // Subscribing again after the last guard was released sends the command again.
+    let _again = manager.subscribe_hub(hub_id).await.unwrap();
+    assert_eq!(
+        server.wait_for_commands(3).await[2],
+        command(WsClientMessage::SubscribeHub { hub_id })
+    );
+}
+
+#[tokio::test]
+async fn dropped_guards_unsubscribe() {
+    let server = MockServer::start().await;
+    let manager = manager(&server, false).await;
+    let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+
+    let guard = manager.subscribe_channel(hub_id, channel_id).await.unwrap();
+    drop(guard);
+    assert_eq!(
+        server.wait_for_commands(2).await[1],
+        command(WsClientMessage::UnsubscribeChannel { hub_id, channel_id })
+    );
+    eventually(|| Some(()).filter(|_| manager.subscriptions().is_empty())).await;
+}
+
+#[tokio::test]
+async fn guards_dropped_without_a_runtime_still_decrement() {
+    let server = MockServer::start().await;
+    let manager = manager(&server, false).await;
+    let hub_id = ID::new_v4();
+
+    let first = manager.subscribe_hub(hub_id).await.unwrap();
+    let second = manager.subscribe_hub(hub_id).await.unwrap();
+    std::thread::spawn(move || drop(first)).join().unwrap();
+    assert_eq!(
+        manager.subscriptions(),
+        vec![(Subscription::Hub(hub_id), 1)]
+    );
+    std::thread::spawn(move || drop(second)).join().unwrap();
+    assert!(manager.subscriptions().is_empty());
+    // The unsubscribe command can not be sent without a runtime.
+    settle().await;
+    assert_eq!(server.commands().len(), 1);
+}
+
+#[tokio::test]
+async fn resubscribes_after_a_reconnect() {
+    let server = MockServer::start().await;
+    let manager = manager(&server, true).await;
+    let (hub_id, channel_id) = (ID::new_v4(), ID::new_v4());
+    let _hub = manager.subscribe_hub(hub_id).await.unwrap();
+    let _channel = manager.subscribe_channel(hub_id, channel_id).await.unwrap();
+    let released = manager.subscribe_hub(ID::new_v4()).await.unwrap();
+    released.release().await.unwrap();
+    server.wait_for_commands(4).await;
+
+    server.disconnect();
+    // The client replays its own subscriptions once it is connected again.
+    server.wait_for_commands(6).await;
+    manager.resubscribe().await.unwrap();
+    let commands = server.wait_for_commands(8).await;
+    let mut resent = commands[6..].to_vec();
+    resent.sort_by_key(|command| command.to_string());
+    let mut expected = vec![
+        command(WsClientMessage::SubscribeHub { hub_id }),
+        command(WsClientMessage::SubscribeChannel { hub_id, channel_id }),
+    ];
+    expected.sort_by_key(|command| command.to_string());
+    assert_eq!(resent, expected);
+    settle().await;
+    assert_eq!(server.commands().len(), 8);
+}
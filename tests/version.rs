@@ -0,0 +1,152 @@
+//! Protocol version negotiation against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::Utc;
+use common::{MockResponse, MockServer};
+use futures_util::{future, StreamExt};
+use hyper::Method;
+use wicrs_api::{
+    http::HttpClient,
+    protocol::{ChannelPermission, Message, ID},
+    rate_limit::RateLimiter,
+    version::{Feature, ProtocolVersion},
+    Error,
+};
+
+fn client(server: &MockServer) -> HttpClient {
+    HttpClient::new(ID::new_v4(), server.api_url()).unwrap()
+}
+
+fn version_requests(server: &MockServer) -> usize {
+    server
+        .requests()
+        .iter()
+        .filter(|request| request.path == "/api/version")
+        .count()
+}
+
+#[tokio::test]
+async fn concurrent_negotiations_send_one_request() {
+    let server = MockServer::start().await;
+    server.respond(
+        Method::GET,
+        "/version",
+        MockResponse::new(200, "").delay(Duration::from_millis(100)),
+    );
+    let client = client(&server);
+    let versions = future::join_all((0..8).map(|_| client.negotiate())).await;
+    for version in versions {
+        assert_eq!(version.unwrap(), ProtocolVersion::CURRENT);
+    }
+    assert_eq!(version_requests(&server), 1);
+    client.negotiate().await.unwrap();
+    assert_eq!(version_requests(&server), 1);
+}
+
+#[tokio::test]
+async fn negotiation_is_rate_limited() {
+    let server = MockServer::start().await;
+    server.respond(Method::GET, "/version", MockResponse::new(200, ""));
+    let rate_limiter = Arc::new(RateLimiter::new(1, 5.0).unwrap());
+    let first = client(&server).with_rate_limiter(Arc::clone(&rate_limiter));
+    let second = client(&server).with_rate_limiter(rate_limiter);
+    first.negotiate().await.unwrap();
+    let version = tokio::time::timeout(Duration::from_millis(100), second.negotiate()).await;
+    assert!(version.is_err(), "the probe was not rate limited");
+    assert_eq!(second.negotiate().await.unwrap(), ProtocolVersion::CURRENT);
+    assert_eq!(version_requests(&server), 2);
+}
+
+#[tokio::test]
+async fn legacy_servers_keep_the_baseline_routes() {
+    let server = MockServer::start().await;
+    server.legacy(true);
+    let (hub, channel, member) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    server.respond(
+        Method::GET,
+        &format!("/message/{}/{}/between", hub, channel),
+        MockResponse::success(Vec::<Message>::new()),
+    );
+    let permission = format!(
+        "/member/{}/{}/channel_permission/{}/{}",
+        hub,
+        member,
+        channel,
+        ChannelPermission::Read
+    );
+    server.respond(Method::GET, &permission, MockResponse::success(Some(true)));
+    // Legacy servers set the permission for every channel, the route has no channel.
+    let set_permission = format!(
+        "/member/{}/{}/channel_permission/{}",
+        hub,
+        member,
+        ChannelPermission::Read
+    );
+    server.respond(Method::PUT, &set_permission, MockResponse::success("set"));
+    let client = client(&server);
+
+    assert_eq!(client.negotiate().await.unwrap(), ProtocolVersion::LEGACY);
+    let messages = client
+        .messages_get_between(hub, channel, Utc::now(), Utc::now(), 10, false)
+        .await
+        .unwrap();
+    assert!(messages.is_empty());
+    let streamed = client
+        .messages_stream_between(hub, channel, Utc::now(), Utc::now(), 10, false)
+        .await
+        .unwrap()
+        .collect::<Vec<Result<Message, Error>>>()
+        .await;
+    assert!(streamed.is_empty());
+    let setting = client
+        .member_get_channel_permission(hub, member, channel, ChannelPermission::Read)
+        .await
+        .unwrap();
+    assert_eq!(setting, Some(true));
+    client
+        .member_set_channel_permission(hub, member, channel, ChannelPermission::Read, None)
+        .await
+        .unwrap();
+
+    match client
+        .attachment_download(hub, channel, ID::new_v4(), 0)
+        .await
+    {
+        Err(Error::Unsupported { feature, server }) => {
+            assert_eq!(feature, Feature::Attachments);
+            assert_eq!(server, ProtocolVersion::LEGACY);
+        }
+        Err(error) => panic!("{:?}", error),
+        Ok(_) => panic!("downloaded an attachment from a legacy server"),
+    }
+}
+
+#[tokio::test]
+async fn current_servers_set_channel_permissions_per_channel() {
+    let server = MockServer::start().await;
+    let (hub, channel, member) = (ID::new_v4(), ID::new_v4(), ID::new_v4());
+    let permission = format!(
+        "/member/{}/{}/channel_permission/{}/{}",
+        hub,
+        member,
+        channel,
+        ChannelPermission::Read
+    );
+    server.respond(Method::PUT, &permission, MockResponse::success("set"));
+    let client = client(&server);
+
+    client
+        .member_set_channel_permission(hub, member, channel, ChannelPermission::Read, None)
+        .await
+        .unwrap();
+    assert!(server
+        .requests()
+        .iter()
+        .any(|request| request.method == Method::PUT
+            && request.path == format!("/api{}", permission)));
+}
@@ -0,0 +1,144 @@
+//! Reconnects of the async websocket client against the mock server.
+
+#![cfg(feature = "use-tokio")]
+
+mod common;
+
+use std::sync::Arc;
+
+use common::{eventually, MockServer, TIMEOUT};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use wicrs_api::{
+    protocol::{WsServerMessage, ID},
+    websocket::{asyncws::WebsocketClient, HeartbeatConfig},
+    Error,
+};
+
+fn chat_message(hub_id: ID, message: &str) -> WsServerMessage {
+    WsServerMessage::ChatMessage {
+        sender_id: ID::new_v4(),
+        hub_id,
+        channel_id: ID::new_v4(),
+        message_id: ID::new_v4(),
+        message: message.to_string(),
+    }
+}
+
+/// Connects and starts the message loop, returning the events it receives.
+async fn connect(
+    server: &MockServer,
+    reconnect: bool,
+) -> (Arc<WebsocketClient>, UnboundedReceiver<WsServerMessage>) {
+    let heartbeat = HeartbeatConfig {
+        reconnect,
+        ..HeartbeatConfig::disabled()
+    };
+    let websocket = WebsocketClient::new_with_heartbeat(ID::new_v4(), &server.ws_url(), heartbeat)
+        .await
+        .unwrap();
+    let (send, events) = unbounded_channel();
+    tokio::spawn(
+        Arc::clone(&websocket).start_loop(move |_, message| send.send(message).err().map(|_| ())),
+    );
+    websocket.wait_for_loop().await;
+    (websocket, events)
+}
+
+async fn next_message(events: &mut UnboundedReceiver<WsServerMessage>) -> String {
+    match tokio::time::timeout(TIMEOUT, events.recv()).await {
+        Ok(Some(WsServerMessage::ChatMessage { message, .. })) => message,
+        other => panic!("expected a chat message, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn reconnects_and_keeps_events_seen_while_resubscribing() {
+    let server = MockServer::start().await;
+    let (websocket, mut events) = connect(&server, true).await;
+    let hub_id = ID::new_v4();
+    websocket.subscribe_hub(hub_id).await.unwrap();
+
+    server.event_before_ack(&chat_message(hub_id, "during replay"));
+    server.disconnect();
+    let commands = server.wait_for_commands(2).await;
+    assert_eq!(commands[0], commands[1]);
+    assert_eq!(next_message(&mut events).await, "during replay");
+
+    server.push_event(&chat_message(hub_id, "after"));
+    assert_eq!(next_message(&mut events).await, "after");
+    websocket.subscribe_hub(ID::new_v4()).await.unwrap();
+}
+
+#[tokio::test]
+async fn pending_acks_fail_when_the_connection_is_replaced() {
+    let server = MockServer::start().await;
+    let (websocket, _events) = connect(&server, true).await;
+    server.hold_acks(true);
+    let subscribe = tokio::spawn({
+        let websocket = Arc::clone(&websocket);
+        async move { websocket.subscribe_hub(ID::new_v4()).await }
+    });
+    server.wait_for_commands(1).await;
+    server.hold_acks(false);
+    server.disconnect();
+    let result = tokio::time::timeout(TIMEOUT, subscribe)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(result, Err(Error::WsClosed)), "{:?}", result);
+    assert!(websocket.subscriptions().is_empty());
+
+    // The next command is acknowledged by the new connection.
+    websocket.subscribe_hub(ID::new_v4()).await.unwrap();
+}
+
+#[tokio::test]
+async fn pending_acks_fail_when_the_loop_stops() {
+    let server = MockServer::start().await;
+    let (websocket, _events) = connect(&server, false).await;
+    server.hold_acks(true);
+    let subscribe = tokio::spawn({
+        let websocket = Arc::clone(&websocket);
+        async move { websocket.subscribe_hub(ID::new_v4()).await }
+    });
+    server.wait_for_commands(1).await;
+    server.disconnect();
+    let result = tokio::time::timeout(TIMEOUT, subscribe)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(matches!(result, Err(Error::WsClosed)), "{:?}", result);
+    assert!(!websocket.is_loop_running());
+}
+
+#[tokio::test]
+async fn keeps_reconnecting_until_the_server_is_back() {
+    let server = MockServer::start().await;
+    let (websocket, mut events) = connect(&server, true).await;
+    let hub_id = ID::new_v4();
+    websocket.subscribe_hub(hub_id).await.unwrap();
+
+    server.reject_websocket(true);
+    server.disconnect();
+    eventually(|| Some(()).filter(|_| server.websocket_attempts() >= 4)).await;
+    assert!(websocket.is_loop_running());
+    server.reject_websocket(false);
+
+    server.wait_for_commands(2).await;
+    server.push_event(&chat_message(hub_id, "after"));
+    assert_eq!(next_message(&mut events).await, "after");
+}
+
+#[tokio::test]
+async fn close_stops_reconnecting() {
+    let server = MockServer::start().await;
+    let (websocket, _events) = connect(&server, true).await;
+    server.reject_websocket(true);
+    server.disconnect();
+    eventually(|| Some(()).filter(|_| server.websocket_attempts() >= 3)).await;
+    tokio::time::timeout(TIMEOUT, websocket.close(1000, "done"))
+        .await
+        .expect("close waited for the reconnect")
+        .ok();
+    assert!(!websocket.is_loop_running());
+}